@@ -0,0 +1,579 @@
+//! Actions performed on the objects shown in a table, e.g. acknowledging problems, scheduling
+//! downtimes or rescheduling checks.
+
+
+use std::borrow::Cow;
+use std::convert::Infallible;
+
+use askama::Template;
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone, Utc};
+use serde::Serialize;
+use hyper::{Body, Method, Request, Response};
+use tracing::{error, warn};
+
+use crate::{
+    get_optional_parameter, get_required_parameter, handle_400_invalid_filter,
+    handle_400_wrong_parameter, handle_plaintext_response, OutputFormat, respond_icinga_error,
+    return_500, RowPart, TableQuery, TableQueryError,
+};
+use crate::auth::{self, Permissions};
+use crate::columns::{CellValue, Column};
+use crate::icinga::{self, ActionResult, InstanceError, QueryError};
+use crate::objtypes;
+
+
+/// The format in which `<input type="datetime-local">` submits its value.
+const DATETIME_LOCAL_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+/// How long a downtime lasts by default, in minutes.
+const DEFAULT_DOWNTIME_MINUTES: i64 = 120;
+
+/// The values Icinga accepts for the `child_options` of a downtime.
+const CHILD_OPTIONS: &[&str] = &["DowntimeNoChildren", "DowntimeTriggeredChildren", "DowntimeNonTriggeredChildren"];
+
+
+#[derive(Template)]
+#[template(path = "action_result.html")]
+struct ActionResultTemplate {
+    pub title: &'static str,
+    pub results: Vec<ActionResult>,
+    pub back: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "downtime.html")]
+struct DowntimeTemplate {
+    pub objtype: &'static str,
+    pub filter: String,
+    pub normalized_filter: String,
+    pub columns: Vec<Column>,
+    pub rows: Vec<RowPart>,
+    pub default_start: String,
+    pub default_end: String,
+    pub default_duration_minutes: i64,
+    pub instance_errors: Vec<(String, String)>,
+    pub csrf_token: String,
+}
+
+#[derive(Template)]
+#[template(path = "recheck.html")]
+struct RecheckTemplate {
+    pub status_url: String,
+    pub results: Vec<ActionResult>,
+    pub back: Option<String>,
+}
+
+/// The progress of rescheduled checks, as polled by the re-check page.
+#[derive(Clone, Debug, Serialize)]
+struct RecheckStatus {
+    pub total: usize,
+    pub done: usize,
+    pub rows: Vec<RecheckRow>,
+}
+
+/// The check status of a single object whose check has been rescheduled.
+#[derive(Clone, Debug, Serialize)]
+struct RecheckRow {
+    pub key: String,
+    pub state: u8,
+    pub state_name: String,
+    pub last_check: String,
+    pub done: bool,
+}
+
+
+/// Parses the form data in the body of a POST request.
+///
+/// Returns an error response if the request is not a POST request, the body cannot be read or the
+/// form does not contain the user's CSRF token.
+pub(crate) async fn read_form(request: Request<Body>) -> Result<Vec<(String, String)>, Result<Response<Body>, Infallible>> {
+    if request.method() != Method::POST {
+        return Err(
+            Response::builder()
+                .status(405)
+                .header("Allow", "POST")
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(Body::from("405 Method Not Allowed"))
+                .or_else(|e| {
+                    error!("failed to construct 405 response: {}", e);
+                    return_500()
+                })
+        );
+    }
+
+    let expected_token = auth::csrf_token(&request);
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(b) => b,
+        Err(e) => {
+            error!("failed to read form body: {}", e);
+            return Err(return_500());
+        },
+    };
+    let form: Vec<(String, String)> = form_urlencoded::parse(&body)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+
+    let token_valid = form.iter()
+        .find(|(k, _v)| k == auth::CSRF_FIELD_NAME)
+        .map(|(_k, v)| auth::csrf_tokens_match(&expected_token, v))
+        .unwrap_or(false);
+    if !token_valid {
+        warn!("rejecting form submission without a valid CSRF token");
+        return Err(handle_plaintext_response(403, "403 Forbidden: invalid or missing CSRF token").await);
+    }
+    Ok(form)
+}
+
+/// Parses the value of a `datetime-local` form field as local time.
+fn parse_datetime_local(value: &str) -> Option<i64> {
+    let naive = NaiveDateTime::parse_from_str(value, DATETIME_LOCAL_FORMAT).ok()?;
+    let local = Local.from_local_datetime(&naive).earliest()?;
+    Some(local.timestamp())
+}
+
+/// Returns the page to which the result page should link back, if it is a table or report.
+fn back_link(form_pairs: &[(Cow<str>, Cow<str>)]) -> Option<String> {
+    let back = get_optional_parameter(form_pairs, "back")?;
+    if back.starts_with("table?") || back.starts_with("report/") {
+        Some(back.to_string())
+    } else {
+        None
+    }
+}
+
+/// Collects the keys of the objects selected in the form.
+fn selected_objects(form_pairs: &[(Cow<str>, Cow<str>)]) -> Vec<(String, String, String)> {
+    form_pairs
+        .iter()
+        .filter(|(k, _v)| k == "object")
+        .filter_map(|(_k, v)| icinga::split_object_key(v))
+        .collect()
+}
+
+async fn respond_action_result(title: &'static str, results: Vec<ActionResult>, back: Option<String>) -> Result<Response<Body>, Infallible> {
+    let template = ActionResultTemplate {
+        title,
+        results,
+        back,
+    };
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render action result template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
+
+async fn respond_action_error(action: &str, error: QueryError) -> Result<Response<Body>, Infallible> {
+    match error {
+        QueryError::Icinga { response } => {
+            handle_plaintext_response(
+                502,
+                format!("Icinga returned status code {}: {}", response.status_code, response.body_string()),
+            ).await
+        },
+        other => {
+            error!("failed to perform {} via Icinga: {}", action, other);
+            return_500()
+        },
+    }
+}
+
+
+/// Acknowledges the problems of the selected hosts or services.
+pub(crate) async fn handle_acknowledge(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let permissions = match auth::get_permissions(&request).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let form = match read_form(request).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let form_pairs: Vec<(Cow<str>, Cow<str>)> = form.iter()
+        .map(|(k, v)| (Cow::Borrowed(k.as_str()), Cow::Borrowed(v.as_str())))
+        .collect();
+
+    let objtype = match get_required_parameter(&form_pairs, "objtype").await {
+        Ok(ot) => ot,
+        Err(resp) => return resp,
+    };
+    let object_type = match objtypes::by_plural(objtype) {
+        Some(ot) if ot.actionable => ot,
+        _ => return handle_400_wrong_parameter("objtype", objtype).await,
+    };
+
+    let objects = selected_objects(&form_pairs);
+    if objects.is_empty() {
+        return handle_plaintext_response(400, "no objects selected").await;
+    }
+
+    // Icinga requires both an author and a comment
+    let author = match get_required_parameter(&form_pairs, "author").await {
+        Ok(a) => a,
+        Err(resp) => return resp,
+    };
+    if author.trim().is_empty() {
+        return handle_400_wrong_parameter("author", author).await;
+    }
+    let comment = match get_required_parameter(&form_pairs, "comment").await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    if comment.trim().is_empty() {
+        return handle_400_wrong_parameter("comment", comment).await;
+    }
+
+    // checkboxes are only submitted if they are checked
+    let sticky = get_optional_parameter(&form_pairs, "sticky").is_some();
+    let notify = get_optional_parameter(&form_pairs, "notify").is_some();
+
+    let mut params = serde_json::Map::new();
+    params.insert("author".to_owned(), serde_json::Value::String(author.to_string()));
+    params.insert("comment".to_owned(), serde_json::Value::String(comment.to_string()));
+    params.insert("sticky".to_owned(), serde_json::Value::Bool(sticky));
+    params.insert("notify".to_owned(), serde_json::Value::Bool(notify));
+    if let Some(expiry) = get_optional_parameter(&form_pairs, "expiry") {
+        if !expiry.is_empty() {
+            let timestamp = match parse_datetime_local(expiry) {
+                Some(t) => t,
+                None => return handle_400_wrong_parameter("expiry", expiry).await,
+            };
+            params.insert("expiry".to_owned(), serde_json::json!(timestamp));
+        }
+    }
+
+    let results = match icinga::perform_action("acknowledge-problem", object_type, &objects, permissions.restriction.as_ref(), &params, permissions.icinga_credentials.as_ref()).await {
+        Ok(r) => r,
+        Err(e) => return respond_action_error("acknowledge-problem", e).await,
+    };
+    respond_action_result("Probleme bestätigen", results, back_link(&form_pairs)).await
+}
+
+
+/// Parses a required `datetime-local` form field, returning an error response if it is invalid.
+async fn get_required_timestamp<'a>(form_pairs: &'a [(Cow<'a, str>, Cow<'a, str>)], key: &str) -> Result<i64, Result<Response<Body>, Infallible>> {
+    let value = get_required_parameter(form_pairs, key).await?;
+    match parse_datetime_local(value) {
+        Some(t) => Ok(t),
+        None => Err(handle_400_wrong_parameter(key, value).await),
+    }
+}
+
+/// Validates the object type and filter of an action that applies to all objects matching a filter.
+///
+/// An empty filter is rejected, since an action on absolutely every object is hardly ever intended.
+/// The query is restricted according to the user's permissions.
+async fn parse_filter_action_query<'a>(form_pairs: &'a [(Cow<'a, str>, Cow<'a, str>)], columns: Option<&'a str>, permissions: &Permissions) -> Result<TableQuery, Result<Response<Body>, Infallible>> {
+    let objtype = get_required_parameter(form_pairs, "objtype").await?;
+    let filter = get_required_parameter(form_pairs, "filter").await?;
+    let mut query = match TableQuery::parse(objtype, filter, columns, None) {
+        Ok(q) => q,
+        Err(TableQueryError::InvalidParameter { name, value }) => return Err(handle_400_wrong_parameter(name, value).await),
+        Err(TableQueryError::InvalidFilter { error }) => return Err(handle_400_invalid_filter(OutputFormat::Html, filter, &error).await),
+    };
+    if !query.object_type.actionable {
+        return Err(handle_400_wrong_parameter("objtype", objtype).await);
+    }
+    if query.parsed_filter.is_none() {
+        return Err(handle_400_wrong_parameter("filter", filter).await);
+    }
+    query.apply_permissions(permissions);
+    Ok(query)
+}
+
+/// Shows the objects matching a filter and a form to schedule a downtime for them (GET), or
+/// schedules the downtime (POST).
+pub(crate) async fn handle_downtime(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() == Method::POST {
+        schedule_downtime(request).await
+    } else {
+        preview_downtime(request).await
+    }
+}
+
+async fn preview_downtime(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let permissions = match auth::get_permissions(&request).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let query_string = request.uri().query().unwrap_or("");
+    let query_pairs: Vec<(Cow<str>, Cow<str>)> = form_urlencoded::parse(query_string.as_bytes())
+        .collect();
+
+    // preview the affected objects the same way a table is queried
+    let mut query = match parse_filter_action_query(&query_pairs, None, &permissions).await {
+        Ok(q) => q,
+        Err(resp) => return resp,
+    };
+    if icinga::instance_names().await.len() > 1 {
+        query.add_instance_column();
+    }
+
+    let query_result = match icinga::fetch_all_rows(&query, true).await {
+        Ok(qr) => qr,
+        Err(InstanceError { error: QueryError::Icinga { response }, .. }) => return respond_icinga_error(&query, OutputFormat::Html, &response).await,
+        Err(InstanceError { instance, error }) => {
+            error!("failed to query {} from Icinga instance {:?}: {}", query.object_type.plural, instance, error);
+            return return_500();
+        },
+    };
+
+    let now = Local::now();
+    let template = DowntimeTemplate {
+        objtype: query.object_type.plural,
+        filter: query.filter.clone(),
+        normalized_filter: query.normalized_filter(),
+        columns: query.columns.clone(),
+        rows: query_result.rows,
+        default_start: now.format(DATETIME_LOCAL_FORMAT).to_string(),
+        default_end: (now + Duration::minutes(DEFAULT_DOWNTIME_MINUTES)).format(DATETIME_LOCAL_FORMAT).to_string(),
+        default_duration_minutes: DEFAULT_DOWNTIME_MINUTES,
+        instance_errors: query_result.errors.iter()
+            .map(|ie| (ie.instance.clone(), ie.error.to_string()))
+            .collect(),
+        csrf_token: permissions.csrf_token.clone(),
+    };
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render downtime template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
+
+async fn schedule_downtime(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let permissions = match auth::get_permissions(&request).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let form = match read_form(request).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let form_pairs: Vec<(Cow<str>, Cow<str>)> = form.iter()
+        .map(|(k, v)| (Cow::Borrowed(k.as_str()), Cow::Borrowed(v.as_str())))
+        .collect();
+
+    // validate the filter just like for the preview
+    let query = match parse_filter_action_query(&form_pairs, None, &permissions).await {
+        Ok(q) => q,
+        Err(resp) => return resp,
+    };
+
+    let author = match get_required_parameter(&form_pairs, "author").await {
+        Ok(a) => a,
+        Err(resp) => return resp,
+    };
+    if author.trim().is_empty() {
+        return handle_400_wrong_parameter("author", author).await;
+    }
+    let comment = match get_required_parameter(&form_pairs, "comment").await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    if comment.trim().is_empty() {
+        return handle_400_wrong_parameter("comment", comment).await;
+    }
+
+    let start_time = match get_required_timestamp(&form_pairs, "start").await {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    let end_time = match get_required_timestamp(&form_pairs, "end").await {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    if end_time <= start_time {
+        return handle_plaintext_response(400, "the downtime must end after it starts").await;
+    }
+
+    let fixed = match get_optional_parameter(&form_pairs, "fixed").map(|f| &f[..]) {
+        None | Some("fixed") => true,
+        Some("flexible") => false,
+        Some(other) => return handle_400_wrong_parameter("fixed", other).await,
+    };
+
+    let child_options = match get_optional_parameter(&form_pairs, "child_options") {
+        Some(co) => {
+            if !CHILD_OPTIONS.iter().any(|o| *o == &co[..]) {
+                return handle_400_wrong_parameter("child_options", co).await;
+            }
+            co.to_string()
+        },
+        None => CHILD_OPTIONS[0].to_owned(),
+    };
+
+    let mut params = serde_json::Map::new();
+    params.insert("author".to_owned(), serde_json::Value::String(author.to_string()));
+    params.insert("comment".to_owned(), serde_json::Value::String(comment.to_string()));
+    params.insert("start_time".to_owned(), serde_json::json!(start_time));
+    params.insert("end_time".to_owned(), serde_json::json!(end_time));
+    params.insert("fixed".to_owned(), serde_json::Value::Bool(fixed));
+    params.insert("child_options".to_owned(), serde_json::Value::String(child_options));
+    if !fixed {
+        // a flexible downtime lasts this long from the moment the problem occurs
+        let duration_value = match get_required_parameter(&form_pairs, "duration").await {
+            Ok(d) => d,
+            Err(resp) => return resp,
+        };
+        let duration_minutes: u64 = match duration_value.parse() {
+            Ok(d) if d > 0 => d,
+            _ => return handle_400_wrong_parameter("duration", duration_value).await,
+        };
+        params.insert("duration".to_owned(), serde_json::json!(duration_minutes * 60));
+    }
+
+    let results = icinga::perform_action_on_filter_everywhere("schedule-downtime", query.object_type, &query.api_filter(), &params, query.credentials.as_ref()).await;
+    respond_action_result("Downtime planen", results, back_link(&form_pairs)).await
+}
+
+
+/// Reschedules the checks of all objects matching a filter and shows their progress (POST), or
+/// returns the progress as JSON (GET).
+pub(crate) async fn handle_recheck(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() == Method::POST {
+        reschedule_checks(request).await
+    } else {
+        recheck_status(request).await
+    }
+}
+
+async fn reschedule_checks(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let permissions = match auth::get_permissions(&request).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let form = match read_form(request).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let form_pairs: Vec<(Cow<str>, Cow<str>)> = form.iter()
+        .map(|(k, v)| (Cow::Borrowed(k.as_str()), Cow::Borrowed(v.as_str())))
+        .collect();
+
+    let query = match parse_filter_action_query(&form_pairs, None, &permissions).await {
+        Ok(q) => q,
+        Err(resp) => return resp,
+    };
+
+    // checks count as done once their last check is newer than this
+    let requested_at = Utc::now();
+
+    let mut params = serde_json::Map::new();
+    params.insert("force".to_owned(), serde_json::Value::Bool(true));
+    let results = icinga::perform_action_on_filter_everywhere("reschedule-check", query.object_type, &query.api_filter(), &params, query.credentials.as_ref()).await;
+
+    let since = format!("{}.{:03}", requested_at.timestamp(), requested_at.timestamp_subsec_millis());
+    let status_url = form_urlencoded::Serializer::new("recheck?".to_owned())
+        .append_pair("objtype", query.object_type.plural)
+        .append_pair("filter", &query.filter)
+        .append_pair("since", &since)
+        .finish();
+    let template = RecheckTemplate {
+        status_url,
+        results,
+        back: back_link(&form_pairs),
+    };
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render recheck template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
+
+async fn recheck_status(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let permissions = match auth::get_permissions(&request).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let query_string = request.uri().query().unwrap_or("");
+    let query_pairs: Vec<(Cow<str>, Cow<str>)> = form_urlencoded::parse(query_string.as_bytes())
+        .collect();
+
+    let since_value = match get_required_parameter(&query_pairs, "since").await {
+        Ok(s) => s,
+        Err(resp) => return resp,
+    };
+    let since_opt = since_value.parse::<f64>().ok()
+        .and_then(|s| Utc.timestamp_millis_opt((s * 1000.0) as i64).single());
+    let since: DateTime<Utc> = match since_opt {
+        Some(s) => s,
+        None => return handle_400_wrong_parameter("since", since_value).await,
+    };
+
+    let query = match parse_filter_action_query(&query_pairs, Some("last_check"), &permissions).await {
+        Ok(q) => q,
+        Err(resp) => return resp,
+    };
+    // the progress must be current
+    let query_result = match icinga::fetch_all_rows(&query, false).await {
+        Ok(qr) => qr,
+        Err(InstanceError { instance, error }) => {
+            error!("failed to query {} from Icinga instance {:?}: {}", query.object_type.plural, instance, error);
+            return return_500();
+        },
+    };
+
+    let rows: Vec<RecheckRow> = query_result.rows.iter()
+        .map(|row| {
+            let last_check = &row.cells[0];
+            let done = match last_check {
+                CellValue::Timestamp(t) => *t > since,
+                _ => false,
+            };
+            RecheckRow {
+                key: row.key(),
+                state: row.state.to_base_type(),
+                state_name: row.state.to_string(),
+                last_check: last_check.to_string(),
+                done,
+            }
+        })
+        .collect();
+    let status = RecheckStatus {
+        total: rows.len(),
+        done: rows.iter().filter(|r| r.done).count(),
+        rows,
+    };
+
+    let json = serde_json::to_string(&status)
+        .expect("failed to serialize recheck status");
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "no-cache")
+        .body(Body::from(json))
+        .or_else(|e| {
+            error!("failed to construct JSON response: {}", e);
+            return_500()
+        })
+}
@@ -0,0 +1,667 @@
+//! Authentication of icingcake users.
+//!
+//! Users are authenticated either by a trusted reverse proxy passing their name in a header or by
+//! HTTP basic authentication against an htpasswd file. After a successful basic authentication, a
+//! session is created whose token is stored in a cookie, so that subsequent requests (including
+//! form submissions) need not be verified against the htpasswd file again.
+//!
+//! Alternatively, the credentials passed via HTTP basic authentication can be verified by Icinga and
+//! then used to access the Icinga API on behalf of the user. They are kept in the session, i.e. in
+//! memory, until the session expires or the user logs out.
+//!
+//! Since browsers also send session cookies and cached basic authentication credentials along with
+//! form submissions triggered by other sites, forms that change something contain a CSRF token
+//! derived from the user's name, which other sites cannot read.
+
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, Duration, Utc};
+use hyper::{Body, Request, Response};
+use hyper::header::{AUTHORIZATION, COOKIE, HeaderValue, SET_COOKIE, WWW_AUTHENTICATE};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use tracing::{error, warn};
+
+use crate::{handle_plaintext_response, return_500};
+use crate::actions;
+use crate::config::{AuthConfig, CONFIG, IcingaCredentialsStrategy, RoleConfig};
+use crate::filter::{self, BinaryOperator, Expression};
+use crate::icinga::{self, IcingaCredentials};
+
+
+/// The name of the cookie containing the session token.
+pub(crate) const SESSION_COOKIE_NAME: &str = "icingcake_session";
+
+/// The name of the form field containing the CSRF token.
+pub(crate) const CSRF_FIELD_NAME: &str = "csrf_token";
+
+
+/// The active sessions, keyed by session token.
+static SESSIONS: Lazy<RwLock<HashMap<String, Session>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The secret from which CSRF tokens are derived; a new one is generated whenever icingcake starts.
+static CSRF_SECRET: Lazy<[u8; 32]> = Lazy::new(|| {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    secret
+});
+
+
+/// A user who has been authenticated.
+///
+/// Stored in the extensions of each request once the user has been authenticated.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct AuthenticatedUser {
+    pub username: String,
+    pub groups: Vec<String>,
+
+    /// The user's own credentials for the Icinga API, if they are passed through.
+    pub icinga_credentials: Option<IcingaCredentials>,
+}
+
+
+/// What a user may see and do.
+#[derive(Clone, Debug)]
+pub(crate) struct Permissions {
+    /// The filter that all objects visible to the user match, if the user is restricted.
+    pub restriction: Option<Expression>,
+
+    /// Whether the user is shown the filter actually passed to Icinga.
+    pub show_effective_filter: bool,
+
+    /// The user's own credentials for the Icinga API, if they are passed through.
+    pub icinga_credentials: Option<IcingaCredentials>,
+
+    /// The token that forms submitted by the user must contain.
+    pub csrf_token: String,
+}
+
+
+/// A session created after a successful login.
+#[derive(Clone, Debug)]
+struct Session {
+    user: AuthenticatedUser,
+    expires_at: DateTime<Utc>,
+}
+
+
+/// The outcome of authenticating a request.
+#[derive(Clone, Debug)]
+pub(crate) enum AuthOutcome {
+    /// Authentication is not configured; everyone may access icingcake.
+    Disabled,
+
+    /// The user has been authenticated. If a new session has been created, its token is returned
+    /// so that it can be sent to the browser.
+    Authenticated { user: AuthenticatedUser, new_session_token: Option<String> },
+
+    /// The user has not been authenticated.
+    Unauthenticated { realm: Option<String> },
+}
+
+
+/// Returns the value of the cookie with the given name, if the request contains it.
+fn get_cookie<'a>(request: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    request.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _value)| *key == name)
+        .map(|(_key, value)| value)
+}
+
+/// Returns the username and password passed via HTTP basic authentication, if any.
+fn get_basic_credentials(request: &Request<Body>) -> Option<(String, String)> {
+    let header_value = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = header_value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = BASE64.decode(encoded.trim()).ok()?;
+    let decoded_string = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded_string.split_once(':')?;
+    Some((username.to_owned(), password.to_owned()))
+}
+
+/// Checks a password against a bcrypt or Argon2 hash.
+fn verify_hash(username: &str, hash: &str, password: &str) -> bool {
+    if hash.starts_with("$2") {
+        match bcrypt::verify(password, hash) {
+            Ok(matches) => matches,
+            Err(e) => {
+                warn!("invalid bcrypt hash for user {:?}: {}", username, e);
+                false
+            },
+        }
+    } else if hash.starts_with("$argon2") {
+        match PasswordHash::new(hash) {
+            Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+            Err(e) => {
+                warn!("invalid Argon2 hash for user {:?}: {}", username, e);
+                false
+            },
+        }
+    } else {
+        warn!("unsupported password hash for user {:?}; only bcrypt and Argon2 are supported", username);
+        false
+    }
+}
+
+/// Checks a username and password against an htpasswd file.
+fn verify_htpasswd(path: &Path, username: &str, password: &str) -> Result<bool, io::Error> {
+    let content = std::fs::read_to_string(path)?;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (line_username, hash) = match line.split_once(':') {
+            Some(uh) => uh,
+            None => continue,
+        };
+        if line_username == username {
+            return Ok(verify_hash(username, hash, password));
+        }
+    }
+    Ok(false)
+}
+
+/// Obtains the groups of a user from an htgroup file.
+fn read_group_file(path: &Path, username: &str) -> Result<Vec<String>, io::Error> {
+    let content = std::fs::read_to_string(path)?;
+    let mut groups = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (group, members) = match line.split_once(':') {
+            Some(gm) => gm,
+            None => continue,
+        };
+        if members.split_whitespace().any(|m| m == username) {
+            groups.push(group.trim().to_owned());
+        }
+    }
+    Ok(groups)
+}
+
+/// Encodes bytes as a lowercase hexadecimal string.
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 * bytes.len());
+    for b in bytes {
+        hex.push_str(&format!("{:02x}", b));
+    }
+    hex
+}
+
+/// Generates a new random session token.
+fn generate_session_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// Derives the CSRF token of a user, or of anonymous users if no username is given, from a secret.
+fn derive_csrf_token(secret: &[u8], username: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(secret);
+    match username {
+        Some(u) => {
+            hasher.update([1]);
+            hasher.update(u.as_bytes());
+        },
+        None => hasher.update([0]),
+    }
+    to_hex(&hasher.finalize())
+}
+
+/// Returns the CSRF token that forms shown to the user who sent the request must contain.
+///
+/// The token stays the same for as long as icingcake runs, so forms remain valid across sessions
+/// and for users authenticated by a reverse proxy, who have no session at all.
+pub(crate) fn csrf_token(request: &Request<Body>) -> String {
+    let username = request.extensions().get::<AuthenticatedUser>()
+        .map(|u| u.username.as_str());
+    derive_csrf_token(&*CSRF_SECRET, username)
+}
+
+/// Checks whether a CSRF token submitted with a form matches the expected one.
+pub(crate) fn csrf_tokens_match(expected: &str, submitted: &str) -> bool {
+    // compare in constant time so that the token cannot be guessed byte by byte
+    expected.len() == submitted.len()
+        && expected.bytes().zip(submitted.bytes()).fold(0u8, |acc, (e, s)| acc | (e ^ s)) == 0
+}
+
+/// Creates a new session for the given user and returns its token.
+async fn create_session(user: AuthenticatedUser, lifetime_s: u64) -> String {
+    let token = generate_session_token();
+    let now = Utc::now();
+    // chrono durations are limited to i64::MAX milliseconds
+    let lifetime = Duration::seconds(lifetime_s.min(i64::MAX as u64 / 1000) as i64);
+    let session = Session {
+        user,
+        expires_at: now.checked_add_signed(lifetime).unwrap_or(DateTime::<Utc>::MAX_UTC),
+    };
+
+    let mut sessions_guard = SESSIONS.write().await;
+    sessions_guard.retain(|_token, s| s.expires_at > now);
+    sessions_guard.insert(token.clone(), session);
+    token
+}
+
+/// Returns the user of the session with the given token, if the session exists and has not expired.
+async fn get_session_user(token: &str) -> Option<AuthenticatedUser> {
+    let sessions_guard = SESSIONS.read().await;
+    let session = sessions_guard.get(token)?;
+    if session.expires_at <= Utc::now() {
+        return None;
+    }
+    Some(session.user.clone())
+}
+
+/// Obtains the user passed by a trusted reverse proxy, if any.
+fn get_proxy_user(auth_config: &AuthConfig, request: &Request<Body>, remote_addr: SocketAddr) -> Option<AuthenticatedUser> {
+    let header_name = auth_config.proxy_header.as_ref()?;
+    if !auth_config.trusted_proxies.contains(&remote_addr.ip()) {
+        return None;
+    }
+    let username = request.headers().get(header_name.as_str())?.to_str().ok()?.trim();
+    if username.is_empty() {
+        return None;
+    }
+    let groups = match &auth_config.proxy_groups_header {
+        Some(groups_header_name) => request.headers()
+            .get_all(groups_header_name.as_str())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|group| group.trim())
+            .filter(|group| !group.is_empty())
+            .map(|group| group.to_owned())
+            .collect(),
+        None => Vec::new(),
+    };
+    Some(AuthenticatedUser {
+        username: username.to_owned(),
+        groups,
+        icinga_credentials: None,
+    })
+}
+
+/// Obtains the groups of a user from the group file, if one is configured.
+async fn get_file_groups(group_file_path: Option<PathBuf>, username: &str) -> Option<Vec<String>> {
+    let group_file_path = match group_file_path {
+        Some(gfp) => gfp,
+        None => return Some(Vec::new()),
+    };
+    let group_username = username.to_owned();
+    match tokio::task::spawn_blocking(move || read_group_file(&group_file_path, &group_username)).await {
+        Ok(Ok(groups)) => Some(groups),
+        Ok(Err(e)) => {
+            error!("failed to read group file: {}", e);
+            None
+        },
+        Err(e) => {
+            error!("group file reading task failed: {}", e);
+            None
+        },
+    }
+}
+
+/// Obtains the user whose HTTP basic authentication credentials are accepted by Icinga, if any.
+async fn get_pass_through_user(group_file_path: Option<PathBuf>, request: &Request<Body>) -> Option<AuthenticatedUser> {
+    let (username, password) = get_basic_credentials(request)?;
+    let credentials = IcingaCredentials {
+        username,
+        password,
+    };
+    match icinga::verify_credentials(&credentials).await {
+        Ok(true) => {},
+        Ok(false) => {
+            warn!("failed login attempt for user {:?}", credentials.username);
+            return None;
+        },
+        Err(e) => {
+            error!("failed to verify credentials of user {:?} with Icinga: {}", credentials.username, e);
+            return None;
+        },
+    }
+    let groups = get_file_groups(group_file_path, &credentials.username).await?;
+    Some(AuthenticatedUser {
+        username: credentials.username.clone(),
+        groups,
+        icinga_credentials: Some(credentials),
+    })
+}
+
+/// Obtains the user who has authenticated via HTTP basic authentication, if any.
+async fn get_basic_user(htpasswd_path: PathBuf, group_file_path: Option<PathBuf>, request: &Request<Body>) -> Option<AuthenticatedUser> {
+    let (username, password) = get_basic_credentials(request)?;
+
+    // reading the file and verifying the hash blocks
+    let verify_username = username.clone();
+    let verified = tokio::task::spawn_blocking(move || verify_htpasswd(&htpasswd_path, &verify_username, &password))
+        .await;
+    match verified {
+        Ok(Ok(true)) => {
+            let groups = get_file_groups(group_file_path, &username).await?;
+            Some(AuthenticatedUser { username, groups, icinga_credentials: None })
+        },
+        Ok(Ok(false)) => {
+            warn!("failed login attempt for user {:?}", username);
+            None
+        },
+        Ok(Err(e)) => {
+            error!("failed to read htpasswd file: {}", e);
+            None
+        },
+        Err(e) => {
+            error!("htpasswd verification task failed: {}", e);
+            None
+        },
+    }
+}
+
+/// Authenticates the user who sent the request.
+///
+/// A user passed by a trusted reverse proxy takes precedence over an existing session, which takes
+/// precedence over HTTP basic authentication.
+pub(crate) async fn authenticate(request: &Request<Body>, remote_addr: SocketAddr) -> AuthOutcome {
+    let auth_config = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        match &config_guard.auth {
+            Some(ac) => ac.clone(),
+            None => return AuthOutcome::Disabled,
+        }
+    };
+
+    if let Some(user) = get_proxy_user(&auth_config, request, remote_addr) {
+        return AuthOutcome::Authenticated { user, new_session_token: None };
+    }
+
+    if let Some(token) = get_cookie(request, SESSION_COOKIE_NAME) {
+        if let Some(user) = get_session_user(token).await {
+            return AuthOutcome::Authenticated { user, new_session_token: None };
+        }
+    }
+
+    if auth_config.icinga_credentials == IcingaCredentialsStrategy::PassThrough {
+        if let Some(user) = get_pass_through_user(auth_config.group_file_path.clone(), request).await {
+            let token = create_session(user.clone(), auth_config.session_lifetime_s).await;
+            return AuthOutcome::Authenticated { user, new_session_token: Some(token) };
+        }
+        return AuthOutcome::Unauthenticated { realm: Some(auth_config.realm) };
+    }
+
+    if let Some(htpasswd_path) = &auth_config.htpasswd_path {
+        if let Some(user) = get_basic_user(htpasswd_path.clone(), auth_config.group_file_path.clone(), request).await {
+            let token = create_session(user.clone(), auth_config.session_lifetime_s).await;
+            return AuthOutcome::Authenticated { user, new_session_token: Some(token) };
+        }
+        return AuthOutcome::Unauthenticated { realm: Some(auth_config.realm) };
+    }
+
+    AuthOutcome::Unauthenticated { realm: None }
+}
+
+/// Combines the filters of the roles of a user into the restriction of the user.
+///
+/// The user may see the objects visible to any of the roles, so the filters are joined using `||`;
+/// if any of the roles has no filter, the user is not restricted at all. Returns a description of
+/// the problem if a filter is invalid.
+fn combine_role_filters(user_roles: &[&RoleConfig]) -> Result<Option<Expression>, String> {
+    let mut restriction: Option<Expression> = None;
+    for role in user_roles {
+        let role_filter = match &role.filter {
+            Some(f) => f,
+            None => {
+                // this role is unrestricted
+                return Ok(None);
+            },
+        };
+        let role_expression = filter::parse(role_filter)
+            .map_err(|e| format!("role {:?} has invalid filter {:?}: {}", role.name, role_filter, e))?;
+        restriction = Some(match restriction {
+            Some(r) => Expression::Binary {
+                operator: BinaryOperator::LogicalOr,
+                left: Box::new(r),
+                right: Box::new(role_expression),
+            },
+            None => role_expression,
+        });
+    }
+    Ok(restriction)
+}
+
+/// Determines what the user who sent the request may see and do.
+///
+/// Returns an error response if the user may not see anything at all.
+pub(crate) async fn get_permissions(request: &Request<Body>) -> Result<Permissions, Result<Response<Body>, Infallible>> {
+    let roles = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        match &config_guard.auth {
+            Some(ac) => ac.roles.clone(),
+            None => Vec::new(),
+        }
+    };
+    let user_opt = request.extensions().get::<AuthenticatedUser>();
+    let icinga_credentials = user_opt.and_then(|u| u.icinga_credentials.clone());
+    let csrf_token = csrf_token(request);
+    if roles.is_empty() {
+        return Ok(Permissions {
+            restriction: None,
+            show_effective_filter: false,
+            icinga_credentials,
+            csrf_token,
+        });
+    }
+
+    let user = match user_opt {
+        Some(u) => u,
+        None => return Err(handle_plaintext_response(403, "403 Forbidden").await),
+    };
+    let user_roles: Vec<&RoleConfig> = roles.iter()
+        .filter(|r| r.has_member(&user.username, &user.groups))
+        .collect();
+    if user_roles.is_empty() {
+        warn!("user {:?} is not a member of any role", user.username);
+        return Err(handle_plaintext_response(403, "403 Forbidden").await);
+    }
+
+    let restriction = match combine_role_filters(&user_roles) {
+        Ok(r) => r,
+        Err(description) => {
+            error!("{}", description);
+            return Err(return_500());
+        },
+    };
+
+    Ok(Permissions {
+        restriction,
+        show_effective_filter: user_roles.iter().any(|r| r.admin),
+        icinga_credentials,
+        csrf_token,
+    })
+}
+
+/// Returns the value of the `Set-Cookie` header that stores the given session token.
+///
+/// `SameSite=Lax` keeps the browser from sending the cookie along with form submissions from other
+/// sites. Sibling subdomains count as the same site, though, and browsers resend basic
+/// authentication credentials regardless, which is why forms are protected by CSRF tokens as well.
+/// If `secure` is set, the browser only sends the cookie via HTTPS.
+pub(crate) fn session_cookie(token: &str, secure: bool) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax{}",
+        SESSION_COOKIE_NAME, token, if secure { "; Secure" } else { "" },
+    )
+}
+
+/// Adds the cookie storing the given session token to a response.
+pub(crate) async fn add_session_cookie(response: &mut Response<Body>, token: &str) {
+    let secure = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        config_guard.http_server.tls.is_some()
+    };
+    match HeaderValue::from_str(&session_cookie(token, secure)) {
+        Ok(value) => {
+            response.headers_mut().append(SET_COOKIE, value);
+        },
+        Err(e) => {
+            error!("failed to construct session cookie header: {}", e);
+        },
+    }
+}
+
+/// Responds to a request whose user has not been authenticated.
+///
+/// If a realm is given, the browser is asked for credentials.
+pub(crate) async fn respond_401(realm: Option<&str>) -> Result<Response<Body>, Infallible> {
+    let mut builder = Response::builder()
+        .status(401)
+        .header("Content-Type", "text/plain; charset=utf-8");
+    if let Some(realm) = realm {
+        let escaped_realm = realm.replace('\\', "\\\\").replace('"', "\\\"");
+        builder = builder.header(WWW_AUTHENTICATE, format!("Basic realm=\"{}\", charset=\"UTF-8\"", escaped_realm));
+    }
+    builder
+        .body(Body::from("401 Unauthorized"))
+        .or_else(|e| {
+            error!("failed to construct HTTP 401 response: {}", e);
+            return_500()
+        })
+}
+
+/// Ends the current session.
+///
+/// Only accepts POST requests carrying the user's CSRF token, so that other sites cannot log the
+/// user out. Browsers keep sending HTTP basic authentication credentials until they are closed, so
+/// a user authenticated that way is logged in again on the next request.
+pub(crate) async fn handle_logout(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let session_token = get_cookie(&request, SESSION_COOKIE_NAME)
+        .map(|t| t.to_owned());
+    if let Err(response) = actions::read_form(request).await {
+        return response;
+    }
+
+    if let Some(token) = session_token {
+        let mut sessions_guard = SESSIONS.write().await;
+        sessions_guard.remove(&token);
+    }
+
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .header(SET_COOKIE, format!("{}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0", SESSION_COOKIE_NAME))
+        .body(Body::from("Abgemeldet."))
+        .or_else(|e| {
+            error!("failed to construct logout response: {}", e);
+            return_500()
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(name: &str, filter: Option<&str>, users: &[&str], groups: &[&str]) -> RoleConfig {
+        RoleConfig {
+            name: name.to_owned(),
+            filter: filter.map(|f| f.to_owned()),
+            users: users.iter().map(|u| (*u).to_owned()).collect(),
+            groups: groups.iter().map(|g| (*g).to_owned()).collect(),
+            admin: false,
+        }
+    }
+
+    fn restriction_of(roles: &[RoleConfig], username: &str, groups: &[&str]) -> Option<Expression> {
+        let groups: Vec<String> = groups.iter().map(|g| (*g).to_owned()).collect();
+        let user_roles: Vec<&RoleConfig> = roles.iter()
+            .filter(|r| r.has_member(username, &groups))
+            .collect();
+        combine_role_filters(&user_roles).unwrap()
+    }
+
+    #[test]
+    fn test_single_role() {
+        let roles = vec![
+            role("db", Some("\"team-db\" in host.groups"), &["alice"], &[]),
+            role("web", Some("\"team-web\" in host.groups"), &["bob"], &[]),
+        ];
+        assert_eq!(
+            restriction_of(&roles, "alice", &[]),
+            Some(filter::parse("\"team-db\" in host.groups").unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_roles_are_ored() {
+        let roles = vec![
+            role("db", Some("\"team-db\" in host.groups"), &["alice"], &[]),
+            role("web", Some("\"team-web\" in host.groups || host.vars.public"), &[], &["webmasters"]),
+            role("mail", Some("\"team-mail\" in host.groups"), &["bob"], &[]),
+        ];
+        let restriction = restriction_of(&roles, "alice", &["webmasters"]).unwrap();
+        assert_eq!(
+            restriction,
+            filter::parse("\"team-db\" in host.groups || (\"team-web\" in host.groups || host.vars.public)").unwrap(),
+        );
+        assert_eq!(
+            restriction.to_string(),
+            "\"team-db\" in host.groups || (\"team-web\" in host.groups || host.vars.public)",
+        );
+    }
+
+    #[test]
+    fn test_unrestricted_role_wins() {
+        let roles = vec![
+            role("db", Some("\"team-db\" in host.groups"), &["alice"], &[]),
+            role("ops", None, &[], &["operators"]),
+        ];
+        assert_eq!(restriction_of(&roles, "alice", &["operators"]), None);
+        assert!(restriction_of(&roles, "alice", &[]).is_some());
+    }
+
+    #[test]
+    fn test_invalid_role_filter() {
+        let roles = [role("broken", Some("host.name = \"x\""), &["alice"], &[])];
+        let user_roles: Vec<&RoleConfig> = roles.iter().collect();
+        assert!(combine_role_filters(&user_roles).unwrap_err().contains("\"broken\""));
+    }
+
+    #[test]
+    fn test_csrf_tokens() {
+        let secret = [42u8; 32];
+        let alice = derive_csrf_token(&secret, Some("alice"));
+        assert_eq!(alice, derive_csrf_token(&secret, Some("alice")));
+        assert_ne!(alice, derive_csrf_token(&secret, Some("bob")));
+        assert_ne!(alice, derive_csrf_token(&[43u8; 32], Some("alice")));
+        assert_ne!(derive_csrf_token(&secret, Some("")), derive_csrf_token(&secret, None));
+
+        assert!(csrf_tokens_match(&alice, &alice));
+        assert!(!csrf_tokens_match(&alice, &alice[1..]));
+        assert!(!csrf_tokens_match(&alice, ""));
+        assert!(!csrf_tokens_match(&alice, &derive_csrf_token(&secret, Some("bob"))));
+    }
+}
@@ -0,0 +1,796 @@
+//! Availability reports calculated from state changes and downtimes recorded locally.
+//!
+//! The Icinga API does not offer the history of states, so the hard state changes of all hosts and
+//! services as well as the downtimes are recorded from the event stream of each Icinga instance
+//! into the history database. Whenever the event stream is (re)opened, the current states and
+//! downtimes are recorded as well, to catch up on what was missed in the meantime.
+
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::time::Duration;
+
+use askama::Template;
+use chrono::{Datelike, DateTime, Local, NaiveDate, TimeZone, Utc};
+use hyper::{Body, Request, Response};
+use rusqlite::{OptionalExtension, params};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+use crate::{
+    auth, get_optional_parameter, handle_400_invalid_filter, handle_400_wrong_parameter,
+    handle_plaintext_response, OutputFormat, return_500, TableQuery, TableQueryError,
+};
+use crate::columns::{self, CellValue};
+use crate::config::CONFIG;
+use crate::history::{self, HistoryError};
+use crate::icinga::{self, InstanceError};
+
+
+/// The types of Icinga events that are recorded.
+const EVENT_TYPES: &[&str] = &["StateChange", "DowntimeTriggered", "DowntimeRemoved"];
+
+/// How long an event stream is kept open before it is reopened (and the current states recorded).
+const EVENT_STREAM_MAX_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before reconnecting after the event stream failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// How often to check whether recording has been enabled or Icinga instances have been added.
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The object types for which availability can be calculated.
+const AVAILABILITY_OBJECT_TYPES: &[&str] = &["hosts", "services"];
+
+
+/// How long a host or service was available within a time range.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub(crate) struct Availability {
+    /// Seconds during which the state was known and no downtime was in effect.
+    pub monitored_s: i64,
+
+    /// Seconds of `monitored_s` during which the object was down or critical.
+    pub outage_s: i64,
+
+    /// Seconds during which a downtime was in effect; these are excluded.
+    pub downtime_s: i64,
+
+    /// Seconds during which the state is unknown because nothing had been recorded yet.
+    pub unrecorded_s: i64,
+
+    /// How many outages began or were ongoing during the monitored time.
+    pub incidents: u64,
+}
+impl Availability {
+    /// The percentage of the monitored time during which the object was available, if any time was
+    /// monitored.
+    pub fn percentage(&self) -> Option<f64> {
+        if self.monitored_s > 0 {
+            Some(100.0 * (self.monitored_s - self.outage_s) as f64 / self.monitored_s as f64)
+        } else {
+            None
+        }
+    }
+}
+
+
+/// Calculates the availability of an object within the time range from `start` (inclusive) to
+/// `end` (exclusive).
+///
+/// `initial_state` is the state at `start`, if known; `changes` are the subsequent state changes
+/// as `(time, state)`, sorted by time; `downtimes` are the times `(start, end)` during which
+/// downtimes were in effect.
+pub(crate) fn calculate(
+    initial_state: Option<u8>,
+    changes: &[(i64, u8)],
+    downtimes: &[(i64, i64)],
+    start: i64,
+    end: i64,
+    is_outage: impl Fn(u8) -> bool,
+) -> Availability {
+    let mut availability = Availability::default();
+    if start >= end {
+        return availability;
+    }
+
+    // split the time range into segments during which neither the state nor the downtimes change
+    let mut boundaries: BTreeSet<i64> = BTreeSet::new();
+    boundaries.insert(start);
+    boundaries.insert(end);
+    boundaries.extend(changes.iter().map(|(time, _state)| *time).filter(|t| *t > start && *t < end));
+    for (downtime_start, downtime_end) in downtimes {
+        for boundary in [*downtime_start, *downtime_end] {
+            if boundary > start && boundary < end {
+                boundaries.insert(boundary);
+            }
+        }
+    }
+    let boundaries: Vec<i64> = boundaries.into_iter().collect();
+
+    let mut state = initial_state;
+    let mut change_index = 0;
+    let mut in_incident = false;
+    for segment in boundaries.windows(2) {
+        let (segment_start, segment_end) = (segment[0], segment[1]);
+        let length = segment_end - segment_start;
+        while change_index < changes.len() && changes[change_index].0 <= segment_start {
+            state = Some(changes[change_index].1);
+            change_index += 1;
+        }
+        let in_downtime = downtimes.iter()
+            .any(|(ds, de)| *ds <= segment_start && segment_start < *de);
+
+        let current_state = match state {
+            Some(s) => s,
+            None => {
+                availability.unrecorded_s += length;
+                continue;
+            },
+        };
+        let outage = is_outage(current_state);
+        if in_downtime {
+            // an outage continuing after the downtime is still the same incident
+            availability.downtime_s += length;
+            if !outage {
+                in_incident = false;
+            }
+            continue;
+        }
+
+        availability.monitored_s += length;
+        if outage {
+            availability.outage_s += length;
+            if !in_incident {
+                availability.incidents += 1;
+                in_incident = true;
+            }
+        } else {
+            in_incident = false;
+        }
+    }
+    availability
+}
+
+
+/// Whether a state of an object of the given type counts as an outage: hosts that are down and
+/// services that are critical.
+fn is_outage(objtype: &str, state: u8) -> bool {
+    if objtype == "hosts" {
+        state != 0
+    } else {
+        state == 2
+    }
+}
+
+
+/// Whether state changes and downtimes are to be recorded.
+async fn recording_enabled() -> bool {
+    let config_guard = CONFIG
+        .get().expect("CONFIG not set?!")
+        .read().await;
+    config_guard.history
+        .as_ref()
+        .map(|h| h.record_availability)
+        .unwrap_or(false)
+}
+
+
+/// Records a state of an object, unless it is already the most recently recorded one.
+///
+/// The state is never recorded before the most recently recorded one, keeping the records in order
+/// even if the current states obtained when reconnecting are older than the events.
+fn record_state(
+    connection: &rusqlite::Connection,
+    instance: &str,
+    host: &str,
+    service: &str,
+    at: i64,
+    state: u8,
+) -> Result<(), HistoryError> {
+    let latest: Option<(i64, u8)> = connection
+        .query_row(
+            "SELECT changed_at, state FROM state_changes WHERE instance = ?1 AND host = ?2 AND service = ?3 ORDER BY changed_at DESC, rowid DESC LIMIT 1",
+            params![instance, host, service],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()?;
+    let at = match latest {
+        Some((_latest_at, latest_state)) if latest_state == state => return Ok(()),
+        Some((latest_at, _latest_state)) => at.max(latest_at),
+        None => at,
+    };
+    connection.execute(
+        "INSERT INTO state_changes (instance, host, service, changed_at, state) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![instance, host, service, at, state],
+    )?;
+    Ok(())
+}
+
+/// Records that a downtime has taken effect, unless this is already known.
+fn record_downtime_start(
+    connection: &rusqlite::Connection,
+    instance: &str,
+    host: &str,
+    service: &str,
+    name: &str,
+    at: i64,
+) -> Result<(), HistoryError> {
+    connection.execute(
+        "INSERT OR IGNORE INTO downtimes (instance, host, service, name, started_at, ended_at) VALUES (?1, ?2, ?3, ?4, ?5, NULL)",
+        params![instance, host, service, name, at],
+    )?;
+    Ok(())
+}
+
+/// Records that a downtime has ended.
+fn record_downtime_end(connection: &rusqlite::Connection, instance: &str, name: &str, at: i64) -> Result<(), HistoryError> {
+    connection.execute(
+        "UPDATE downtimes SET ended_at = ?3 WHERE instance = ?1 AND name = ?2 AND ended_at IS NULL",
+        params![instance, name, at],
+    )?;
+    Ok(())
+}
+
+
+/// Extracts the host, service and full name of the downtime contained in an Icinga event.
+fn event_downtime(event: &serde_json::Value) -> Option<(String, String, String)> {
+    let downtime = &event["downtime"];
+    let host = downtime["host_name"].as_str()?;
+    let service = downtime["service_name"].as_str().unwrap_or("");
+    let name = match downtime["__name"].as_str() {
+        Some(n) => n.to_owned(),
+        None => {
+            let short_name = downtime["name"].as_str()?;
+            if service.is_empty() {
+                format!("{}!{}", host, short_name)
+            } else {
+                format!("{}!{}!{}", host, service, short_name)
+            }
+        },
+    };
+    Some((host.to_owned(), service.to_owned(), name))
+}
+
+/// Records a state change or downtime event from an Icinga instance.
+async fn record_event(instance: &str, event: serde_json::Value) -> Result<(), HistoryError> {
+    let path = history::database_path().await?;
+    let instance = instance.to_owned();
+    let at = event["timestamp"].as_f64()
+        .map(|t| t as i64)
+        .unwrap_or_else(|| Utc::now().timestamp());
+
+    match event["type"].as_str() {
+        Some("StateChange") => {
+            // only hard states count
+            if event["state_type"].as_f64() != Some(1.0) {
+                return Ok(());
+            }
+            let host = match event["host"].as_str() {
+                Some(h) => h.to_owned(),
+                None => return Ok(()),
+            };
+            let service = event["service"].as_str().unwrap_or("").to_owned();
+            let state = match columns::state_from_json(&event["state"]) {
+                Some(s) => s.to_base_type(),
+                None => return Ok(()),
+            };
+            history::with_database(path, move |connection|
+                record_state(connection, &instance, &host, &service, at, state)
+            ).await
+        },
+        Some("DowntimeTriggered") => {
+            let (host, service, name) = match event_downtime(&event) {
+                Some(d) => d,
+                None => return Ok(()),
+            };
+            history::with_database(path, move |connection|
+                record_downtime_start(connection, &instance, &host, &service, &name, at)
+            ).await
+        },
+        Some("DowntimeRemoved") => {
+            let (_host, _service, name) = match event_downtime(&event) {
+                Some(d) => d,
+                None => return Ok(()),
+            };
+            history::with_database(path, move |connection|
+                record_downtime_end(connection, &instance, &name, at)
+            ).await
+        },
+        _ => Ok(()),
+    }
+}
+
+
+/// Records the current hard states of all hosts and services as well as the downtimes currently in
+/// effect on an Icinga instance, and the end of the recorded downtimes that are no longer in
+/// effect.
+async fn record_current_state(instance: &str) -> Result<(), String> {
+    let now = Utc::now().timestamp();
+
+    let mut states = Vec::new();
+    for objtype in AVAILABILITY_OBJECT_TYPES {
+        let query = TableQuery::parse(objtype, "", Some("last_hard_state,last_hard_state_change"), None)
+            .expect("failed to assemble state query");
+        let result = icinga::fetch_rows(instance, &query, false).await
+            .map_err(|e| format!("failed to query {}: {}", objtype, e))?;
+        for row in result.rows {
+            let state = match row.cells.first().and_then(|c| c.as_state()) {
+                Some(s) => s.to_base_type(),
+                None => continue,
+            };
+            let at = match row.cells.get(1) {
+                Some(CellValue::Timestamp(t)) => t.timestamp(),
+                _ => now,
+            };
+            states.push((row.host, row.service, at, state));
+        }
+    }
+
+    let query = TableQuery::parse("downtimes", "", Some("is_in_effect,trigger_time"), None)
+        .expect("failed to assemble downtime query");
+    let result = icinga::fetch_rows(instance, &query, false).await
+        .map_err(|e| format!("failed to query downtimes: {}", e))?;
+    let mut downtimes = Vec::new();
+    for row in result.rows {
+        if row.cells.first() != Some(&CellValue::Boolean(true)) {
+            continue;
+        }
+        let at = match row.cells.get(1) {
+            Some(CellValue::Timestamp(t)) => t.timestamp(),
+            _ => now,
+        };
+        downtimes.push((row.host, row.service, row.name, at));
+    }
+
+    let path = history::database_path().await
+        .map_err(|e| e.to_string())?;
+    let instance = instance.to_owned();
+    history::with_database(path, move |connection| {
+        let transaction = connection.transaction()?;
+        for (host, service, at, state) in &states {
+            record_state(&transaction, &instance, host, service, *at, *state)?;
+        }
+
+        let open_downtimes: Vec<String> = transaction
+            .prepare("SELECT name FROM downtimes WHERE instance = ?1 AND ended_at IS NULL")?
+            .query_map(params![instance], |r| r.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        let current_names: BTreeSet<&str> = downtimes.iter()
+            .map(|(_host, _service, name, _at)| name.as_str())
+            .collect();
+        for name in &open_downtimes {
+            if !current_names.contains(name.as_str()) {
+                record_downtime_end(&transaction, &instance, name, now)?;
+            }
+        }
+        for (host, service, name, at) in &downtimes {
+            record_downtime_start(&transaction, &instance, host, service, name, *at)?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }).await
+        .map_err(|e| e.to_string())
+}
+
+
+/// Records the state changes and downtimes of an Icinga instance for as long as recording is
+/// enabled and the instance is configured.
+async fn record_instance(instance: String) {
+    let queue_name = format!("icingcake-availability-{}-{}", std::process::id(), instance);
+
+    loop {
+        if !recording_enabled().await || !icinga::instance_names().await.contains(&instance) {
+            debug!("no longer recording availability of Icinga instance {:?}", instance);
+            return;
+        }
+
+        // catch up on whatever happened while disconnected
+        if let Err(e) = record_current_state(&instance).await {
+            error!("failed to record current state of Icinga instance {:?}: {}", instance, e);
+        }
+
+        let mut response = match icinga::open_event_stream(&instance, &queue_name, EVENT_TYPES, EVENT_STREAM_MAX_DURATION, None).await {
+            Ok(r) if r.status().is_success() => r,
+            Ok(r) => {
+                error!("Icinga instance {:?} refused to open event stream: status code {}", instance, r.status());
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            },
+            Err(e) => {
+                error!("failed to open event stream of Icinga instance {:?}: {}", instance, e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            },
+        };
+
+        let mut buffer: Vec<u8> = Vec::new();
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    buffer.extend_from_slice(&chunk);
+                    while let Some(newline_index) = buffer.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = buffer.drain(..=newline_index).collect();
+                        let event: serde_json::Value = match serde_json::from_slice(&line) {
+                            Ok(e) => e,
+                            Err(e) => {
+                                warn!("failed to parse Icinga event: {}", e);
+                                continue;
+                            },
+                        };
+                        if let Err(e) = record_event(&instance, event).await {
+                            error!("failed to record event of Icinga instance {:?}: {}", instance, e);
+                        }
+                    }
+                },
+                Ok(None) => {
+                    debug!("Icinga event stream ended");
+                    break;
+                },
+                Err(e) => {
+                    error!("failed to read from Icinga event stream: {}", e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    break;
+                },
+            }
+        }
+    }
+}
+
+/// Records the state changes and downtimes of all Icinga instances while recording is enabled.
+///
+/// The configuration is checked regularly, so enabling recording or adding instances by reloading
+/// the configuration takes effect without a restart.
+pub(crate) async fn record_availability() {
+    let mut recorders: HashMap<String, JoinHandle<()>> = HashMap::new();
+    loop {
+        if recording_enabled().await {
+            for instance in icinga::instance_names().await {
+                let running = recorders.get(&instance)
+                    .map(|r| !r.is_finished())
+                    .unwrap_or(false);
+                if !running {
+                    recorders.insert(instance.clone(), tokio::spawn(record_instance(instance)));
+                }
+            }
+        }
+        tokio::time::sleep(SUPERVISE_INTERVAL).await;
+    }
+}
+
+
+/// Calculates the availability of the given objects (`(instance, host, service)`) within the time
+/// range from `start` to `end`.
+async fn load_availabilities(
+    objtype: &'static str,
+    objects: Vec<(String, String, String)>,
+    start: i64,
+    end: i64,
+) -> Result<Vec<Availability>, HistoryError> {
+    let path = history::database_path().await?;
+    let now = Utc::now().timestamp();
+    let end = end.min(now);
+
+    history::with_database(path, move |connection| {
+        let mut initial_statement = connection.prepare(
+            "SELECT state FROM state_changes WHERE instance = ?1 AND host = ?2 AND service = ?3 AND changed_at <= ?4 ORDER BY changed_at DESC, rowid DESC LIMIT 1",
+        )?;
+        let mut changes_statement = connection.prepare(
+            "SELECT changed_at, state FROM state_changes WHERE instance = ?1 AND host = ?2 AND service = ?3 AND changed_at > ?4 AND changed_at < ?5 ORDER BY changed_at, rowid",
+        )?;
+        // downtimes of a host also count for its services
+        let mut downtimes_statement = connection.prepare(
+            "SELECT started_at, ended_at FROM downtimes WHERE instance = ?1 AND host = ?2 AND (service = ?3 OR service = '') AND started_at < ?5 AND (ended_at IS NULL OR ended_at > ?4)",
+        )?;
+
+        let mut availabilities = Vec::with_capacity(objects.len());
+        for (instance, host, service) in &objects {
+            let initial_state: Option<u8> = initial_statement
+                .query_row(params![instance, host, service, start], |r| r.get(0))
+                .optional()?;
+            let changes = changes_statement
+                .query_map(params![instance, host, service, start, end], |r| Ok((r.get(0)?, r.get(1)?)))?
+                .collect::<Result<Vec<(i64, u8)>, _>>()?;
+            let downtimes = downtimes_statement
+                .query_map(params![instance, host, service, start, end], |r| Ok((
+                    r.get::<_, i64>(0)?,
+                    r.get::<_, Option<i64>>(1)?.unwrap_or(now),
+                )))?
+                .collect::<Result<Vec<(i64, i64)>, _>>()?;
+            availabilities.push(calculate(
+                initial_state,
+                &changes,
+                &downtimes,
+                start,
+                end,
+                |state| is_outage(objtype, state),
+            ));
+        }
+        Ok(availabilities)
+    }).await
+}
+
+
+#[derive(Template)]
+#[template(path = "availability.html")]
+struct AvailabilityTemplate {
+    pub objtype: &'static str,
+    pub filter: String,
+    pub from: String,
+    pub to: String,
+    pub show_instance: bool,
+    pub rows: Option<Vec<AvailabilityRow>>,
+    pub instance_errors: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug)]
+struct AvailabilityRow {
+    pub instance: String,
+    pub host: String,
+    pub service: String,
+    pub percentage: String,
+    pub outage: String,
+    pub incidents: u64,
+    pub downtime: String,
+    pub unrecorded: String,
+}
+
+
+/// Formats a number of seconds as hours, minutes and seconds.
+fn format_duration(seconds: i64) -> String {
+    format!("{}:{:02}:{:02}", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+}
+
+/// The start of the given day in local time.
+fn local_midnight(date: NaiveDate) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&date.and_hms_opt(0, 0, 0)?).earliest()
+}
+
+/// The first and last day of the previous month.
+fn previous_month() -> (NaiveDate, NaiveDate) {
+    let today = Local::now().date_naive();
+    let first_of_this_month = today.with_day(1).expect("first day of month is invalid?!");
+    let last_of_previous_month = first_of_this_month.pred_opt().expect("no day before first day of month?!");
+    let first_of_previous_month = last_of_previous_month.with_day(1).expect("first day of month is invalid?!");
+    (first_of_previous_month, last_of_previous_month)
+}
+
+
+/// Shows the availability of the hosts or services matching a filter within a range of days.
+///
+/// Without a `filter` parameter, only the form is shown. The range defaults to the previous month.
+pub(crate) async fn handle_availability(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let permissions = match auth::get_permissions(&request).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    if !recording_enabled().await {
+        return handle_plaintext_response(404, "404 Not Found").await;
+    }
+
+    let query_pairs: Vec<(Cow<str>, Cow<str>)> = if let Some(query) = request.uri().query() {
+        form_urlencoded::parse(query.as_bytes())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let objtype = get_optional_parameter(&query_pairs, "objtype")
+        .map(|o| o.as_ref())
+        .unwrap_or("services");
+    let objtype = match AVAILABILITY_OBJECT_TYPES.iter().find(|ot| **ot == objtype) {
+        Some(ot) => *ot,
+        None => return handle_400_wrong_parameter("objtype", objtype).await,
+    };
+
+    let (default_from, default_to) = previous_month();
+    let from = match get_optional_parameter(&query_pairs, "from") {
+        Some(f) => match NaiveDate::parse_from_str(f, "%Y-%m-%d") {
+            Ok(d) => d,
+            Err(_) => return handle_400_wrong_parameter("from", f).await,
+        },
+        None => default_from,
+    };
+    let to = match get_optional_parameter(&query_pairs, "to") {
+        Some(t) => match NaiveDate::parse_from_str(t, "%Y-%m-%d") {
+            Ok(d) if d >= from => d,
+            _ => return handle_400_wrong_parameter("to", t).await,
+        },
+        None => default_to,
+    };
+
+    let mut template = AvailabilityTemplate {
+        objtype,
+        filter: String::new(),
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+        show_instance: icinga::instance_names().await.len() > 1,
+        rows: None,
+        instance_errors: Vec::new(),
+    };
+
+    if let Some(filter) = get_optional_parameter(&query_pairs, "filter") {
+        template.filter = filter.to_string();
+
+        // the range includes the last day
+        let start = local_midnight(from);
+        let end = to.succ_opt().and_then(local_midnight);
+        let (start, end) = match (start, end) {
+            (Some(s), Some(e)) => (s.timestamp(), e.timestamp()),
+            _ => return handle_400_wrong_parameter("to", &template.to).await,
+        };
+
+        let mut query = match TableQuery::parse(objtype, filter, None, None) {
+            Ok(q) => q,
+            Err(TableQueryError::InvalidParameter { name, value }) => return handle_400_wrong_parameter(name, value).await,
+            Err(TableQueryError::InvalidFilter { error }) => return handle_400_invalid_filter(OutputFormat::Html, filter, &error).await,
+        };
+        query.apply_permissions(&permissions);
+
+        let query_result = match icinga::fetch_all_rows(&query, true).await {
+            Ok(qr) => qr,
+            Err(InstanceError { instance, error }) => {
+                error!("failed to query {} from Icinga instance {:?}: {}", objtype, instance, error);
+                return return_500();
+            },
+        };
+        template.instance_errors = query_result.errors.iter()
+            .map(|ie| (ie.instance.clone(), ie.error.to_string()))
+            .collect();
+
+        let mut objects: Vec<(String, String, String)> = query_result.rows.into_iter()
+            .map(|r| (r.instance, r.host, r.service))
+            .collect();
+        objects.sort_unstable_by(|a, b|
+            a.1.cmp(&b.1)
+                .then_with(|| a.2.cmp(&b.2))
+                .then_with(|| a.0.cmp(&b.0))
+        );
+
+        let availabilities = match load_availabilities(objtype, objects.clone(), start, end).await {
+            Ok(a) => a,
+            Err(e) => {
+                error!("failed to calculate availability: {}", e);
+                return return_500();
+            },
+        };
+        template.rows = Some(
+            objects.into_iter()
+                .zip(availabilities)
+                .map(|((instance, host, service), availability)| AvailabilityRow {
+                    instance,
+                    host,
+                    service,
+                    percentage: match availability.percentage() {
+                        Some(p) => format!("{:.3}\u{a0}%", p),
+                        None => "\u{2013}".to_owned(),
+                    },
+                    outage: format_duration(availability.outage_s),
+                    incidents: availability.incidents,
+                    downtime: format_duration(availability.downtime_s),
+                    unrecorded: format_duration(availability.unrecorded_s),
+                })
+                .collect()
+        );
+    }
+
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render availability template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service_outage(state: u8) -> bool {
+        is_outage("services", state)
+    }
+
+    #[test]
+    fn test_outage_spanning_downtime() {
+        // critical from 100 to 400 with a downtime from 200 to 300
+        let availability = calculate(Some(0), &[(100, 2), (400, 0)], &[(200, 300)], 0, 500, service_outage);
+        assert_eq!(availability.monitored_s, 400);
+        assert_eq!(availability.outage_s, 200);
+        assert_eq!(availability.downtime_s, 100);
+        assert_eq!(availability.unrecorded_s, 0);
+        assert_eq!(availability.incidents, 1);
+        assert_eq!(availability.percentage(), Some(50.0));
+    }
+
+    #[test]
+    fn test_separate_incidents() {
+        let availability = calculate(Some(2), &[(100, 0), (200, 2), (300, 0)], &[], 0, 400, service_outage);
+        assert_eq!(availability.monitored_s, 400);
+        assert_eq!(availability.outage_s, 200);
+        assert_eq!(availability.incidents, 2);
+
+        // an outage that recovers during a downtime ends the incident
+        let availability = calculate(Some(2), &[(150, 0), (250, 2)], &[(100, 200)], 0, 400, service_outage);
+        assert_eq!(availability.outage_s, 250);
+        assert_eq!(availability.downtime_s, 100);
+        assert_eq!(availability.incidents, 2);
+    }
+
+    #[test]
+    fn test_range_before_first_record() {
+        let availability = calculate(None, &[(100, 0), (150, 2)], &[], 0, 200, service_outage);
+        assert_eq!(availability.unrecorded_s, 100);
+        assert_eq!(availability.monitored_s, 100);
+        assert_eq!(availability.outage_s, 50);
+        assert_eq!(availability.incidents, 1);
+        assert_eq!(availability.percentage(), Some(50.0));
+
+        // changes before the range do not count, but determine the state at its start
+        let availability = calculate(None, &[(-50, 2), (100, 0)], &[], 0, 200, service_outage);
+        assert_eq!(availability.unrecorded_s, 0);
+        assert_eq!(availability.outage_s, 100);
+
+        let availability = calculate(None, &[], &[], 0, 200, service_outage);
+        assert_eq!(availability.unrecorded_s, 200);
+        assert_eq!(availability.monitored_s, 0);
+        assert_eq!(availability.percentage(), None);
+    }
+
+    #[test]
+    fn test_open_ended_downtime() {
+        // downtimes that have not ended yet last until now, which may be after the end of the range
+        let availability = calculate(Some(2), &[], &[(50, 1000)], 0, 200, service_outage);
+        assert_eq!(availability.monitored_s, 50);
+        assert_eq!(availability.outage_s, 50);
+        assert_eq!(availability.downtime_s, 150);
+        assert_eq!(availability.incidents, 1);
+
+        let availability = calculate(Some(2), &[], &[(-100, 1000)], 0, 200, service_outage);
+        assert_eq!(availability.monitored_s, 0);
+        assert_eq!(availability.downtime_s, 200);
+        assert_eq!(availability.incidents, 0);
+        assert_eq!(availability.percentage(), None);
+    }
+
+    #[test]
+    fn test_empty_range() {
+        for (start, end) in [(100, 100), (200, 100)] {
+            let availability = calculate(Some(2), &[(150, 0)], &[(120, 180)], start, end, service_outage);
+            assert_eq!(availability.monitored_s, 0);
+            assert_eq!(availability.outage_s, 0);
+            assert_eq!(availability.downtime_s, 0);
+            assert_eq!(availability.unrecorded_s, 0);
+            assert_eq!(availability.incidents, 0);
+            assert_eq!(availability.percentage(), None);
+        }
+    }
+
+    #[test]
+    fn test_is_outage() {
+        // hosts: up, down
+        assert!(!is_outage("hosts", 0));
+        assert!(is_outage("hosts", 1));
+
+        // services: ok, warning, critical, unknown
+        assert!(!is_outage("services", 0));
+        assert!(!is_outage("services", 1));
+        assert!(is_outage("services", 2));
+        assert!(!is_outage("services", 3));
+
+        let host = calculate(Some(1), &[], &[], 0, 100, |s| is_outage("hosts", s));
+        assert_eq!(host.outage_s, 100);
+        let service = calculate(Some(1), &[], &[], 0, 100, service_outage);
+        assert_eq!(service.outage_s, 0);
+    }
+}
@@ -0,0 +1,95 @@
+//! Caching of object queries.
+//!
+//! Identical queries made while a query is in flight wait for its response instead of calling the
+//! Icinga API themselves. Responses are then kept for the configured time to live.
+
+
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use tokio::sync::OnceCell;
+use tokio::time::Instant;
+
+use crate::config::CONFIG;
+use crate::icinga::{IcingaCredentials, QueryError};
+use crate::metrics;
+
+
+/// A cached response, or a query in flight whose response is yet to be stored.
+type InFlight = Arc<OnceCell<Arc<ObjectsResponse>>>;
+
+/// The cached responses and the queries in flight.
+static CACHE: Lazy<Mutex<HashMap<CacheKey, InFlight>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+
+/// Identifies an object query.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct CacheKey {
+    pub instance: String,
+    pub objtype: &'static str,
+    pub filter: String,
+    pub attributes: BTreeSet<String>,
+    pub joins: BTreeSet<String>,
+
+    /// Users whose credentials are passed through may see different objects.
+    pub credentials: Option<IcingaCredentials>,
+}
+
+
+/// The objects returned by the Icinga API in response to a query.
+#[derive(Clone, Debug)]
+pub(crate) struct ObjectsResponse {
+    pub status_code: u16,
+    pub received_at: DateTime<Utc>,
+    pub received_instant: Instant,
+    pub results: Vec<serde_json::Value>,
+}
+
+
+/// Returns the cached response to the query with the given key if it is still fresh; otherwise,
+/// calls `fetch` to obtain it, unless an identical query is already in flight, whose response is
+/// then awaited instead.
+///
+/// Errors are not cached.
+pub(crate) async fn get_or_fetch<F, Fut>(key: CacheKey, fetch: F) -> Result<Arc<ObjectsResponse>, QueryError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<ObjectsResponse, QueryError>>,
+{
+    let ttl = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        Duration::from_secs(config_guard.cache.ttl_s)
+    };
+
+    let cell = {
+        let mut cache_guard = CACHE.lock().expect("query cache poisoned");
+
+        // forget stale responses as well as failed queries nobody is waiting for anymore
+        cache_guard.retain(|_key, cell| match cell.get() {
+            Some(response) => response.received_instant.elapsed() < ttl,
+            None => Arc::strong_count(cell) > 1,
+        });
+
+        Arc::clone(cache_guard.entry(key).or_default())
+    };
+
+    if let Some(response) = cell.get() {
+        metrics::CACHE_REQUESTS.with_label_values(&["hit"]).inc();
+        return Ok(Arc::clone(response));
+    }
+
+    let mut fetched = false;
+    let response = cell.get_or_try_init(|| async {
+        fetched = true;
+        fetch().await.map(Arc::new)
+    }).await?;
+    let result = if fetched { "miss" } else { "coalesced" };
+    metrics::CACHE_REQUESTS.with_label_values(&[result]).inc();
+    Ok(Arc::clone(response))
+}
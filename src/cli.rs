@@ -0,0 +1,166 @@
+//! Commands run from the command line instead of serving the web interface.
+
+
+use std::io::Write;
+use std::process::ExitCode;
+
+use askama::Template;
+use tracing::error;
+
+use crate::{ConfigOpts, initialize, ReportFormat, ReportOpts, TableQuery, TableQueryError, TableTemplate};
+use crate::config::CONFIG;
+use crate::export;
+use crate::icinga::{self, InstanceError};
+use crate::tls;
+
+
+/// Assembles the query and title of the report requested on the command line.
+async fn report_query(opts: &ReportOpts) -> Result<(TableQuery, Option<String>), String> {
+    if let Some(report_name) = &opts.report {
+        let report = {
+            let config_guard = CONFIG
+                .get().expect("CONFIG not set?!")
+                .read().await;
+            config_guard.reports
+                .iter()
+                .find(|r| &r.name == report_name)
+                .cloned()
+        };
+        let report = report
+            .ok_or_else(|| format!("no report named {:?} is configured", report_name))?;
+        let query = TableQuery::from_report(&report)?;
+        let title = report.title.clone().unwrap_or_else(|| report.name.clone());
+        return Ok((query, Some(title)));
+    }
+
+    let objtype = opts.objtype.as_deref()
+        .expect("neither report nor objtype given?!");
+    let filter = opts.filter.as_deref().unwrap_or("");
+    let query = TableQuery::parse(objtype, filter, opts.columns.as_deref(), opts.sort.as_deref())
+        .map_err(|e| match e {
+            TableQueryError::InvalidParameter { name, value }
+                => format!("invalid {} {:?}", name, value),
+            TableQueryError::InvalidFilter { error }
+                => format!("invalid filter {:?}: {}", filter, error),
+        })?;
+    Ok((query, None))
+}
+
+
+/// Generates a report and writes it to stdout or a file.
+///
+/// Fails if the report cannot be generated or if any Icinga instance cannot be queried; in the
+/// latter case, the rows returned by the other instances are still output.
+pub(crate) async fn run_report(opts: ReportOpts) -> ExitCode {
+    if let Err(e) = initialize(opts.config_path.clone()) {
+        error!("{}", e);
+        return ExitCode::FAILURE;
+    }
+
+    let (mut query, title) = match report_query(&opts).await {
+        Ok(qt) => qt,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::FAILURE;
+        },
+    };
+    if icinga::instance_names().await.len() > 1 {
+        query.add_instance_column();
+    }
+
+    let query_result = match icinga::fetch_all_rows(&query, false).await {
+        Ok(qr) => qr,
+        Err(InstanceError { instance, error }) => {
+            error!("failed to query {} from Icinga instance {:?}: {}", query.object_type.plural, instance, error);
+            return ExitCode::FAILURE;
+        },
+    };
+    for instance_error in &query_result.errors {
+        error!("failed to query {} from Icinga instance {:?}: {}", query.object_type.plural, instance_error.instance, instance_error.error);
+    }
+
+    let document = match opts.format {
+        ReportFormat::Html => {
+            let template = TableTemplate::standalone(
+                &query,
+                title,
+                query_result.rows,
+                &query_result.errors,
+                query_result.oldest_fetched_at,
+            );
+            match template.render() {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("failed to render table template: {}", e);
+                    return ExitCode::FAILURE;
+                },
+            }
+        },
+        ReportFormat::Csv => export::rows_to_csv(&query.columns, &query_result.rows),
+        ReportFormat::Json => {
+            let json_table = export::JsonTable::new(
+                query.object_type.plural,
+                &query.filter,
+                query_result.fetched_at,
+                query_result.status_code,
+                &query.columns,
+                &query_result.rows,
+                &query_result.errors,
+            );
+            serde_json::to_string_pretty(&json_table)
+                .expect("failed to serialize JSON table")
+        },
+        ReportFormat::Text => export::rows_to_text(&query.columns, &query_result.rows),
+    };
+
+    let write_res = match &opts.output {
+        Some(path) => std::fs::write(path, document.as_bytes()),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(document.as_bytes())
+                .and_then(|()| stdout.flush())
+        },
+    };
+    if let Err(e) = write_res {
+        error!("failed to write report: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    if query_result.errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+
+/// Checks whether the configuration can be loaded and everything it references (TLS certificates,
+/// report definitions) is valid.
+///
+/// Report definitions are already checked while loading the configuration.
+pub(crate) async fn check_config(opts: ConfigOpts) -> ExitCode {
+    if let Err(e) = initialize(opts.config_path) {
+        error!("{}", e);
+        return ExitCode::FAILURE;
+    }
+
+    let config = CONFIG
+        .get().expect("CONFIG not set?!")
+        .read().await
+        .clone();
+    let mut problems = Vec::new();
+    if let Some(https_config) = &config.http_server.tls {
+        if let Err(e) = tls::server_config(https_config) {
+            problems.push(format!("invalid HTTPS configuration: {}", e));
+        }
+    }
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        return ExitCode::FAILURE;
+    }
+    println!("configuration OK");
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,534 @@
+//! Columns of a table report, referencing arbitrary attributes of Icinga objects.
+
+
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fmt;
+
+use chrono::{DateTime, Local, TimeZone, Utc};
+
+use crate::NagiosState;
+use crate::objtypes::ObjectType;
+
+
+/// Attributes whose numeric values are states.
+const STATE_ATTRIBUTES: &[&str] = &[
+    "state",
+    "last_state",
+    "last_hard_state",
+    "last_check_result.state",
+];
+
+/// Attributes whose numeric values are UNIX timestamps.
+const TIMESTAMP_ATTRIBUTES: &[&str] = &[
+    "last_check",
+    "next_check",
+    "next_update",
+    "last_state_change",
+    "last_hard_state_change",
+    "last_state_ok",
+    "last_state_warning",
+    "last_state_critical",
+    "last_state_unknown",
+    "last_state_up",
+    "last_state_down",
+    "last_state_unreachable",
+    "acknowledgement_expiry",
+    "last_check_result.schedule_start",
+    "last_check_result.schedule_end",
+    "last_check_result.execution_start",
+    "last_check_result.execution_end",
+    "entry_time",
+    "expire_time",
+    "start_time",
+    "end_time",
+    "trigger_time",
+    "last_notification",
+    "next_notification",
+];
+
+/// The path of the column showing the Icinga instance an object belongs to.
+pub(crate) const INSTANCE_PATH: &str = "instance";
+
+/// Human-readable titles of commonly used columns, keyed by the column's path.
+const KNOWN_TITLES: &[(&str, &str)] = &[
+    ("instance", "Instance"),
+    ("host.name", "Host"),
+    ("service.name", "Service"),
+    ("state", "State"),
+    ("last_check_result.output", "Output"),
+    ("last_check", "Last Check"),
+    ("state_type", "State Type"),
+    ("check_command", "Check Command"),
+    ("address", "Address"),
+    ("groups", "Groups"),
+    ("notes_url", "Notes URL"),
+    ("name", "Name"),
+    ("display_name", "Display Name"),
+    ("host_name", "Host"),
+    ("service_name", "Service"),
+    ("author", "Author"),
+    ("comment", "Comment"),
+    ("text", "Comment"),
+    ("start_time", "Start"),
+    ("end_time", "End"),
+    ("entry_time", "Entry Time"),
+    ("expire_time", "Expiry"),
+    ("fixed", "Fixed"),
+    ("trigger_time", "Triggered"),
+    ("users", "Users"),
+    ("user_groups", "User Groups"),
+    ("last_notification", "Last Notification"),
+    ("notification_number", "Number"),
+    ("email", "Email"),
+    ("enable_notifications", "Notifications"),
+    ("command", "Command"),
+    ("timeout", "Timeout"),
+    ("parent", "Parent"),
+    ("endpoints", "Endpoints"),
+    ("global", "Global"),
+];
+
+
+/// How the values of a column are interpreted.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum ColumnKind {
+    /// The type of value is derived from the JSON value returned by Icinga.
+    Auto,
+
+    /// The value is a numeric state.
+    State,
+
+    /// The value is a UNIX timestamp.
+    Timestamp,
+}
+
+
+/// Where the value of a column is taken from in an Icinga API result.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum AttributeSource {
+    /// An attribute of the queried object itself (`$.attrs`).
+    Object,
+
+    /// An attribute of a joined object (`$.joins.<type>`).
+    Join(String),
+
+    /// The name of the Icinga instance that returned the object.
+    Instance,
+}
+
+
+/// A column of a table report.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Column {
+    /// The dotted path as specified by the user, e.g. `vars.os` or `host.address`.
+    pub path: String,
+
+    /// The object from which the value is taken.
+    pub source: AttributeSource,
+
+    /// The path to the value within the attributes of the source object.
+    pub attribute_path: Vec<String>,
+}
+impl Column {
+    /// Parses a dotted attribute path in the context of the given object type.
+    ///
+    /// A leading segment equal to the object type's own singular name is stripped (`service.name`
+    /// is the same as `name` when querying services); a leading segment equal to a joinable object
+    /// type references that object's attributes (`host.address` when querying services). The path
+    /// `instance` references the name of the Icinga instance that returned the object.
+    pub fn parse(object_type: &ObjectType, path: &str) -> Option<Self> {
+        if path == INSTANCE_PATH {
+            return Some(Self::instance());
+        }
+
+        let segments: Vec<&str> = path.split('.').collect();
+        let segments_valid = segments.iter().all(|seg|
+            !seg.is_empty()
+            && seg.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        );
+        if !segments_valid {
+            return None;
+        }
+
+        let (source, attribute_segments) = if segments.len() > 1 && segments[0] == object_type.singular {
+            (AttributeSource::Object, &segments[1..])
+        } else if segments.len() > 1 && object_type.joins.iter().any(|j| *j == segments[0]) {
+            (AttributeSource::Join(segments[0].to_owned()), &segments[1..])
+        } else {
+            (AttributeSource::Object, &segments[..])
+        };
+
+        Some(Self {
+            path: path.to_owned(),
+            source,
+            attribute_path: attribute_segments.iter().map(|s| (*s).to_owned()).collect(),
+        })
+    }
+
+    /// Parses a comma-separated list of dotted attribute paths.
+    ///
+    /// Returns the offending path if one of them is invalid.
+    pub fn parse_list<'a>(object_type: &ObjectType, paths: &'a str) -> Result<Vec<Self>, &'a str> {
+        let mut columns = Vec::new();
+        for path in paths.split(',') {
+            let trimmed = path.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            match Self::parse(object_type, trimmed) {
+                Some(c) => columns.push(c),
+                None => return Err(trimmed),
+            }
+        }
+        Ok(columns)
+    }
+
+    /// Returns the column showing the Icinga instance an object belongs to.
+    pub fn instance() -> Self {
+        Self {
+            path: INSTANCE_PATH.to_owned(),
+            source: AttributeSource::Instance,
+            attribute_path: Vec::new(),
+        }
+    }
+
+    /// Returns the default columns of the given object type.
+    pub fn defaults(object_type: &ObjectType) -> Vec<Self> {
+        object_type.default_columns
+            .iter()
+            .map(|path| Self::parse(object_type, path).expect("invalid default column"))
+            .collect()
+    }
+
+    /// The dotted path of the attribute within the source object.
+    fn attribute_path_string(&self) -> String {
+        self.attribute_path.join(".")
+    }
+
+    /// The name of the top-level attribute that must be requested from Icinga.
+    pub fn top_attribute(&self) -> &str {
+        &self.attribute_path[0]
+    }
+
+    /// How the values of this column are interpreted.
+    pub fn kind(&self) -> ColumnKind {
+        let attribute_path = self.attribute_path_string();
+        if STATE_ATTRIBUTES.iter().any(|a| *a == attribute_path) {
+            ColumnKind::State
+        } else if TIMESTAMP_ATTRIBUTES.iter().any(|a| *a == attribute_path) {
+            ColumnKind::Timestamp
+        } else {
+            ColumnKind::Auto
+        }
+    }
+
+    /// The title of the column as shown in the table header.
+    pub fn title(&self) -> String {
+        for (path, title) in KNOWN_TITLES {
+            if *path == self.path {
+                return (*title).to_owned();
+            }
+        }
+        self.path.clone()
+    }
+
+    /// The CSS class of the header and cells of this column.
+    pub fn css_class(&self) -> String {
+        match self.path.as_str() {
+            "host.name" | "host_name" => "host".to_owned(),
+            "service.name" | "service_name" => "service".to_owned(),
+            "last_check_result.output" => "output".to_owned(),
+            other => other.replace('.', "-"),
+        }
+    }
+
+    /// The CSS classes of a cell of this column containing the given value.
+    pub fn cell_css_class(&self, cell: &CellValue) -> String {
+        let mut classes = self.css_class();
+        if let CellValue::State(state) = cell {
+            if classes != "state" {
+                classes.push_str(" state");
+            }
+            classes.push_str(&format!(" state-{}", state.to_base_type()));
+        }
+        classes
+    }
+
+    /// Extracts the value of this column from an Icinga API result object returned by the given
+    /// instance.
+    pub fn extract(&self, instance: &str, result: &serde_json::Value) -> CellValue {
+        let mut value = match &self.source {
+            AttributeSource::Object => &result["attrs"],
+            AttributeSource::Join(join_type) => &result["joins"][join_type.as_str()],
+            AttributeSource::Instance => return CellValue::Text(instance.to_owned()),
+        };
+        for segment in &self.attribute_path {
+            value = &value[segment.as_str()];
+        }
+        CellValue::from_json(value, self.kind())
+    }
+}
+
+
+/// Collects the attributes and joins that must be requested from Icinga to fill the given columns.
+///
+/// Returns a tuple of object attributes and joins.
+pub(crate) fn required_attributes(columns: &[Column]) -> (BTreeSet<String>, BTreeSet<String>) {
+    let mut attrs = BTreeSet::new();
+    let mut joins = BTreeSet::new();
+    for column in columns {
+        match &column.source {
+            AttributeSource::Object => {
+                attrs.insert(column.top_attribute().to_owned());
+            },
+            AttributeSource::Join(join_type) => {
+                joins.insert(format!("{}.{}", join_type, column.top_attribute()));
+            },
+            AttributeSource::Instance => {},
+        }
+    }
+    (attrs, joins)
+}
+
+
+/// Attempts to interpret a JSON value as a numeric state.
+pub(crate) fn state_from_json(value: &serde_json::Value) -> Option<NagiosState> {
+    // Icinga likes to return states as floating-point numbers
+    let number = value.as_u64()
+        .or_else(|| value.as_f64().filter(|f| *f >= 0.0 && f.fract() == 0.0).map(|f| f as u64))?;
+    let state_num: u8 = number.try_into().ok()?;
+    Some(NagiosState::from(state_num))
+}
+
+
+/// The value of a single table cell.
+#[derive(Clone, Debug)]
+pub(crate) enum CellValue {
+    Empty,
+    Boolean(bool),
+    Number(f64),
+    Timestamp(DateTime<Utc>),
+    State(NagiosState),
+    Text(String),
+    List(Vec<String>),
+}
+impl CellValue {
+    /// Converts a JSON value returned by Icinga into a cell value.
+    pub fn from_json(value: &serde_json::Value, kind: ColumnKind) -> Self {
+        if value.is_null() {
+            return Self::Empty;
+        }
+
+        match kind {
+            ColumnKind::State => {
+                if let Some(state) = state_from_json(value) {
+                    return Self::State(state);
+                }
+            },
+            ColumnKind::Timestamp => {
+                if let Some(secs) = value.as_f64() {
+                    if secs == 0.0 {
+                        // Icinga's way of saying "never"
+                        return Self::Empty;
+                    }
+                    let whole_secs = secs.trunc() as i64;
+                    let nanos = (secs.fract() * 1_000_000_000.0) as u32;
+                    if let Some(timestamp) = Utc.timestamp_opt(whole_secs, nanos).single() {
+                        return Self::Timestamp(timestamp);
+                    }
+                }
+            },
+            ColumnKind::Auto => {},
+        }
+
+        match value {
+            serde_json::Value::Null => Self::Empty,
+            serde_json::Value::Bool(b) => Self::Boolean(*b),
+            serde_json::Value::Number(n) => Self::Number(n.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::String(s) => Self::Text(s.clone()),
+            serde_json::Value::Array(items) => Self::List(
+                items.iter()
+                    .map(|item| match item {
+                        serde_json::Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    })
+                    .collect()
+            ),
+            serde_json::Value::Object(_) => Self::Text(value.to_string()),
+        }
+    }
+
+    /// Converts this cell value into a JSON value for machine consumption.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Empty => serde_json::Value::Null,
+            Self::Boolean(b) => serde_json::Value::Bool(*b),
+            Self::Number(n) => serde_json::Number::from_f64(*n)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            Self::Timestamp(t) => serde_json::Value::String(t.to_rfc3339()),
+            Self::State(s) => serde_json::Value::from(s.to_base_type()),
+            Self::Text(t) => serde_json::Value::String(t.clone()),
+            Self::List(l) => serde_json::Value::Array(
+                l.iter().map(|s| serde_json::Value::String(s.clone())).collect()
+            ),
+        }
+    }
+
+    /// Converts this cell value into JSON from which it can be restored exactly using
+    /// [`CellValue::from_stored_json`].
+    pub fn to_stored_json(&self) -> serde_json::Value {
+        match self {
+            Self::Empty => serde_json::Value::Null,
+            Self::Boolean(b) => serde_json::json!({"boolean": b}),
+            Self::Number(n) => serde_json::json!({"number": n}),
+            Self::Timestamp(t) => serde_json::json!({"timestamp": t.to_rfc3339()}),
+            Self::State(s) => serde_json::json!({"state": s.to_base_type()}),
+            Self::Text(t) => serde_json::json!({"text": t}),
+            Self::List(l) => serde_json::json!({"list": l}),
+        }
+    }
+
+    /// Restores a cell value from JSON produced by [`CellValue::to_stored_json`].
+    pub fn from_stored_json(value: &serde_json::Value) -> Option<Self> {
+        if value.is_null() {
+            return Some(Self::Empty);
+        }
+        let object = value.as_object()?;
+        if object.len() != 1 {
+            return None;
+        }
+        let (tag, inner) = object.iter().next()?;
+        match tag.as_str() {
+            "boolean" => inner.as_bool().map(Self::Boolean),
+            // non-finite numbers are stored as null
+            "number" => Some(Self::Number(inner.as_f64().unwrap_or(f64::NAN))),
+            "timestamp" => inner.as_str()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| Self::Timestamp(t.with_timezone(&Utc))),
+            "state" => inner.as_u64()
+                .and_then(|s| u8::try_from(s).ok())
+                .map(|s| Self::State(NagiosState::from(s))),
+            "text" => inner.as_str().map(|t| Self::Text(t.to_owned())),
+            "list" => inner.as_array()
+                .and_then(|items| items.iter()
+                    .map(|item| item.as_str().map(|i| i.to_owned()))
+                    .collect::<Option<Vec<String>>>()
+                )
+                .map(Self::List),
+            _ => None,
+        }
+    }
+
+    /// Returns the state contained in this cell value, if any.
+    pub fn as_state(&self) -> Option<NagiosState> {
+        match self {
+            Self::State(s) => Some(*s),
+            _ => None,
+        }
+    }
+
+    /// Ranks the variants for the purpose of ordering values of different types.
+    fn variant_rank(&self) -> u8 {
+        match self {
+            Self::Empty => 0,
+            Self::Boolean(_) => 1,
+            Self::Number(_) => 2,
+            Self::Timestamp(_) => 3,
+            Self::State(_) => 4,
+            Self::Text(_) => 5,
+            Self::List(_) => 6,
+        }
+    }
+}
+impl fmt::Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => Ok(()),
+            Self::Boolean(b) => write!(f, "{}", b),
+            Self::Number(n) => write!(f, "{}", n),
+            Self::Timestamp(t) => write!(f, "{}", t.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")),
+            Self::State(s) => write!(f, "{}", s),
+            Self::Text(t) => write!(f, "{}", t),
+            Self::List(l) => write!(f, "{}", l.join(", ")),
+        }
+    }
+}
+impl PartialEq for CellValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for CellValue {}
+impl PartialOrd for CellValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for CellValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Empty, Self::Empty) => Ordering::Equal,
+            (Self::Boolean(a), Self::Boolean(b)) => a.cmp(b),
+            (Self::Number(a), Self::Number(b)) => a.total_cmp(b),
+            (Self::Timestamp(a), Self::Timestamp(b)) => a.cmp(b),
+            (Self::State(a), Self::State(b)) => a.cmp(b),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::List(a), Self::List(b)) => a.cmp(b),
+            (a, b) => a.variant_rank().cmp(&b.variant_rank()),
+        }
+    }
+}
+
+
+/// A criterion by which table rows are sorted.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) struct SortKey {
+    /// The index of the column by whose values to sort.
+    pub column_index: usize,
+
+    /// Whether to sort in descending order.
+    pub descending: bool,
+}
+impl SortKey {
+    /// Parses a comma-separated list of column paths, each optionally prefixed by `-` to sort in
+    /// descending order. Each path must refer to one of the given columns.
+    ///
+    /// Returns the offending path if one of them is invalid.
+    pub fn parse_list<'a>(object_type: &ObjectType, columns: &[Column], spec: &'a str) -> Result<Vec<Self>, &'a str> {
+        let mut keys = Vec::new();
+        for piece in spec.split(',') {
+            let trimmed = piece.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            let (path, descending) = match trimmed.strip_prefix('-') {
+                Some(rest) => (rest, true),
+                None => (trimmed, false),
+            };
+            let sort_column = Column::parse(object_type, path)
+                .ok_or(trimmed)?;
+            let column_index = columns.iter()
+                .position(|c| c.source == sort_column.source && c.attribute_path == sort_column.attribute_path)
+                .ok_or(trimmed)?;
+            keys.push(Self {
+                column_index,
+                descending,
+            });
+        }
+        Ok(keys)
+    }
+
+    /// Compares two lists of cell values according to a list of sort keys.
+    pub fn compare(keys: &[Self], left: &[CellValue], right: &[CellValue]) -> Ordering {
+        for key in keys {
+            let ordering = left[key.column_index].cmp(&right[key.column_index]);
+            let ordering = if key.descending { ordering.reverse() } else { ordering };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
@@ -1,12 +1,15 @@
 //! Structures representing configuration data.
 
+use std::collections::BTreeSet;
 use std::fmt;
 use std::fs::File;
 use std::io::{self, Read};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
-use std::str::Utf8Error;
+use std::str::{FromStr, Utf8Error};
 
+use cron::Schedule;
+use lettre::message::Mailbox;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
@@ -20,12 +23,136 @@ pub(crate) static CONFIG_PATH: OnceCell<PathBuf> = OnceCell::new();
 /// The current configuration of icingcake, behind an [RwLock].
 pub(crate) static CONFIG: OnceCell<RwLock<Config>> = OnceCell::new();
 
+/// The name of the Icinga instance configured via `icinga_api`.
+pub(crate) const DEFAULT_INSTANCE_NAME: &str = "icinga";
+
 
 /// icingcake's full configuration.
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub(crate) struct Config {
     pub http_server: HttpServerConfig,
-    pub icinga_api: IcingaApiConfig,
+
+    /// The Icinga instance to query, if there is only one.
+    #[serde(default)]
+    pub icinga_api: Option<IcingaApiConfig>,
+
+    /// The Icinga instances to query, if there are multiple.
+    #[serde(default)]
+    pub icinga_instances: Vec<IcingaInstanceConfig>,
+
+    #[serde(default)]
+    pub reports: Vec<ReportConfig>,
+
+    /// How users of icingcake are authenticated. If not set, everyone can access icingcake.
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+
+    #[serde(default)]
+    pub cache: CacheConfig,
+
+    /// How to send e-mail, required for `scheduled_reports`.
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+
+    /// Reports sent via e-mail on a schedule.
+    #[serde(default)]
+    pub scheduled_reports: Vec<ScheduledReportConfig>,
+
+    /// Where snapshots of reports are recorded, required for reports with `history` enabled.
+    #[serde(default)]
+    pub history: Option<HistoryConfig>,
+}
+
+impl Config {
+    /// The Icinga instances to query, in the order in which they are configured.
+    ///
+    /// An instance configured via `icinga_api` is named [DEFAULT_INSTANCE_NAME].
+    pub fn instances(&self) -> Vec<IcingaInstanceConfig> {
+        let mut instances = Vec::with_capacity(self.icinga_instances.len() + 1);
+        if let Some(icinga_api) = &self.icinga_api {
+            instances.push(IcingaInstanceConfig {
+                name: DEFAULT_INSTANCE_NAME.to_owned(),
+                api: icinga_api.clone(),
+            });
+        }
+        instances.extend(self.icinga_instances.iter().cloned());
+        instances
+    }
+
+    /// The Icinga instance with the given name, if it is configured.
+    pub fn instance(&self, name: &str) -> Option<IcingaInstanceConfig> {
+        self.instances()
+            .into_iter()
+            .find(|i| i.name == name)
+    }
+
+    /// Checks the configuration for errors that cannot be caught during deserialization.
+    fn validate(&self) -> Result<(), String> {
+        let instances = self.instances();
+        if instances.is_empty() {
+            return Err("neither icinga_api nor icinga_instances is configured".to_owned());
+        }
+        let mut names = BTreeSet::new();
+        for instance in &instances {
+            if instance.name.is_empty() || instance.name.contains(':') {
+                return Err(format!("Icinga instance name {:?} is empty or contains a colon", instance.name));
+            }
+            if !names.insert(instance.name.as_str()) {
+                return Err(format!("Icinga instance name {:?} is used multiple times", instance.name));
+            }
+        }
+        if let Some(auth) = &self.auth {
+            if auth.icinga_credentials == IcingaCredentialsStrategy::PassThrough {
+                if auth.htpasswd_path.is_some() || auth.proxy_header.is_some() {
+                    return Err("auth.icinga_credentials = \"pass_through\" cannot be combined with htpasswd_path or proxy_header".to_owned());
+                }
+            } else if auth.htpasswd_path.is_none() && auth.proxy_header.is_none() {
+                return Err("auth requires htpasswd_path or proxy_header".to_owned());
+            }
+            if auth.proxy_header.is_some() && auth.trusted_proxies.is_empty() {
+                return Err("auth.proxy_header requires auth.trusted_proxies".to_owned());
+            }
+            for role in &auth.roles {
+                if let Some(filter) = &role.filter {
+                    if let Err(e) = crate::filter::parse(filter) {
+                        return Err(format!("role {:?} has invalid filter {:?}: {}", role.name, filter, e));
+                    }
+                }
+            }
+        }
+        for report in &self.reports {
+            crate::TableQuery::from_report(report)?;
+        }
+        if self.history.is_none() {
+            if let Some(report) = self.reports.iter().find(|r| r.history) {
+                return Err(format!("report {:?} has history enabled, which requires history", report.name));
+            }
+        }
+        if let Some(smtp) = &self.smtp {
+            if let Err(e) = smtp.from.parse::<Mailbox>() {
+                return Err(format!("invalid smtp.from address {:?}: {}", smtp.from, e));
+            }
+        } else if !self.scheduled_reports.is_empty() {
+            return Err("scheduled_reports requires smtp".to_owned());
+        }
+        for scheduled_report in &self.scheduled_reports {
+            if !self.reports.iter().any(|r| r.name == scheduled_report.report) {
+                return Err(format!("scheduled report refers to unknown report {:?}", scheduled_report.report));
+            }
+            if let Err(e) = Schedule::from_str(&scheduled_report.schedule) {
+                return Err(format!("invalid schedule {:?} for report {:?}: {}", scheduled_report.schedule, scheduled_report.report, e));
+            }
+            if scheduled_report.recipients.is_empty() {
+                return Err(format!("scheduled report {:?} has no recipients", scheduled_report.report));
+            }
+            for recipient in &scheduled_report.recipients {
+                if let Err(e) = recipient.parse::<Mailbox>() {
+                    return Err(format!("invalid recipient address {:?} for report {:?}: {}", recipient, scheduled_report.report, e));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Configuration related to the HTTP server.
@@ -33,6 +160,42 @@ pub(crate) struct Config {
 pub(crate) struct HttpServerConfig {
     /// IP address and port on which to listen for connections.
     pub listen_socket_address: SocketAddr,
+
+    /// If set, connections are served via HTTPS instead of plain HTTP.
+    #[serde(default)]
+    pub tls: Option<HttpsConfig>,
+}
+
+/// Configuration related to serving HTTPS.
+///
+/// The files are checked for changes periodically and reloaded if they have changed.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct HttpsConfig {
+    /// Path to a PEM file with the server certificate followed by any intermediate certificates.
+    pub cert_chain_path: PathBuf,
+
+    /// Path to a PEM file with the private key belonging to the server certificate.
+    pub private_key_path: PathBuf,
+
+    /// The minimum TLS version clients must support.
+    #[serde(default)]
+    pub min_version: TlsVersion,
+
+    /// Path to a PEM file with the CA certificates that may issue client certificates. If set,
+    /// clients must present a certificate issued by one of these CAs.
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// A version of the TLS protocol.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    V1_2,
+
+    #[serde(rename = "1.3")]
+    V1_3,
 }
 
 /// Configuration related to the Icinga API.
@@ -41,23 +204,302 @@ pub(crate) struct IcingaApiConfig {
     /// Base URL of the Icinga API.
     pub base_url: Url,
 
-    /// Username with which to authenticate against the Icinga API.
-    pub username: String,
+    /// Username with which to authenticate against the Icinga API using HTTP basic authentication.
+    /// If not set, basic authentication is not used (e.g. because a client certificate is used).
+    #[serde(default)]
+    pub username: Option<String>,
 
-    /// Password with which to authenticate against the Icinga API.
-    pub password: String,
+    /// Password with which to authenticate against the Icinga API using HTTP basic authentication.
+    #[serde(default)]
+    pub password: Option<String>,
 
     /// API call timeout in seconds.
     #[serde(default = "IcingaApiConfig::default_timeout_s")]
     pub timeout_s: u64,
 
     /// Whether to allow invalid SSL/TLS certificates.
+    #[serde(default)]
     pub allow_invalid_certs: bool,
+
+    /// Path to a PEM file with the CA certificates that may issue the Icinga API's certificate,
+    /// e.g. the Icinga CA. If set, the system's default CAs are not trusted.
+    #[serde(default)]
+    pub ca_bundle_path: Option<PathBuf>,
+
+    /// Path to a PEM file with the client certificate (chain) with which to authenticate against
+    /// the Icinga API.
+    #[serde(default)]
+    pub client_cert_path: Option<PathBuf>,
+
+    /// Path to a PEM file with the private key belonging to the client certificate.
+    #[serde(default)]
+    pub client_key_path: Option<PathBuf>,
+
+    /// SHA-256 fingerprints (hexadecimal, optionally colon-separated) of the certificates the Icinga
+    /// API may present. If set without `ca_bundle_path`, a matching certificate is accepted even if
+    /// it is not issued by a trusted CA.
+    #[serde(default)]
+    pub pinned_fingerprints: Vec<String>,
 }
 impl IcingaApiConfig {
     pub fn default_timeout_s() -> u64 { 10 }
 }
 
+/// Configuration of a named Icinga instance.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct IcingaInstanceConfig {
+    /// Name of the instance, shown in the instance column. Must not contain a colon.
+    pub name: String,
+
+    /// How to access the instance's API.
+    #[serde(flatten)]
+    pub api: IcingaApiConfig,
+}
+
+
+/// Configuration related to caching the responses of the Icinga API.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct CacheConfig {
+    /// How long the objects returned by a query are reused for identical queries, in seconds. If
+    /// 0, responses are only shared between identical queries made at the same time.
+    #[serde(default)]
+    pub ttl_s: u64,
+}
+
+/// Configuration related to the authentication of icingcake users.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct AuthConfig {
+    /// Path to an htpasswd-style file (`username:hash`, one per line) against which HTTP basic
+    /// authentication is verified. Only bcrypt and Argon2 hashes are supported. The file is read
+    /// anew on each login.
+    #[serde(default)]
+    pub htpasswd_path: Option<PathBuf>,
+
+    /// Name of the HTTP header (e.g. `X-Remote-User`) in which a reverse proxy passes the name of
+    /// the user it has authenticated.
+    #[serde(default)]
+    pub proxy_header: Option<String>,
+
+    /// IP addresses of the reverse proxies whose `proxy_header` is trusted. The header is ignored
+    /// on connections from any other address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// Realm announced when asking the browser for credentials.
+    #[serde(default = "AuthConfig::default_realm")]
+    pub realm: String,
+
+    /// Path to an htgroup-style file (`group: user1 user2`, one group per line) assigning users
+    /// authenticated via `htpasswd_path` to groups.
+    #[serde(default)]
+    pub group_file_path: Option<PathBuf>,
+
+    /// Name of the HTTP header (e.g. `X-Remote-Groups`) in which a trusted reverse proxy passes the
+    /// comma-separated groups of the user it has authenticated.
+    #[serde(default)]
+    pub proxy_groups_header: Option<String>,
+
+    /// Which credentials are used to access the Icinga API.
+    #[serde(default)]
+    pub icinga_credentials: IcingaCredentialsStrategy,
+
+    /// How long a session remains valid after login, in seconds.
+    #[serde(default = "AuthConfig::default_session_lifetime_s")]
+    pub session_lifetime_s: u64,
+
+    /// The roles restricting which objects users may see. If empty, every authenticated user may
+    /// see all objects.
+    #[serde(default)]
+    pub roles: Vec<RoleConfig>,
+}
+impl AuthConfig {
+    pub fn default_realm() -> String { "icingcake".to_owned() }
+    pub fn default_session_lifetime_s() -> u64 { 8 * 60 * 60 }
+}
+
+
+/// Which credentials are used to access the Icinga API.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IcingaCredentialsStrategy {
+    /// The username and password configured for each Icinga instance, shared by all users.
+    #[default]
+    ServiceAccount,
+
+    /// The credentials each user enters when asked by the browser (HTTP basic authentication),
+    /// verified by Icinga in place of `htpasswd_path`, so that the permissions and filters of the
+    /// user's own `ApiUser` apply. The credentials are only kept in memory for the duration of the
+    /// session.
+    PassThrough,
+}
+
+/// Configuration of a role, which restricts the objects its members may see and act upon.
+///
+/// A user who is a member of multiple roles may see the objects visible to any of them. If roles
+/// are configured, a user who is a member of none may not see anything.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct RoleConfig {
+    /// Name of the role, used in log messages.
+    pub name: String,
+
+    /// Icinga filter expression that objects must match to be visible to members of this role,
+    /// e.g. `"team-db" in host.groups`. It is combined with the filter entered by the user using
+    /// `&&`. If not set, members may see all objects.
+    #[serde(default)]
+    pub filter: Option<String>,
+
+    /// Names of the users who are members of this role.
+    #[serde(default)]
+    pub users: Vec<String>,
+
+    /// Names of the groups whose users are members of this role.
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// Whether members of this role are shown the filter actually passed to Icinga, which is
+    /// useful when debugging role filters.
+    #[serde(default)]
+    pub admin: bool,
+}
+impl RoleConfig {
+    /// Whether the given user, who is a member of the given groups, is a member of this role.
+    pub fn has_member(&self, username: &str, groups: &[String]) -> bool {
+        self.users.iter().any(|u| u == username)
+            || self.groups.iter().any(|g| groups.contains(g))
+    }
+}
+
+/// Configuration of a named report, accessible via `/report/<name>`.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct ReportConfig {
+    /// Name of the report, used in its URL.
+    pub name: String,
+
+    /// Human-readable title of the report. If not set, the name is used.
+    #[serde(default)]
+    pub title: Option<String>,
+
+    /// Type of object to query, e.g. `hosts` or `services`.
+    pub objtype: String,
+
+    /// Icinga filter expression selecting the objects to show.
+    pub filter: String,
+
+    /// Dotted paths of the attributes to show as columns. If empty, the object type's default
+    /// columns are shown.
+    #[serde(default)]
+    pub columns: Vec<String>,
+
+    /// Dotted paths of the columns by which to sort, each optionally prefixed by `-` for descending
+    /// order. If empty, rows are sorted by state, then host, then service.
+    #[serde(default)]
+    pub sort: Vec<String>,
+
+    /// Whether to export the number of objects shown by the report per state as metrics.
+    #[serde(default)]
+    pub state_metrics: bool,
+
+    /// Whether to record snapshots of the report in the history database.
+    #[serde(default)]
+    pub history: bool,
+}
+
+
+/// Configuration related to recording snapshots of reports.
+///
+/// Snapshots are taken with the Icinga credentials configured for each instance and without role
+/// restrictions.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct HistoryConfig {
+    /// Path to the SQLite database in which snapshots are stored. It is created if it does not
+    /// exist.
+    pub database_path: PathBuf,
+
+    /// How often a snapshot is taken of each report with `history` enabled, in seconds.
+    #[serde(default = "HistoryConfig::default_snapshot_interval_s")]
+    pub snapshot_interval_s: u64,
+
+    /// After how many days snapshots are deleted. If not set, snapshots are kept forever.
+    #[serde(default)]
+    pub retention_days: Option<u64>,
+
+    /// Whether to record the hard state changes of all hosts and services as well as their
+    /// downtimes from the Icinga event stream, from which availability reports are calculated.
+    /// These records are not subject to `retention_days`.
+    #[serde(default)]
+    pub record_availability: bool,
+}
+impl HistoryConfig {
+    pub fn default_snapshot_interval_s() -> u64 { 60 * 60 }
+}
+
+
+/// Configuration related to sending e-mail.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct SmtpConfig {
+    /// Host name of the SMTP server.
+    pub server: String,
+
+    /// Port of the SMTP server. If not set, the default port of the TLS mode is used.
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    #[serde(default)]
+    pub tls: SmtpTlsMode,
+
+    /// Username with which to authenticate against the SMTP server. If not set, no authentication
+    /// is performed.
+    #[serde(default)]
+    pub username: Option<String>,
+
+    /// Password with which to authenticate against the SMTP server.
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Sender address, e.g. `icingcake <icingcake@example.com>`.
+    pub from: String,
+}
+
+/// How the connection to the SMTP server is secured.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SmtpTlsMode {
+    /// Upgrade the connection via STARTTLS (port 587 by default).
+    #[default]
+    Starttls,
+
+    /// Use TLS from the start (port 465 by default).
+    Tls,
+
+    /// Do not secure the connection (port 25 by default).
+    None,
+}
+
+/// Configuration of a report sent via e-mail on a schedule.
+///
+/// Scheduled reports are generated with the Icinga credentials configured for each instance and
+/// without role restrictions.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct ScheduledReportConfig {
+    /// Name of the report to send.
+    pub report: String,
+
+    /// When to send the report, as a cron expression with seconds (`sec min hour day month
+    /// weekday [year]`) in local time, e.g. `0 0 7 * * Mon-Fri`.
+    pub schedule: String,
+
+    /// Addresses to which to send the report.
+    pub recipients: Vec<String>,
+
+    /// Subject of the e-mail. If not set, the title of the report and the current date are used.
+    #[serde(default)]
+    pub subject: Option<String>,
+
+    /// Whether to skip sending the report if it contains no rows.
+    #[serde(default)]
+    pub skip_if_empty: bool,
+}
+
 
 /// An error that may occur when loading the configuration.
 #[derive(Debug)]
@@ -67,6 +509,7 @@ pub(crate) enum ConfigLoadError {
     #[non_exhaustive] Reading { error: io::Error },
     #[non_exhaustive] Decoding { error: Utf8Error },
     #[non_exhaustive] Parsing { error: toml::de::Error },
+    #[non_exhaustive] Invalid { description: String },
 }
 impl fmt::Display for ConfigLoadError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -79,6 +522,8 @@ impl fmt::Display for ConfigLoadError {
                 => write!(f, "error decoding config file: {}", error),
             Self::Parsing { error, .. }
                 => write!(f, "error parsing config file: {}", error),
+            Self::Invalid { description, .. }
+                => write!(f, "invalid configuration: {}", description),
         }
     }
 }
@@ -89,6 +534,7 @@ impl std::error::Error for ConfigLoadError {
             Self::Reading { error, .. } => Some(error),
             Self::Decoding { error, .. } => Some(error),
             Self::Parsing { error, .. } => Some(error),
+            Self::Invalid { .. } => None,
         }
     }
 }
@@ -105,6 +551,41 @@ pub(crate) fn load() -> Result<Config, ConfigLoadError> {
         .map_err(|error| ConfigLoadError::Reading { error })?;
     let string = std::str::from_utf8(buf.as_slice())
         .map_err(|error| ConfigLoadError::Decoding { error })?;
-    toml::from_str(&string)
-        .map_err(|error| ConfigLoadError::Parsing { error })
+    let config: Config = toml::from_str(&string)
+        .map_err(|error| ConfigLoadError::Parsing { error })?;
+    config.validate()
+        .map_err(|description| ConfigLoadError::Invalid { description })?;
+    Ok(config)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    fn config_with_report(report: &str) -> Config {
+        let text = format!(
+            "[http_server]\nlisten_socket_address = \"127.0.0.1:8080\"\n\n[icinga_api]\nbase_url = \"https://icinga.example.com:5665/\"\n\n[[reports]]\nname = \"problems\"\n{}\n",
+            report,
+        );
+        toml::from_str(&text).expect("failed to parse configuration")
+    }
+
+    #[test]
+    fn test_valid_report() {
+        let config = config_with_report("objtype = \"services\"\nfilter = \"service.state != 0\"");
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_reports() {
+        let config = config_with_report("objtype = \"services\"\nfilter = \"service.state != \"");
+        assert!(config.validate().unwrap_err().contains("invalid filter"));
+
+        let config = config_with_report("objtype = \"potatoes\"\nfilter = \"\"");
+        assert!(config.validate().unwrap_err().contains("invalid objtype"));
+
+        let config = config_with_report("objtype = \"services\"\nfilter = \"\"\ncolumns = [\"vars..os\"]");
+        assert!(config.validate().unwrap_err().contains("invalid columns"));
+    }
 }
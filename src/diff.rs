@@ -0,0 +1,420 @@
+//! Comparing two results of a report, e.g. a snapshot from last night with the current state.
+
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+
+use askama::Template;
+use chrono::{DateTime, Local, Utc};
+use hyper::{Body, Request, Response};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use tracing::error;
+
+use crate::{
+    get_optional_parameter, get_required_parameter, handle_400_wrong_parameter,
+    handle_plaintext_response, NagiosState, return_500, RowPart,
+};
+use crate::columns::Column;
+use crate::history;
+use crate::icinga::{self, InstanceError};
+use crate::objtypes::ObjectType;
+
+
+/// The value of the `to` parameter selecting the current state instead of a snapshot.
+const LIVE: &str = "live";
+
+
+/// How a row differs between the older and the newer result.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum RowChange {
+    Unchanged,
+    Appeared,
+    Disappeared,
+    Changed,
+}
+impl RowChange {
+    pub fn css_class(&self) -> &'static str {
+        match self {
+            Self::Unchanged => "",
+            Self::Appeared => "diff-appeared",
+            Self::Disappeared => "diff-disappeared",
+            Self::Changed => "diff-changed",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Unchanged => "",
+            Self::Appeared => "neu",
+            Self::Disappeared => "entfallen",
+            Self::Changed => "geändert",
+        }
+    }
+}
+
+
+/// A row in the comparison of two results.
+#[derive(Clone, Debug)]
+pub(crate) struct DiffRow {
+    pub change: RowChange,
+
+    /// The row in the newer result or, if it has disappeared, in the older one.
+    pub row: RowPart,
+
+    /// The state in the older result, if it has changed.
+    pub previous_state: Option<NagiosState>,
+
+    /// The output in the older result, if it has changed.
+    pub previous_output: Option<String>,
+}
+
+
+/// The comparison of two results of the same query.
+#[derive(Clone, Debug)]
+pub(crate) struct Diff {
+    pub rows: Vec<DiffRow>,
+
+    /// Human-readable descriptions of the changes, e.g. `3 services Ok → Critical`.
+    pub transitions: Vec<String>,
+
+    pub unchanged_count: usize,
+}
+
+
+/// Compares the rows of an older and a newer result, matching them by their keys.
+///
+/// The rows of the newer result are kept in their order, followed by those that have disappeared.
+pub(crate) fn compare(object_type: &ObjectType, old_rows: Vec<RowPart>, new_rows: Vec<RowPart>) -> Diff {
+    let mut old_by_key: HashMap<String, RowPart> = old_rows.into_iter()
+        .map(|r| (r.key(), r))
+        .collect();
+
+    let mut rows = Vec::with_capacity(new_rows.len());
+    let mut state_transitions: BTreeMap<(u8, u8), usize> = BTreeMap::new();
+    let mut appeared_count = 0;
+    let mut output_changed_count = 0;
+    let mut unchanged_count = 0;
+    for new_row in new_rows {
+        let old_row = match old_by_key.remove(&new_row.key()) {
+            Some(or) => or,
+            None => {
+                appeared_count += 1;
+                rows.push(DiffRow {
+                    change: RowChange::Appeared,
+                    row: new_row,
+                    previous_state: None,
+                    previous_output: None,
+                });
+                continue;
+            },
+        };
+
+        let state_changed = old_row.state.to_base_type() != new_row.state.to_base_type();
+        let output_changed = old_row.output != new_row.output;
+        if state_changed {
+            *state_transitions
+                .entry((old_row.state.to_base_type(), new_row.state.to_base_type()))
+                .or_insert(0) += 1;
+        } else if output_changed {
+            output_changed_count += 1;
+        } else {
+            unchanged_count += 1;
+        }
+
+        rows.push(DiffRow {
+            change: if state_changed || output_changed { RowChange::Changed } else { RowChange::Unchanged },
+            row: new_row,
+            previous_state: if state_changed { Some(old_row.state) } else { None },
+            previous_output: if output_changed { Some(old_row.output) } else { None },
+        });
+    }
+
+    let mut disappeared: Vec<RowPart> = old_by_key.into_values().collect();
+    disappeared.sort_unstable();
+    let disappeared_count = disappeared.len();
+    rows.extend(disappeared.into_iter().map(|row| DiffRow {
+        change: RowChange::Disappeared,
+        row,
+        previous_state: None,
+        previous_output: None,
+    }));
+
+    let (singular, plural) = german_nouns(object_type);
+    let noun = |count: usize| if count == 1 { singular } else { plural };
+    let mut transitions = Vec::new();
+    for ((old_state, new_state), count) in state_transitions {
+        transitions.push(format!(
+            "{} {} {} \u{2192} {}",
+            count, noun(count), NagiosState::from(old_state), NagiosState::from(new_state),
+        ));
+    }
+    if output_changed_count > 0 {
+        transitions.push(format!("{} {} mit geänderter Ausgabe", output_changed_count, noun(output_changed_count)));
+    }
+    if appeared_count > 0 {
+        transitions.push(format!("{} {} neu", appeared_count, noun(appeared_count)));
+    }
+    if disappeared_count > 0 {
+        transitions.push(format!("{} {} entfallen", disappeared_count, noun(disappeared_count)));
+    }
+
+    Diff {
+        rows,
+        transitions,
+        unchanged_count,
+    }
+}
+
+/// Returns the German singular and plural nouns for objects of the given type, as used in the
+/// summary of a comparison.
+fn german_nouns(object_type: &ObjectType) -> (&'static str, &'static str) {
+    match object_type.plural {
+        "hosts" => ("Host", "Hosts"),
+        "services" => ("Service", "Services"),
+        _ => ("Objekt", "Objekte"),
+    }
+}
+
+
+#[derive(Template)]
+#[template(path = "diff.html")]
+struct DiffTemplate {
+    pub title: String,
+    pub from_label: String,
+    pub to_label: String,
+    pub history_link: String,
+    pub columns: Vec<Column>,
+    pub diff: Diff,
+    pub instance_errors: Vec<(String, String)>,
+}
+
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+
+/// Compares a snapshot of a report with a newer snapshot or with the current state.
+///
+/// The older snapshot is given in the `from` parameter, the newer one in the `to` parameter; if
+/// `to` is `live` or not given, the current state is queried using the older snapshot's query.
+pub(crate) async fn handle_compare(request: Request<Body>, report_name: &str) -> Result<Response<Body>, Infallible> {
+    if let Err(resp) = history::check_history_access(&request).await {
+        return resp;
+    }
+
+    let query_pairs: Vec<(Cow<str>, Cow<str>)> = if let Some(query) = request.uri().query() {
+        form_urlencoded::parse(query.as_bytes())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let from = match get_required_parameter(&query_pairs, "from").await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let to = get_optional_parameter(&query_pairs, "to")
+        .map(|t| t.as_ref())
+        .unwrap_or(LIVE);
+
+    let old_snapshot = match history::get_snapshot(report_name, from).await {
+        Ok(s) => s,
+        Err(resp) => return resp,
+    };
+    let query = match old_snapshot.query() {
+        Ok(q) => q,
+        Err(e) => {
+            error!("{}", e);
+            return return_500();
+        },
+    };
+
+    let mut instance_errors = old_snapshot.instance_errors;
+    let (columns, to_label, new_rows) = if to == LIVE {
+        let query_result = match icinga::fetch_all_rows(&query, true).await {
+            Ok(qr) => qr,
+            Err(InstanceError { instance, error }) => {
+                error!("failed to query {} from Icinga instance {:?}: {}", query.object_type.plural, instance, error);
+                return return_500();
+            },
+        };
+        instance_errors.extend(
+            query_result.errors.iter()
+                .map(|ie| (ie.instance.clone(), ie.error.to_string()))
+        );
+        let to_label = format!("aktueller Stand ({})", format_timestamp(query_result.oldest_fetched_at));
+        (query.columns.clone(), to_label, query_result.rows)
+    } else {
+        let new_snapshot = match history::get_snapshot(report_name, to).await {
+            Ok(s) => s,
+            Err(resp) => return resp,
+        };
+        if new_snapshot.taken_at < old_snapshot.taken_at {
+            return handle_400_wrong_parameter("to", to).await;
+        }
+        let new_query = match new_snapshot.query() {
+            Ok(q) => q,
+            Err(e) => {
+                error!("{}", e);
+                return return_500();
+            },
+        };
+        if new_query.columns != query.columns {
+            // the cells of the rows could not be shown in a common table
+            return handle_plaintext_response(400, "the snapshots have different columns and cannot be compared").await;
+        }
+        instance_errors.extend(new_snapshot.instance_errors);
+        (new_query.columns, format_timestamp(new_snapshot.taken_at), new_snapshot.rows)
+    };
+
+    let diff = compare(query.object_type, old_snapshot.rows, new_rows);
+    let template = DiffTemplate {
+        title: history::report_title(report_name).await,
+        from_label: format_timestamp(old_snapshot.taken_at),
+        to_label,
+        history_link: format!("../history/{}", utf8_percent_encode(report_name, NON_ALPHANUMERIC)),
+        columns,
+        diff,
+        instance_errors,
+    };
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render diff template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objtypes;
+
+    fn row(instance: &str, host: &str, service: &str, state: u8, output: &str) -> RowPart {
+        RowPart {
+            instance: instance.to_owned(),
+            name: format!("{}!{}", host, service),
+            host: host.to_owned(),
+            service: service.to_owned(),
+            output: output.to_owned(),
+            state: NagiosState::from(state),
+            cells: Vec::new(),
+        }
+    }
+
+    fn services() -> &'static ObjectType {
+        objtypes::by_plural("services").unwrap()
+    }
+
+    #[test]
+    fn test_classification() {
+        let old_rows = vec![
+            row("master", "web01", "http", 0, "HTTP OK"),
+            row("master", "web01", "disk", 0, "DISK OK - 50% free"),
+            row("master", "db01", "postgres", 2, "connection refused"),
+            row("master", "db01", "backup", 0, "backup OK"),
+        ];
+        let new_rows = vec![
+            row("master", "web01", "http", 0, "HTTP OK"),
+            row("master", "web01", "disk", 2, "DISK CRITICAL - 1% free"),
+            row("master", "db01", "postgres", 2, "connection timed out"),
+            row("master", "mail01", "smtp", 1, "slow response"),
+        ];
+        let diff = compare(services(), old_rows, new_rows);
+
+        let summary: Vec<(&str, RowChange)> = diff.rows.iter()
+            .map(|r| (r.row.name.as_str(), r.change))
+            .collect();
+        assert_eq!(summary, vec![
+            ("web01!http", RowChange::Unchanged),
+            ("web01!disk", RowChange::Changed),
+            ("db01!postgres", RowChange::Changed),
+            ("mail01!smtp", RowChange::Appeared),
+            ("db01!backup", RowChange::Disappeared),
+        ]);
+
+        // state and output changed
+        assert_eq!(diff.rows[1].previous_state.map(|s| s.to_base_type()), Some(0));
+        assert_eq!(diff.rows[1].previous_output.as_deref(), Some("DISK OK - 50% free"));
+        assert_eq!(diff.rows[1].row.output, "DISK CRITICAL - 1% free");
+
+        // only output changed
+        assert_eq!(diff.rows[2].previous_state.map(|s| s.to_base_type()), None);
+        assert_eq!(diff.rows[2].previous_output.as_deref(), Some("connection refused"));
+
+        assert_eq!(diff.rows[0].previous_state.map(|s| s.to_base_type()), None);
+        assert_eq!(diff.rows[0].previous_output, None);
+        assert_eq!(diff.rows[4].row.output, "backup OK");
+
+        // a state change that comes with an output change only counts as a transition
+        assert_eq!(diff.transitions, vec![
+            "1 Service Ok \u{2192} Critical".to_owned(),
+            "1 Service mit geänderter Ausgabe".to_owned(),
+            "1 Service neu".to_owned(),
+            "1 Service entfallen".to_owned(),
+        ]);
+        assert_eq!(diff.unchanged_count, 1);
+    }
+
+    #[test]
+    fn test_transitions() {
+        let old_rows = vec![
+            row("master", "a", "x", 0, "ok"),
+            row("master", "b", "x", 0, "ok"),
+            row("master", "c", "x", 2, "critical"),
+            row("master", "d", "x", 1, "warning"),
+        ];
+        let new_rows = vec![
+            row("master", "a", "x", 2, "critical"),
+            row("master", "b", "x", 2, "critical"),
+            row("master", "c", "x", 0, "ok"),
+            row("master", "d", "x", 1, "warning"),
+        ];
+        let diff = compare(services(), old_rows, new_rows);
+        assert_eq!(diff.transitions, vec![
+            "2 Services Ok \u{2192} Critical".to_owned(),
+            "1 Service Critical \u{2192} Ok".to_owned(),
+        ]);
+        assert_eq!(diff.unchanged_count, 1);
+    }
+
+    #[test]
+    fn test_rows_matched_by_instance_and_name() {
+        let old_rows = vec![row("eu", "web01", "http", 0, "ok")];
+        let new_rows = vec![row("us", "web01", "http", 0, "ok")];
+        let diff = compare(services(), old_rows, new_rows);
+        let changes: Vec<RowChange> = diff.rows.iter().map(|r| r.change).collect();
+        assert_eq!(changes, vec![RowChange::Appeared, RowChange::Disappeared]);
+        assert_eq!(diff.unchanged_count, 0);
+    }
+
+    #[test]
+    fn test_identical() {
+        let rows = vec![
+            row("master", "web01", "http", 0, "ok"),
+            row("master", "web02", "http", 2, "down"),
+        ];
+        let diff = compare(services(), rows.clone(), rows);
+        assert!(diff.transitions.is_empty());
+        assert_eq!(diff.unchanged_count, 2);
+        assert!(diff.rows.iter().all(|r| r.change == RowChange::Unchanged));
+    }
+
+    #[test]
+    fn test_german_nouns() {
+        assert_eq!(german_nouns(objtypes::by_plural("hosts").unwrap()), ("Host", "Hosts"));
+        assert_eq!(german_nouns(services()), ("Service", "Services"));
+        assert_eq!(german_nouns(objtypes::by_plural("zones").unwrap()), ("Objekt", "Objekte"));
+    }
+}
@@ -0,0 +1,286 @@
+//! Exporting table rows into formats other than HTML.
+
+use std::borrow::Cow;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::RowPart;
+use crate::columns::{CellValue, Column};
+use crate::icinga::InstanceError;
+
+
+/// Appends a single field to a CSV line, quoting it according to RFC 4180 if necessary.
+fn push_csv_field(line: &mut String, value: &str) {
+    let needs_quoting = value.contains(['"', ',', '\r', '\n']);
+    if !needs_quoting {
+        line.push_str(value);
+        return;
+    }
+
+    line.push('"');
+    for c in value.chars() {
+        if c == '"' {
+            // quotes are escaped by doubling them
+            line.push('"');
+        }
+        line.push(c);
+    }
+    line.push('"');
+}
+
+/// Neutralizes a value that spreadsheet applications would interpret as a formula (e.g. plugin
+/// output starting with `=`) by prefixing it with an apostrophe.
+fn guard_formula(value: &str) -> Cow<'_, str> {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Appends a full record to a CSV document, terminating it with CRLF as prescribed by RFC 4180.
+fn push_csv_record<'a, I: IntoIterator<Item = &'a str>>(document: &mut String, fields: I) {
+    let mut first = true;
+    for field in fields {
+        if first {
+            first = false;
+        } else {
+            document.push(',');
+        }
+        push_csv_field(document, field);
+    }
+    document.push_str("\r\n");
+}
+
+/// Renders the given rows as an RFC 4180 CSV document, including a header record.
+///
+/// Values other than numbers that look like formulas are neutralized with [guard_formula].
+pub(crate) fn rows_to_csv(columns: &[Column], rows: &[RowPart]) -> String {
+    let mut document = String::new();
+    let titles: Vec<String> = columns.iter()
+        .map(|c| guard_formula(&c.title()).into_owned())
+        .collect();
+    push_csv_record(&mut document, titles.iter().map(|t| t.as_str()));
+    for row in rows {
+        let values: Vec<String> = row.cells.iter()
+            .map(|c| match c {
+                CellValue::Number(_) => c.to_string(),
+                _ => guard_formula(&c.to_string()).into_owned(),
+            })
+            .collect();
+        push_csv_record(&mut document, values.iter().map(|v| v.as_str()));
+    }
+    document
+}
+
+
+/// Renders the given rows as a plain-text table with aligned columns, including a header line.
+///
+/// Line breaks within cells are replaced by spaces.
+pub(crate) fn rows_to_text(columns: &[Column], rows: &[RowPart]) -> String {
+    let titles: Vec<String> = columns.iter()
+        .map(|c| c.title())
+        .collect();
+    let values: Vec<Vec<String>> = rows.iter()
+        .map(|row| row.cells.iter()
+            .map(|c| c.to_string().replace(['\r', '\n'], " "))
+            .collect()
+        )
+        .collect();
+
+    let mut widths: Vec<usize> = titles.iter()
+        .map(|t| t.chars().count())
+        .collect();
+    for row_values in &values {
+        for (width, value) in widths.iter_mut().zip(row_values.iter()) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let mut document = String::new();
+    let mut push_line = |fields: &[String]| {
+        let mut line = String::new();
+        for (i, (field, width)) in fields.iter().zip(widths.iter()).enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            line.push_str(field);
+            for _ in field.chars().count()..*width {
+                line.push(' ');
+            }
+        }
+        document.push_str(line.trim_end());
+        document.push('\n');
+    };
+    push_line(&titles);
+    let separators: Vec<String> = widths.iter()
+        .map(|w| "-".repeat(*w))
+        .collect();
+    push_line(&separators);
+    for row_values in &values {
+        push_line(row_values);
+    }
+    document
+}
+
+
+/// A table row as output in JSON format.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct JsonRow<'a> {
+    pub instance: &'a str,
+    pub name: &'a str,
+    pub host: &'a str,
+    pub service: &'a str,
+    pub output: &'a str,
+    pub state: u8,
+    pub state_name: String,
+    pub columns: serde_json::Map<String, serde_json::Value>,
+}
+impl<'a> JsonRow<'a> {
+    pub fn new(columns: &[Column], row: &'a RowPart) -> Self {
+        let column_values = columns.iter()
+            .zip(row.cells.iter())
+            .map(|(column, cell)| (column.path.clone(), cell.to_json()))
+            .collect();
+        Self {
+            instance: &row.instance,
+            name: &row.name,
+            host: &row.host,
+            service: &row.service,
+            output: &row.output,
+            state: row.state.to_base_type(),
+            state_name: row.state.to_string(),
+            columns: column_values,
+        }
+    }
+}
+
+/// The description of a column as output in JSON format.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct JsonColumn<'a> {
+    pub path: &'a str,
+    pub title: String,
+}
+impl<'a> From<&'a Column> for JsonColumn<'a> {
+    fn from(column: &'a Column) -> Self {
+        Self {
+            path: &column.path,
+            title: column.title(),
+        }
+    }
+}
+
+/// A full table, including metadata about the query, as output in JSON format.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct JsonTable<'a> {
+    pub objtype: &'a str,
+    pub filter: &'a str,
+    pub fetched_at: DateTime<Utc>,
+    pub icinga_status: u16,
+    pub columns: Vec<JsonColumn<'a>>,
+    pub rows: Vec<JsonRow<'a>>,
+    pub errors: Vec<JsonInstanceError>,
+}
+impl<'a> JsonTable<'a> {
+    pub fn new(
+        objtype: &'a str,
+        filter: &'a str,
+        fetched_at: DateTime<Utc>,
+        icinga_status: u16,
+        columns: &'a [Column],
+        rows: &'a [RowPart],
+        errors: &[InstanceError],
+    ) -> Self {
+        Self {
+            objtype,
+            filter,
+            fetched_at,
+            icinga_status,
+            columns: columns.iter().map(JsonColumn::from).collect(),
+            rows: rows.iter().map(|row| JsonRow::new(columns, row)).collect(),
+            errors: errors.iter().map(JsonInstanceError::from).collect(),
+        }
+    }
+}
+
+/// An error of one of several queried Icinga instances, as output in JSON format.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct JsonInstanceError {
+    pub instance: String,
+    pub error: String,
+}
+impl From<&InstanceError> for JsonInstanceError {
+    fn from(instance_error: &InstanceError) -> Self {
+        Self {
+            instance: instance_error.instance.clone(),
+            error: instance_error.error.to_string(),
+        }
+    }
+}
+
+/// An error returned by Icinga, including metadata about the query, as output in JSON format.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct JsonIcingaError<'a> {
+    pub objtype: &'a str,
+    pub filter: &'a str,
+    pub fetched_at: DateTime<Utc>,
+    pub icinga_status: u16,
+    pub error: serde_json::Value,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NagiosState;
+    use crate::objtypes;
+
+    fn row(cells: Vec<CellValue>) -> RowPart {
+        RowPart {
+            instance: "icinga".to_owned(),
+            name: "host1".to_owned(),
+            host: "host1".to_owned(),
+            service: String::new(),
+            output: String::new(),
+            state: NagiosState::Ok,
+            cells,
+        }
+    }
+
+    fn csv_of(values: &[&str]) -> String {
+        let hosts = objtypes::by_plural("hosts").unwrap();
+        let columns = vec![Column::parse(hosts, "name").unwrap(); values.len()];
+        let cells = values.iter().map(|v| CellValue::Text((*v).to_owned())).collect();
+        rows_to_csv(&columns, &[row(cells)])
+    }
+
+    #[test]
+    fn test_plain_fields() {
+        assert_eq!(csv_of(&["a", "b c"]), "Name,Name\r\na,b c\r\n");
+    }
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(csv_of(&["a,b"]), "Name\r\n\"a,b\"\r\n");
+        assert_eq!(csv_of(&["say \"hi\""]), "Name\r\n\"say \"\"hi\"\"\"\r\n");
+        assert_eq!(csv_of(&["line1\nline2"]), "Name\r\n\"line1\nline2\"\r\n");
+        assert_eq!(csv_of(&["a\rb"]), "Name\r\n\"a\rb\"\r\n");
+    }
+
+    #[test]
+    fn test_formula_guard() {
+        assert_eq!(csv_of(&["=HYPERLINK(\"x\")"]), "Name\r\n\"'=HYPERLINK(\"\"x\"\")\"\r\n");
+        assert_eq!(csv_of(&["+1", "-1", "@SUM(A1)"]), "Name,Name,Name\r\n'+1,'-1,'@SUM(A1)\r\n");
+        assert_eq!(csv_of(&["\tx"]), "Name\r\n'\tx\r\n");
+        assert_eq!(csv_of(&["a=b"]), "Name\r\na=b\r\n");
+    }
+
+    #[test]
+    fn test_numbers_not_guarded() {
+        let hosts = objtypes::by_plural("hosts").unwrap();
+        let columns = vec![Column::parse(hosts, "check_attempt").unwrap()];
+        let csv = rows_to_csv(&columns, &[row(vec![CellValue::Number(-1.5)])]);
+        assert_eq!(csv, "check_attempt\r\n-1.5\r\n");
+    }
+}
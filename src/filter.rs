@@ -0,0 +1,1051 @@
+//! Parsing, validation and normalization of Icinga 2 filter expressions.
+//!
+//! The accepted language is the subset of the Icinga 2 DSL that makes sense in API filters:
+//! literals, variables, member access, indexing, function calls, arrays, and unary and binary
+//! operators. Assignments, lambdas and control flow are rejected.
+
+
+use std::fmt;
+
+
+/// An error encountered while parsing a filter expression.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct FilterParseError {
+    /// The 1-based line on which the error was encountered.
+    pub line: usize,
+
+    /// The 1-based column (in characters) at which the error was encountered.
+    pub column: usize,
+
+    /// A description of the error.
+    pub message: String,
+}
+impl FilterParseError {
+    fn new<M: Into<String>>(position: Position, message: M) -> Self {
+        Self {
+            line: position.line,
+            column: position.column,
+            message: message.into(),
+        }
+    }
+
+    /// Returns the line of the filter text on which the error was encountered.
+    pub fn source_line<'a>(&self, filter: &'a str) -> &'a str {
+        filter
+            .split('\n')
+            .nth(self.line - 1)
+            .unwrap_or("")
+            .trim_end_matches('\r')
+    }
+
+    /// Returns a string that points at the column of the error when placed below the source line.
+    pub fn marker(&self) -> String {
+        let mut marker = " ".repeat(self.column - 1);
+        marker.push('^');
+        marker
+    }
+}
+impl fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+impl std::error::Error for FilterParseError {}
+
+
+/// A unary operator.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum UnaryOperator {
+    /// `!`
+    LogicalNot,
+
+    /// `~`
+    BitwiseNot,
+
+    /// `+`
+    Plus,
+
+    /// `-`
+    Minus,
+}
+impl UnaryOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::LogicalNot => "!",
+            Self::BitwiseNot => "~",
+            Self::Plus => "+",
+            Self::Minus => "-",
+        }
+    }
+}
+
+
+/// A binary operator.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum BinaryOperator {
+    LogicalOr,
+    LogicalAnd,
+    BitwiseOr,
+    BitwiseXor,
+    BitwiseAnd,
+    Equal,
+    NotEqual,
+    In,
+    NotIn,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+impl BinaryOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::LogicalOr => "||",
+            Self::LogicalAnd => "&&",
+            Self::BitwiseOr => "|",
+            Self::BitwiseXor => "^",
+            Self::BitwiseAnd => "&",
+            Self::Equal => "==",
+            Self::NotEqual => "!=",
+            Self::In => "in",
+            Self::NotIn => "!in",
+            Self::Less => "<",
+            Self::Greater => ">",
+            Self::LessEqual => "<=",
+            Self::GreaterEqual => ">=",
+            Self::ShiftLeft => "<<",
+            Self::ShiftRight => ">>",
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::Modulo => "%",
+        }
+    }
+
+    /// The precedence of the operator; operators with higher precedence bind more tightly.
+    ///
+    /// Follows the operator precedence table in the Icinga 2 language reference.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::LogicalOr => 1,
+            Self::LogicalAnd => 2,
+            Self::BitwiseOr => 3,
+            Self::BitwiseXor => 4,
+            Self::BitwiseAnd => 5,
+            Self::Equal|Self::NotEqual => 6,
+            Self::In|Self::NotIn => 7,
+            Self::Less|Self::Greater|Self::LessEqual|Self::GreaterEqual => 8,
+            Self::ShiftLeft|Self::ShiftRight => 9,
+            Self::Add|Self::Subtract => 10,
+            Self::Multiply|Self::Divide|Self::Modulo => 11,
+        }
+    }
+
+    /// Whether the operator may be chained without parentheses (`a + b + c`).
+    ///
+    /// Equality operators are non-associative: `a == b == c` is rejected like in Icinga 2.
+    pub fn is_associative(&self) -> bool {
+        !matches!(self, Self::Equal|Self::NotEqual)
+    }
+}
+
+/// The precedence of unary operators.
+const UNARY_PRECEDENCE: u8 = 12;
+
+/// The precedence of postfix operations (member access, indexing, calls).
+const POSTFIX_PRECEDENCE: u8 = 13;
+
+/// The precedence of atoms, which never need to be parenthesized.
+const ATOM_PRECEDENCE: u8 = 14;
+
+
+/// A literal value.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Literal {
+    Null,
+    Boolean(bool),
+
+    /// A number, possibly with a duration suffix (e.g. `5m`), kept in its textual form.
+    Number(String),
+
+    String(String),
+}
+
+
+/// A node of the abstract syntax tree of a filter expression.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Expression {
+    Literal(Literal),
+    Variable(String),
+    Array(Vec<Expression>),
+    Member { object: Box<Expression>, member: String },
+    Index { object: Box<Expression>, index: Box<Expression> },
+    Call { function: Box<Expression>, arguments: Vec<Expression> },
+    Unary { operator: UnaryOperator, operand: Box<Expression> },
+    Binary { operator: BinaryOperator, left: Box<Expression>, right: Box<Expression> },
+}
+impl Expression {
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Literal(_)|Self::Variable(_)|Self::Array(_) => ATOM_PRECEDENCE,
+            Self::Member { .. }|Self::Index { .. }|Self::Call { .. } => POSTFIX_PRECEDENCE,
+            Self::Unary { .. } => UNARY_PRECEDENCE,
+            Self::Binary { operator, .. } => operator.precedence(),
+        }
+    }
+
+    /// Writes the expression, surrounded by parentheses if its precedence is lower than the given
+    /// minimum.
+    fn fmt_with_min_precedence(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Literal(Literal::Null) => write!(f, "null"),
+            Self::Literal(Literal::Boolean(b)) => write!(f, "{}", b),
+            Self::Literal(Literal::Number(n)) => write!(f, "{}", n),
+            Self::Literal(Literal::String(s)) => write!(f, "{}", quote_string(s)),
+            Self::Variable(name) => write!(f, "{}", name),
+            Self::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Self::Member { object, member } => {
+                object.fmt_with_min_precedence(f, POSTFIX_PRECEDENCE)?;
+                write!(f, ".{}", member)
+            },
+            Self::Index { object, index } => {
+                object.fmt_with_min_precedence(f, POSTFIX_PRECEDENCE)?;
+                write!(f, "[{}]", index)
+            },
+            Self::Call { function, arguments } => {
+                function.fmt_with_min_precedence(f, POSTFIX_PRECEDENCE)?;
+                write!(f, "(")?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            },
+            Self::Unary { operator, operand } => {
+                write!(f, "{}", operator.symbol())?;
+                operand.fmt_with_min_precedence(f, UNARY_PRECEDENCE)
+            },
+            Self::Binary { operator, left, right } => {
+                // associative binary operators are left-associative
+                let left_precedence = if operator.is_associative() {
+                    operator.precedence()
+                } else {
+                    operator.precedence() + 1
+                };
+                left.fmt_with_min_precedence(f, left_precedence)?;
+                write!(f, " {} ", operator.symbol())?;
+                right.fmt_with_min_precedence(f, operator.precedence() + 1)
+            },
+        }
+    }
+}
+
+
+/// Quotes a string for inclusion in a filter expression.
+///
+/// Compatible with `quoteIcingaFilter` in `script.ts`, additionally escaping control characters.
+pub(crate) fn quote_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => ret.push_str("\\\\"),
+            '"' => ret.push_str("\\\""),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            other => ret.push(other),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+
+/// A position within the filter text.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+
+/// A lexical token.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum Token {
+    Identifier(String),
+    Number(String),
+    String(String),
+    Null,
+    True,
+    False,
+    In,
+    NotIn,
+    Symbol(&'static str),
+}
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identifier(i) => write!(f, "identifier `{}`", i),
+            Self::Number(n) => write!(f, "number `{}`", n),
+            Self::String(s) => write!(f, "string {}", quote_string(s)),
+            Self::Null => write!(f, "`null`"),
+            Self::True => write!(f, "`true`"),
+            Self::False => write!(f, "`false`"),
+            Self::In => write!(f, "`in`"),
+            Self::NotIn => write!(f, "`!in`"),
+            Self::Symbol(s) => write!(f, "`{}`", s),
+        }
+    }
+}
+
+
+/// Symbols recognized by the tokenizer, longest first so that the longest match wins.
+const SYMBOLS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>",
+    "(", ")", "[", "]", ",", ".",
+    "!", "~", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^",
+];
+
+/// Symbols that are valid in the Icinga 2 DSL but not in filters.
+const FORBIDDEN_SYMBOLS: &[&str] = &[
+    "+=", "-=", "*=", "/=", "=>", "=",
+];
+
+
+/// Splits a filter text into tokens.
+struct Tokenizer {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+impl Tokenizer {
+    fn new(text: &str) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            index: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position { line: self.line, column: self.column }
+    }
+
+    fn peek_char(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.index + offset).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.peek_char(i) == Some(c))
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek_char(0)?;
+        self.index += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn advance_by(&mut self, count: usize) {
+        for _ in 0..count {
+            self.advance();
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), FilterParseError> {
+        loop {
+            match self.peek_char(0) {
+                Some(c) if c.is_whitespace() => {
+                    self.advance();
+                },
+                Some('/') if self.peek_char(1) == Some('/') => {
+                    while let Some(c) = self.peek_char(0) {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.advance();
+                    }
+                },
+                Some('#') => {
+                    while let Some(c) = self.peek_char(0) {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.advance();
+                    }
+                },
+                Some('/') if self.peek_char(1) == Some('*') => {
+                    let start = self.position();
+                    self.advance_by(2);
+                    loop {
+                        if self.starts_with("*/") {
+                            self.advance_by(2);
+                            break;
+                        }
+                        if self.advance().is_none() {
+                            return Err(FilterParseError::new(start, "unterminated comment"));
+                        }
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Token, FilterParseError> {
+        let start = self.position();
+        self.advance(); // opening quote
+
+        let mut value = String::new();
+        loop {
+            let escape_position = self.position();
+            match self.advance() {
+                None|Some('\n') => return Err(FilterParseError::new(start, "unterminated string")),
+                Some('"') => return Ok(Token::String(value)),
+                Some('\\') => {
+                    let escaped = match self.advance() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('b') => '\u{08}',
+                        Some('f') => '\u{0C}',
+                        Some(other) => return Err(FilterParseError::new(
+                            escape_position,
+                            format!("invalid escape sequence `\\{}`", other),
+                        )),
+                        None => return Err(FilterParseError::new(start, "unterminated string")),
+                    };
+                    value.push(escaped);
+                },
+                Some(other) => value.push(other),
+            }
+        }
+    }
+
+    fn read_multiline_string(&mut self) -> Result<Token, FilterParseError> {
+        let start = self.position();
+        self.advance_by(3); // {{{
+
+        let mut value = String::new();
+        loop {
+            if self.starts_with("}}}") {
+                self.advance_by(3);
+                return Ok(Token::String(value));
+            }
+            match self.advance() {
+                Some(c) => value.push(c),
+                None => return Err(FilterParseError::new(start, "unterminated multi-line string")),
+            }
+        }
+    }
+
+    fn read_number(&mut self) -> Result<Token, FilterParseError> {
+        let start = self.position();
+        let mut value = String::new();
+        while let Some(c) = self.peek_char(0) {
+            if c.is_ascii_digit() {
+                value.push(c);
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        if self.peek_char(0) == Some('.') && self.peek_char(1).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            value.push('.');
+            self.advance();
+            while let Some(c) = self.peek_char(0) {
+                if c.is_ascii_digit() {
+                    value.push(c);
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        // duration suffix?
+        for suffix in ["ms", "s", "m", "h", "d"] {
+            if self.starts_with(suffix) {
+                let after = self.peek_char(suffix.len());
+                if !after.map(is_identifier_char).unwrap_or(false) {
+                    value.push_str(suffix);
+                    self.advance_by(suffix.len());
+                    break;
+                }
+            }
+        }
+
+        if self.peek_char(0).map(is_identifier_char).unwrap_or(false) {
+            return Err(FilterParseError::new(start, format!("invalid number `{}{}`", value, self.peek_char(0).unwrap())));
+        }
+        Ok(Token::Number(value))
+    }
+
+    fn read_identifier(&mut self) -> Token {
+        let mut value = String::new();
+        while let Some(c) = self.peek_char(0) {
+            if is_identifier_char(c) {
+                value.push(c);
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        match value.as_str() {
+            "null" => Token::Null,
+            "true" => Token::True,
+            "false" => Token::False,
+            "in" => Token::In,
+            _ => Token::Identifier(value),
+        }
+    }
+
+    /// Returns the next token and its position, or `None` at the end of the text.
+    fn next_token(&mut self) -> Result<Option<(Token, Position)>, FilterParseError> {
+        self.skip_whitespace_and_comments()?;
+        let position = self.position();
+        let c = match self.peek_char(0) {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let token = if c == '"' {
+            self.read_string()?
+        } else if self.starts_with("{{{") {
+            self.read_multiline_string()?
+        } else if c.is_ascii_digit() {
+            self.read_number()?
+        } else if is_identifier_start_char(c) {
+            self.read_identifier()
+        } else if self.starts_with("!in") && !self.peek_char(3).map(is_identifier_char).unwrap_or(false) {
+            self.advance_by(3);
+            Token::NotIn
+        } else if let Some(forbidden) = FORBIDDEN_SYMBOLS.iter().find(|s| self.starts_with(s) && !self.starts_with("==")) {
+            return Err(FilterParseError::new(
+                position,
+                format!("`{}` is not allowed in filters (did you mean `==`?)", forbidden),
+            ));
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| self.starts_with(s)) {
+            self.advance_by(symbol.chars().count());
+            Token::Symbol(symbol)
+        } else {
+            return Err(FilterParseError::new(position, format!("unexpected character `{}`", c)));
+        };
+        Ok(Some((token, position)))
+    }
+}
+
+fn is_identifier_start_char(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+
+/// Builds an abstract syntax tree from a sequence of tokens.
+struct Parser {
+    tokens: Vec<(Token, Position)>,
+    index: usize,
+    end_position: Position,
+}
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(t, _p)| t)
+    }
+
+    fn position(&self) -> Position {
+        self.tokens.get(self.index)
+            .map(|(_t, p)| *p)
+            .unwrap_or(self.end_position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).map(|(t, _p)| t.clone());
+        if token.is_some() {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self) -> FilterParseError {
+        match self.peek() {
+            Some(token) => FilterParseError::new(self.position(), format!("unexpected {}", token)),
+            None => FilterParseError::new(self.position(), "unexpected end of filter"),
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<(), FilterParseError> {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.advance();
+            Ok(())
+        } else {
+            match self.peek() {
+                Some(token) => Err(FilterParseError::new(self.position(), format!("expected `{}`, found {}", symbol, token))),
+                None => Err(FilterParseError::new(self.position(), format!("expected `{}`, found end of filter", symbol))),
+            }
+        }
+    }
+
+    fn peek_binary_operator(&self) -> Option<BinaryOperator> {
+        let op = match self.peek()? {
+            Token::In => BinaryOperator::In,
+            Token::NotIn => BinaryOperator::NotIn,
+            Token::Symbol(s) => match *s {
+                "||" => BinaryOperator::LogicalOr,
+                "&&" => BinaryOperator::LogicalAnd,
+                "|" => BinaryOperator::BitwiseOr,
+                "^" => BinaryOperator::BitwiseXor,
+                "&" => BinaryOperator::BitwiseAnd,
+                "==" => BinaryOperator::Equal,
+                "!=" => BinaryOperator::NotEqual,
+                "<" => BinaryOperator::Less,
+                ">" => BinaryOperator::Greater,
+                "<=" => BinaryOperator::LessEqual,
+                ">=" => BinaryOperator::GreaterEqual,
+                "<<" => BinaryOperator::ShiftLeft,
+                ">>" => BinaryOperator::ShiftRight,
+                "+" => BinaryOperator::Add,
+                "-" => BinaryOperator::Subtract,
+                "*" => BinaryOperator::Multiply,
+                "/" => BinaryOperator::Divide,
+                "%" => BinaryOperator::Modulo,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Parses a binary expression whose operators have at least the given precedence.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expression, FilterParseError> {
+        let mut left = self.parse_unary()?;
+        let mut previous_operator: Option<BinaryOperator> = None;
+        while let Some(operator) = self.peek_binary_operator() {
+            if operator.precedence() < min_precedence {
+                break;
+            }
+            if let Some(previous) = previous_operator {
+                if !operator.is_associative() && previous.precedence() == operator.precedence() {
+                    return Err(FilterParseError::new(
+                        self.position(),
+                        format!("`{}` cannot be chained with `{}`; use parentheses", operator.symbol(), previous.symbol()),
+                    ));
+                }
+            }
+            previous_operator = Some(operator);
+            self.advance();
+            let right = self.parse_binary(operator.precedence() + 1)?;
+            left = Expression::Binary {
+                operator,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, FilterParseError> {
+        let operator = match self.peek() {
+            Some(Token::Symbol("!")) => UnaryOperator::LogicalNot,
+            Some(Token::Symbol("~")) => UnaryOperator::BitwiseNot,
+            Some(Token::Symbol("+")) => UnaryOperator::Plus,
+            Some(Token::Symbol("-")) => UnaryOperator::Minus,
+            _ => return self.parse_postfix(),
+        };
+        self.advance();
+        let operand = self.parse_unary()?;
+        Ok(Expression::Unary {
+            operator,
+            operand: Box::new(operand),
+        })
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expression, FilterParseError> {
+        let mut expression = self.parse_atom()?;
+        loop {
+            match self.peek() {
+                Some(Token::Symbol(".")) => {
+                    self.advance();
+                    let member = match self.peek() {
+                        Some(Token::Identifier(i)) => i.clone(),
+                        // keywords are valid member names (e.g. host.vars.in)
+                        Some(Token::Null) => "null".to_owned(),
+                        Some(Token::True) => "true".to_owned(),
+                        Some(Token::False) => "false".to_owned(),
+                        Some(Token::In) => "in".to_owned(),
+                        _ => return Err(FilterParseError::new(self.position(), "expected attribute name after `.`")),
+                    };
+                    self.advance();
+                    expression = Expression::Member {
+                        object: Box::new(expression),
+                        member,
+                    };
+                },
+                Some(Token::Symbol("[")) => {
+                    self.advance();
+                    let index = self.parse_binary(1)?;
+                    self.expect_symbol("]")?;
+                    expression = Expression::Index {
+                        object: Box::new(expression),
+                        index: Box::new(index),
+                    };
+                },
+                Some(Token::Symbol("(")) => {
+                    self.advance();
+                    let arguments = self.parse_list(")")?;
+                    expression = Expression::Call {
+                        function: Box::new(expression),
+                        arguments,
+                    };
+                },
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    /// Parses a comma-separated list of expressions up to and including the given closing symbol.
+    fn parse_list(&mut self, closing: &'static str) -> Result<Vec<Expression>, FilterParseError> {
+        let mut items = Vec::new();
+        if self.peek() == Some(&Token::Symbol(closing)) {
+            self.advance();
+            return Ok(items);
+        }
+        loop {
+            items.push(self.parse_binary(1)?);
+            if self.peek() == Some(&Token::Symbol(",")) {
+                self.advance();
+                // allow trailing comma
+                if self.peek() == Some(&Token::Symbol(closing)) {
+                    self.advance();
+                    return Ok(items);
+                }
+            } else {
+                self.expect_symbol(closing)?;
+                return Ok(items);
+            }
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Expression, FilterParseError> {
+        let expression = match self.peek() {
+            Some(Token::Null) => Expression::Literal(Literal::Null),
+            Some(Token::True) => Expression::Literal(Literal::Boolean(true)),
+            Some(Token::False) => Expression::Literal(Literal::Boolean(false)),
+            Some(Token::Number(n)) => Expression::Literal(Literal::Number(n.clone())),
+            Some(Token::String(s)) => Expression::Literal(Literal::String(s.clone())),
+            Some(Token::Identifier(i)) => Expression::Variable(i.clone()),
+            Some(Token::Symbol("(")) => {
+                self.advance();
+                let inner = self.parse_binary(1)?;
+                self.expect_symbol(")")?;
+                return Ok(inner);
+            },
+            Some(Token::Symbol("[")) => {
+                self.advance();
+                let items = self.parse_list("]")?;
+                return Ok(Expression::Array(items));
+            },
+            _ => return Err(self.unexpected()),
+        };
+        self.advance();
+        Ok(expression)
+    }
+}
+
+
+/// Parses a filter expression into an abstract syntax tree.
+pub(crate) fn parse(filter: &str) -> Result<Expression, FilterParseError> {
+    let mut tokenizer = Tokenizer::new(filter);
+    let mut tokens = Vec::new();
+    while let Some(token) = tokenizer.next_token()? {
+        tokens.push(token);
+    }
+
+    let mut parser = Parser {
+        tokens,
+        index: 0,
+        end_position: tokenizer.position(),
+    };
+    if parser.peek().is_none() {
+        return Err(parser.unexpected());
+    }
+    let expression = parser.parse_binary(1)?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+    Ok(expression)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Expression {
+        Expression::Variable(name.to_owned())
+    }
+
+    fn string(value: &str) -> Expression {
+        Expression::Literal(Literal::String(value.to_owned()))
+    }
+
+    fn member(object: Expression, name: &str) -> Expression {
+        Expression::Member { object: Box::new(object), member: name.to_owned() }
+    }
+
+    fn binary(operator: BinaryOperator, left: Expression, right: Expression) -> Expression {
+        Expression::Binary { operator, left: Box::new(left), right: Box::new(right) }
+    }
+
+    fn call(function: &str, arguments: Vec<Expression>) -> Expression {
+        Expression::Call { function: Box::new(var(function)), arguments }
+    }
+
+    fn assert_round_trip(filter: &str) {
+        let parsed = parse(filter).unwrap();
+        let printed = parsed.to_string();
+        let reparsed = parse(&printed)
+            .unwrap_or_else(|e| panic!("failed to reparse {:?} (printed from {:?}): {}", printed, filter, e));
+        assert_eq!(parsed, reparsed, "{:?} printed as {:?}", filter, printed);
+    }
+
+    fn assert_error_at(filter: &str, line: usize, column: usize) {
+        let error = parse(filter).unwrap_err();
+        assert_eq!((error.line, error.column), (line, column), "{:?}: {}", filter, error);
+    }
+
+    #[test]
+    fn test_precedence() {
+        use BinaryOperator::*;
+
+        assert_eq!(
+            parse("a || b && c").unwrap(),
+            binary(LogicalOr, var("a"), binary(LogicalAnd, var("b"), var("c"))),
+        );
+        assert_eq!(
+            parse("a && b || c").unwrap(),
+            binary(LogicalOr, binary(LogicalAnd, var("a"), var("b")), var("c")),
+        );
+        assert_eq!(
+            parse("a == 1 && b != 2").unwrap(),
+            binary(
+                LogicalAnd,
+                binary(Equal, var("a"), Expression::Literal(Literal::Number("1".to_owned()))),
+                binary(NotEqual, var("b"), Expression::Literal(Literal::Number("2".to_owned()))),
+            ),
+        );
+        assert_eq!(
+            parse("a - b - c").unwrap(),
+            binary(Subtract, binary(Subtract, var("a"), var("b")), var("c")),
+        );
+        assert_eq!(
+            parse("!a && b").unwrap(),
+            binary(
+                LogicalAnd,
+                Expression::Unary { operator: UnaryOperator::LogicalNot, operand: Box::new(var("a")) },
+                var("b"),
+            ),
+        );
+        assert_eq!(
+            parse("-host.vars.x").unwrap(),
+            Expression::Unary {
+                operator: UnaryOperator::Minus,
+                operand: Box::new(member(member(var("host"), "vars"), "x")),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parenthesization() {
+        use BinaryOperator::*;
+
+        assert_eq!(
+            parse("(a || b) && c").unwrap(),
+            binary(LogicalAnd, binary(LogicalOr, var("a"), var("b")), var("c")),
+        );
+        assert_eq!(
+            parse("a - (b - c)").unwrap(),
+            binary(Subtract, var("a"), binary(Subtract, var("b"), var("c"))),
+        );
+        assert_eq!(parse("((a))").unwrap(), var("a"));
+
+        assert_eq!(parse("(a || b) && c").unwrap().to_string(), "(a || b) && c");
+        assert_eq!(parse("a - (b - c)").unwrap().to_string(), "a - (b - c)");
+        assert_eq!(parse("(a - b) - c").unwrap().to_string(), "a - b - c");
+        assert_eq!(parse("!(a && b)").unwrap().to_string(), "!(a && b)");
+        assert_eq!(parse("(a == b) == c").unwrap().to_string(), "(a == b) == c");
+        assert_eq!(parse("(-a).b").unwrap().to_string(), "(-a).b");
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for filter in [
+            "host.name == \"web01\"",
+            "a || b && c",
+            "(a || b) && c",
+            "a - (b - c) * d % 2",
+            "!(a && b) || ~c",
+            "(a == b) != (c == d)",
+            "(a == b) == c",
+            "a == (b == c)",
+            "1 + 2 < 4 && 5m > 30s",
+            "host.vars[\"os\"] == \"Linux\"",
+            "[1, 2, \"three\",] == x",
+            "match(\"web*\", host.name) && !regex(\"^db[0-9]+$\", host.name)",
+            "\"linux-servers\" in host.groups || \"windows-servers\" !in host.groups",
+            "get_object(Host, \"x\").vars.in == null",
+            "{{{multi\nline \"quoted\" \\text}}} == service.vars.note",
+            "\"tab\\there\\nnewline\\rcarriage\" == x",
+        ] {
+            assert_round_trip(filter);
+        }
+    }
+
+    #[test]
+    fn test_quote_string() {
+        for value in ["plain", "with \"quotes\"", "back\\slash", "C:\\temp\\\"x\"", "\\\"", "line\nbreak\ttab\r", ""] {
+            let quoted = quote_string(value);
+            assert_eq!(parse(&quoted).unwrap(), string(value), "{:?} quoted as {:?}", value, quoted);
+        }
+    }
+
+    #[test]
+    fn test_quote_icinga_filter_escapes() {
+        // quoteIcingaFilter in script.ts escapes only backslashes and double quotes
+        assert_eq!(parse(r#""a\\b""#).unwrap(), string("a\\b"));
+        assert_eq!(parse(r#""say \"hi\"""#).unwrap(), string("say \"hi\""));
+        assert_eq!(parse(r#""\\\"""#).unwrap(), string("\\\""));
+        assert_eq!(parse(r#""ends with backslash\\""#).unwrap(), string("ends with backslash\\"));
+        assert_eq!(
+            parse(r#"host.name == "x\" || true || \"y""#).unwrap(),
+            binary(BinaryOperator::Equal, member(var("host"), "name"), string("x\" || true || \"y")),
+        );
+
+        // a literal newline passes through quoteIcingaFilter unescaped but is not allowed in a string
+        assert_error_at("\"a\nb\"", 1, 1);
+    }
+
+    #[test]
+    fn test_match_regex_in_and_calls() {
+        use BinaryOperator::*;
+
+        assert_eq!(
+            parse("match(\"web*\", host.name)").unwrap(),
+            call("match", vec![string("web*"), member(var("host"), "name")]),
+        );
+        assert_eq!(
+            parse("regex(\"^db[0-9]+$\", service.host_name, MatchAny)").unwrap(),
+            call("regex", vec![string("^db[0-9]+$"), member(var("service"), "host_name"), var("MatchAny")]),
+        );
+        assert_eq!(parse("len()").unwrap(), call("len", Vec::new()));
+        assert_eq!(
+            parse("\"linux\" in host.groups").unwrap(),
+            binary(In, string("linux"), member(var("host"), "groups")),
+        );
+        assert_eq!(
+            parse("\"linux\" !in host.groups").unwrap(),
+            binary(NotIn, string("linux"), member(var("host"), "groups")),
+        );
+        assert_eq!(
+            parse("x in [1, 2]").unwrap(),
+            binary(
+                In,
+                var("x"),
+                Expression::Array(vec![
+                    Expression::Literal(Literal::Number("1".to_owned())),
+                    Expression::Literal(Literal::Number("2".to_owned())),
+                ]),
+            ),
+        );
+        // `!inside` is a negated variable, not `!in` followed by `side`
+        assert_eq!(
+            parse("!inside").unwrap(),
+            Expression::Unary { operator: UnaryOperator::LogicalNot, operand: Box::new(var("inside")) },
+        );
+        assert_eq!(
+            parse("host.vars.disks[\"/\"].free").unwrap(),
+            member(
+                Expression::Index {
+                    object: Box::new(member(member(var("host"), "vars"), "disks")),
+                    index: Box::new(string("/")),
+                },
+                "free",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_chained_equality_rejected() {
+        assert_error_at("a == b == c", 1, 8);
+        assert_error_at("a != b != c", 1, 8);
+        assert_error_at("a == b != c", 1, 8);
+        assert_error_at("x && a != b == c", 1, 13);
+
+        assert!(parse("a == b && b == c").is_ok());
+        assert!(parse("(a == b) == c").is_ok());
+        assert!(parse("a == (b != c)").is_ok());
+        assert!(parse("a < b == c < d").is_ok());
+    }
+
+    #[test]
+    fn test_error_positions() {
+        assert_error_at("", 1, 1);
+        assert_error_at("host.name ==", 1, 13);
+        assert_error_at("host.name = \"x\"", 1, 11);
+        assert_error_at("a &&\n  b += 1", 2, 5);
+        assert_error_at("a && (b || c", 1, 13);
+        assert_error_at("a b", 1, 3);
+        assert_error_at("x == \"unterminated", 1, 6);
+        assert_error_at("x == \"bad \\q escape\"", 1, 11);
+        assert_error_at("x == 12abc", 1, 6);
+        assert_error_at("x /* never closed", 1, 3);
+        assert_error_at("host.", 1, 6);
+        assert_error_at("a $ b", 1, 3);
+        assert_error_at("f(a,, b)", 1, 5);
+
+        let error = parse("a &&\n  b += 1").unwrap_err();
+        assert_eq!(error.source_line("a &&\n  b += 1"), "  b += 1");
+        assert_eq!(error.marker(), "    ^");
+    }
+}
@@ -0,0 +1,615 @@
+//! Recording snapshots of reports in an SQLite database and viewing them later.
+
+
+use std::convert::Infallible;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use askama::Template;
+use chrono::{DateTime, Local, TimeZone, Utc};
+use hyper::{Body, Request, Response};
+use once_cell::sync::Lazy;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use rusqlite::{Connection, OptionalExtension, params};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    auth, handle_404, handle_plaintext_response, NagiosState, return_500, RowPart, TableQuery,
+    TableQueryError, TableTemplate,
+};
+use crate::columns::CellValue;
+use crate::config::{CONFIG, HistoryConfig, ReportConfig};
+use crate::icinga::{self, InstanceError};
+
+
+/// How long to wait before checking again whether snapshots are to be recorded if history is not
+/// configured.
+const UNCONFIGURED_SLEEP: Duration = Duration::from_secs(60);
+
+/// The statements creating the database schema.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS snapshots (
+        id INTEGER PRIMARY KEY,
+        report TEXT NOT NULL,
+        taken_at INTEGER NOT NULL,
+        objtype TEXT NOT NULL,
+        filter TEXT NOT NULL,
+        columns TEXT NOT NULL,
+        row_count INTEGER NOT NULL,
+        instance_errors TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS snapshots_report_taken_at ON snapshots (report, taken_at);
+    CREATE TABLE IF NOT EXISTS snapshot_rows (
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        instance TEXT NOT NULL,
+        name TEXT NOT NULL,
+        host TEXT NOT NULL,
+        service TEXT NOT NULL,
+        output TEXT NOT NULL,
+        state INTEGER NOT NULL,
+        cells TEXT NOT NULL,
+        PRIMARY KEY (snapshot_id, position)
+    );
+    CREATE TABLE IF NOT EXISTS state_changes (
+        instance TEXT NOT NULL,
+        host TEXT NOT NULL,
+        service TEXT NOT NULL,
+        changed_at INTEGER NOT NULL,
+        state INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS state_changes_object ON state_changes (instance, host, service, changed_at);
+    CREATE TABLE IF NOT EXISTS downtimes (
+        instance TEXT NOT NULL,
+        host TEXT NOT NULL,
+        service TEXT NOT NULL,
+        name TEXT NOT NULL,
+        started_at INTEGER NOT NULL,
+        ended_at INTEGER,
+        UNIQUE (instance, name)
+    );
+    CREATE INDEX IF NOT EXISTS downtimes_object ON downtimes (instance, host, service, started_at);
+";
+
+
+/// The open history database along with its path, so that it is reopened if the path is changed by
+/// reloading the configuration.
+static DATABASE: Lazy<Mutex<Option<(PathBuf, Connection)>>> = Lazy::new(|| Mutex::new(None));
+
+
+/// An error that may occur when accessing the history database.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum HistoryError {
+    /// History is not configured.
+    NotConfigured,
+
+    /// The database could not be accessed.
+    #[non_exhaustive] Database { error: rusqlite::Error },
+
+    /// A snapshot could not be encoded or decoded.
+    #[non_exhaustive] Json { error: serde_json::Error },
+
+    /// A stored snapshot cannot be interpreted.
+    #[non_exhaustive] InvalidSnapshot { id: i64, description: String },
+}
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConfigured
+                => write!(f, "history is not configured"),
+            Self::Database { error, .. }
+                => write!(f, "history database error: {}", error),
+            Self::Json { error, .. }
+                => write!(f, "failed to encode or decode snapshot: {}", error),
+            Self::InvalidSnapshot { id, description, .. }
+                => write!(f, "invalid snapshot {}: {}", id, description),
+        }
+    }
+}
+impl std::error::Error for HistoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::NotConfigured => None,
+            Self::Database { error, .. } => Some(error),
+            Self::Json { error, .. } => Some(error),
+            Self::InvalidSnapshot { .. } => None,
+        }
+    }
+}
+impl From<rusqlite::Error> for HistoryError {
+    fn from(error: rusqlite::Error) -> Self { Self::Database { error } }
+}
+impl From<serde_json::Error> for HistoryError {
+    fn from(error: serde_json::Error) -> Self { Self::Json { error } }
+}
+
+
+/// Summary information about a stored snapshot.
+#[derive(Clone, Debug)]
+pub(crate) struct SnapshotInfo {
+    pub id: i64,
+    pub taken_at: DateTime<Utc>,
+    pub row_count: i64,
+    pub error_count: usize,
+}
+
+
+/// A stored snapshot of a report.
+#[derive(Clone, Debug)]
+pub(crate) struct Snapshot {
+    pub id: i64,
+    pub taken_at: DateTime<Utc>,
+    pub objtype: String,
+    pub filter: String,
+
+    /// The comma-separated paths of the columns.
+    pub columns: String,
+
+    pub rows: Vec<RowPart>,
+
+    /// The instances that could not be queried and the error messages.
+    pub instance_errors: Vec<(String, String)>,
+}
+impl Snapshot {
+    /// Reassembles the query that produced this snapshot, e.g. to obtain its columns.
+    pub fn query(&self) -> Result<TableQuery, HistoryError> {
+        TableQuery::parse(&self.objtype, &self.filter, Some(&self.columns), None)
+            .map_err(|e| {
+                let description = match e {
+                    TableQueryError::InvalidParameter { name, value }
+                        => format!("invalid {} {:?}", name, value),
+                    TableQueryError::InvalidFilter { error }
+                        => format!("invalid filter {:?}: {}", self.filter, error),
+                };
+                HistoryError::InvalidSnapshot { id: self.id, description }
+            })
+    }
+}
+
+
+#[derive(Template)]
+#[template(path = "history.html")]
+struct HistoryTemplate {
+    pub title: String,
+    pub snapshots: Vec<SnapshotLink>,
+}
+
+#[derive(Clone, Debug)]
+struct SnapshotLink {
+    pub url: String,
+    pub compare_live_url: String,
+    pub compare_previous_url: Option<String>,
+    pub taken_at: String,
+    pub row_count: i64,
+    pub error_count: usize,
+}
+
+
+/// Runs the given function on the history database at the given path, opening it (and creating its
+/// schema) if necessary.
+///
+/// The function runs on a thread where blocking is allowed.
+pub(crate) async fn with_database<T, F>(path: PathBuf, f: F) -> Result<T, HistoryError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, HistoryError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let mut database_guard = DATABASE.lock().expect("history database poisoned");
+        let is_open = database_guard.as_ref()
+            .map(|(open_path, _connection)| open_path == &path)
+            .unwrap_or(false);
+        if !is_open {
+            *database_guard = None;
+            let connection = open_database(&path)?;
+            *database_guard = Some((path, connection));
+        }
+        let (_path, connection) = database_guard.as_mut().expect("history database not open?!");
+        f(connection)
+    }).await
+        .expect("history database task panicked")
+}
+
+/// Opens the history database at the given path and ensures that its schema exists.
+fn open_database(path: &Path) -> Result<Connection, HistoryError> {
+    let connection = Connection::open(path)?;
+    connection.pragma_update(None, "foreign_keys", true)?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+}
+
+/// Returns the path to the history database if history is configured.
+pub(crate) async fn database_path() -> Result<PathBuf, HistoryError> {
+    let config_guard = CONFIG
+        .get().expect("CONFIG not set?!")
+        .read().await;
+    config_guard.history
+        .as_ref()
+        .map(|h| h.database_path.clone())
+        .ok_or(HistoryError::NotConfigured)
+}
+
+/// Converts a UNIX timestamp stored in the database.
+fn timestamp_from_db(id: i64, seconds: i64) -> Result<DateTime<Utc>, HistoryError> {
+    Utc.timestamp_opt(seconds, 0)
+        .single()
+        .ok_or_else(|| HistoryError::InvalidSnapshot { id, description: format!("invalid timestamp {}", seconds) })
+}
+
+
+/// Stores a snapshot of a report, returning its ID.
+pub(crate) async fn store_snapshot(
+    report: &str,
+    query: &TableQuery,
+    taken_at: DateTime<Utc>,
+    rows: &[RowPart],
+    errors: &[InstanceError],
+) -> Result<i64, HistoryError> {
+    let path = database_path().await?;
+
+    let report = report.to_owned();
+    let objtype = query.object_type.plural;
+    let filter = query.filter.clone();
+    let columns = query.columns.iter()
+        .map(|c| c.path.as_str())
+        .collect::<Vec<&str>>()
+        .join(",");
+    let instance_errors: Vec<(String, String)> = errors.iter()
+        .map(|ie| (ie.instance.clone(), ie.error.to_string()))
+        .collect();
+    let instance_errors = serde_json::to_string(&instance_errors)?;
+    let mut encoded_rows = Vec::with_capacity(rows.len());
+    for row in rows {
+        let cells: Vec<serde_json::Value> = row.cells.iter()
+            .map(|c| c.to_stored_json())
+            .collect();
+        encoded_rows.push((row.clone(), serde_json::to_string(&cells)?));
+    }
+
+    with_database(path, move |connection| {
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO snapshots (report, taken_at, objtype, filter, columns, row_count, instance_errors) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![report, taken_at.timestamp(), objtype, filter, columns, encoded_rows.len() as i64, instance_errors],
+        )?;
+        let id = transaction.last_insert_rowid();
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO snapshot_rows (snapshot_id, position, instance, name, host, service, output, state, cells) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for (position, (row, cells)) in encoded_rows.iter().enumerate() {
+                statement.execute(params![
+                    id, position as i64, row.instance, row.name, row.host, row.service, row.output,
+                    row.state.to_base_type(), cells,
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(id)
+    }).await
+}
+
+/// Lists the snapshots of a report, newest first.
+pub(crate) async fn list_snapshots(report: &str) -> Result<Vec<SnapshotInfo>, HistoryError> {
+    let path = database_path().await?;
+    let report = report.to_owned();
+    with_database(path, move |connection| {
+        let mut statement = connection.prepare(
+            "SELECT id, taken_at, row_count, instance_errors FROM snapshots WHERE report = ?1 ORDER BY taken_at DESC, id DESC",
+        )?;
+        let raw_snapshots = statement
+            .query_map(params![report], |r| Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, i64>(1)?,
+                r.get::<_, i64>(2)?,
+                r.get::<_, String>(3)?,
+            )))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut snapshots = Vec::with_capacity(raw_snapshots.len());
+        for (id, taken_at, row_count, instance_errors) in raw_snapshots {
+            let instance_errors: Vec<(String, String)> = serde_json::from_str(&instance_errors)?;
+            snapshots.push(SnapshotInfo {
+                id,
+                taken_at: timestamp_from_db(id, taken_at)?,
+                row_count,
+                error_count: instance_errors.len(),
+            });
+        }
+        Ok(snapshots)
+    }).await
+}
+
+/// Loads a snapshot of a report, if it exists.
+pub(crate) async fn load_snapshot(report: &str, id: i64) -> Result<Option<Snapshot>, HistoryError> {
+    let path = database_path().await?;
+    let report = report.to_owned();
+    with_database(path, move |connection| {
+        let header = connection
+            .query_row(
+                "SELECT taken_at, objtype, filter, columns, instance_errors FROM snapshots WHERE report = ?1 AND id = ?2",
+                params![report, id],
+                |r| Ok((
+                    r.get::<_, i64>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, String>(2)?,
+                    r.get::<_, String>(3)?,
+                    r.get::<_, String>(4)?,
+                )),
+            )
+            .optional()?;
+        let (taken_at, objtype, filter, columns, instance_errors) = match header {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        let mut statement = connection.prepare(
+            "SELECT instance, name, host, service, output, state, cells FROM snapshot_rows WHERE snapshot_id = ?1 ORDER BY position",
+        )?;
+        let raw_rows = statement
+            .query_map(params![id], |r| Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, String>(1)?,
+                r.get::<_, String>(2)?,
+                r.get::<_, String>(3)?,
+                r.get::<_, String>(4)?,
+                r.get::<_, u8>(5)?,
+                r.get::<_, String>(6)?,
+            )))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut rows = Vec::with_capacity(raw_rows.len());
+        for (instance, name, host, service, output, state, cells) in raw_rows {
+            let cells: Vec<serde_json::Value> = serde_json::from_str(&cells)?;
+            let cells = cells.iter()
+                .map(CellValue::from_stored_json)
+                .collect::<Option<Vec<CellValue>>>()
+                .ok_or_else(|| HistoryError::InvalidSnapshot { id, description: format!("invalid cells in row {:?}", name) })?;
+            rows.push(RowPart {
+                instance,
+                name,
+                host,
+                service,
+                output,
+                state: NagiosState::from(state),
+                cells,
+            });
+        }
+
+        Ok(Some(Snapshot {
+            id,
+            taken_at: timestamp_from_db(id, taken_at)?,
+            objtype,
+            filter,
+            columns,
+            rows,
+            instance_errors: serde_json::from_str(&instance_errors)?,
+        }))
+    }).await
+}
+
+/// Deletes the snapshots taken before the given time, returning how many were deleted.
+async fn delete_snapshots_before(before: DateTime<Utc>) -> Result<usize, HistoryError> {
+    let path = database_path().await?;
+    with_database(path, move |connection| {
+        let deleted = connection.execute(
+            "DELETE FROM snapshots WHERE taken_at < ?1",
+            params![before.timestamp()],
+        )?;
+        Ok(deleted)
+    }).await
+}
+
+
+/// Queries a report and stores the result as a snapshot.
+///
+/// Snapshots are taken with the Icinga credentials configured for each instance and without role
+/// restrictions. If only some of the instances can be queried, the snapshot is stored along with
+/// the errors.
+async fn take_snapshot(report: &ReportConfig) -> Result<(), String> {
+    let mut query = TableQuery::from_report(report)?;
+    if icinga::instance_names().await.len() > 1 {
+        query.add_instance_column();
+    }
+
+    let query_result = icinga::fetch_all_rows(&query, false).await
+        .map_err(|InstanceError { instance, error }| format!("failed to query Icinga instance {:?}: {}", instance, error))?;
+    for instance_error in &query_result.errors {
+        warn!(
+            "snapshot of report {:?}: failed to query {} from Icinga instance {:?}: {}",
+            report.name, query.object_type.plural, instance_error.instance, instance_error.error,
+        );
+    }
+
+    let id = store_snapshot(&report.name, &query, query_result.oldest_fetched_at, &query_result.rows, &query_result.errors).await
+        .map_err(|e| e.to_string())?;
+    debug!("stored snapshot {} of report {:?}", id, report.name);
+    Ok(())
+}
+
+/// Regularly records snapshots of the reports with history enabled and deletes those that have
+/// exceeded the retention period.
+///
+/// The configuration is read anew before each round, so reloading it takes effect without a
+/// restart.
+pub(crate) async fn record_snapshots() {
+    loop {
+        let (history_config, reports): (Option<HistoryConfig>, Vec<ReportConfig>) = {
+            let config_guard = CONFIG
+                .get().expect("CONFIG not set?!")
+                .read().await;
+            let reports = config_guard.reports
+                .iter()
+                .filter(|r| r.history)
+                .cloned()
+                .collect();
+            (config_guard.history.clone(), reports)
+        };
+        let history_config = match history_config {
+            Some(hc) => hc,
+            None => {
+                tokio::time::sleep(UNCONFIGURED_SLEEP).await;
+                continue;
+            },
+        };
+
+        for report in &reports {
+            if let Err(e) = take_snapshot(report).await {
+                error!("failed to take snapshot of report {:?}: {}", report.name, e);
+            }
+        }
+
+        if let Some(retention_days) = history_config.retention_days {
+            let retention = chrono::Duration::days(retention_days.min(i64::MAX as u64 / (24 * 60 * 60 * 1000)) as i64);
+            match delete_snapshots_before(Utc::now() - retention).await {
+                Ok(0) => {},
+                Ok(deleted) => info!("deleted {} snapshots older than {} days", deleted, retention_days),
+                Err(e) => error!("failed to delete old snapshots: {}", e),
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(history_config.snapshot_interval_s.max(1))).await;
+    }
+}
+
+
+/// Determines whether the user may view snapshots.
+///
+/// Since snapshots are taken without restrictions, only users who may see all objects may view
+/// them.
+pub(crate) async fn check_history_access(request: &Request<Body>) -> Result<(), Result<Response<Body>, Infallible>> {
+    let permissions = auth::get_permissions(request).await?;
+    if permissions.restriction.is_some() || permissions.icinga_credentials.is_some() {
+        return Err(handle_plaintext_response(403, "403 Forbidden").await);
+    }
+    Ok(())
+}
+
+/// Returns the title of the report with the given name; if it is no longer configured, its name.
+pub(crate) async fn report_title(report_name: &str) -> String {
+    let config_guard = CONFIG
+        .get().expect("CONFIG not set?!")
+        .read().await;
+    config_guard.reports
+        .iter()
+        .find(|r| r.name == report_name)
+        .map(|r| r.title.clone().unwrap_or_else(|| r.name.clone()))
+        .unwrap_or_else(|| report_name.to_owned())
+}
+
+/// Lists the snapshots of a report.
+pub(crate) async fn handle_history(request: Request<Body>, report_name: &str) -> Result<Response<Body>, Infallible> {
+    if let Err(resp) = check_history_access(&request).await {
+        return resp;
+    }
+
+    let snapshots = match list_snapshots(report_name).await {
+        Ok(s) => s,
+        Err(HistoryError::NotConfigured) => return handle_404(request).await,
+        Err(e) => {
+            error!("failed to list snapshots of report {:?}: {}", report_name, e);
+            return return_500();
+        },
+    };
+
+    // snapshots are listed newest first, so the previous snapshot is the next one in the list
+    let encoded_name = utf8_percent_encode(report_name, NON_ALPHANUMERIC).to_string();
+    let previous_ids = snapshots.iter()
+        .skip(1)
+        .map(|s| Some(s.id))
+        .chain(std::iter::once(None));
+    let template = HistoryTemplate {
+        title: report_title(report_name).await,
+        snapshots: snapshots.iter()
+            .zip(previous_ids)
+            .map(|(s, previous_id)| SnapshotLink {
+                url: format!("{}/{}", encoded_name, s.id),
+                compare_live_url: format!("../compare/{}?from={}", encoded_name, s.id),
+                compare_previous_url: previous_id
+                    .map(|pi| format!("../compare/{}?from={}&to={}", encoded_name, pi, s.id)),
+                taken_at: s.taken_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+                row_count: s.row_count,
+                error_count: s.error_count,
+            })
+            .collect(),
+    };
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render history template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
+
+/// Loads the snapshot with the given ID (as passed in the path) of a report, responding with 404
+/// if it does not exist.
+pub(crate) async fn get_snapshot(report_name: &str, id: &str) -> Result<Snapshot, Result<Response<Body>, Infallible>> {
+    let id: i64 = match id.parse() {
+        Ok(i) => i,
+        Err(_) => return Err(handle_plaintext_response(404, "404 Not Found").await),
+    };
+    match load_snapshot(report_name, id).await {
+        Ok(Some(s)) => Ok(s),
+        Ok(None) | Err(HistoryError::NotConfigured) => Err(handle_plaintext_response(404, "404 Not Found").await),
+        Err(e) => {
+            error!("failed to load snapshot {} of report {:?}: {}", id, report_name, e);
+            Err(return_500())
+        },
+    }
+}
+
+/// Shows a snapshot of a report in the same way as the report itself.
+pub(crate) async fn handle_snapshot(request: Request<Body>, report_name: &str, id: &str) -> Result<Response<Body>, Infallible> {
+    if let Err(resp) = check_history_access(&request).await {
+        return resp;
+    }
+    let snapshot = match get_snapshot(report_name, id).await {
+        Ok(s) => s,
+        Err(resp) => return resp,
+    };
+    let query = match snapshot.query() {
+        Ok(q) => q,
+        Err(e) => {
+            error!("{}", e);
+            return return_500();
+        },
+    };
+
+    let mut template = TableTemplate::standalone(
+        &query,
+        Some(report_title(report_name).await),
+        snapshot.rows,
+        &[],
+        snapshot.taken_at,
+    );
+    template.root_path = "../../";
+    template.instance_errors = snapshot.instance_errors;
+    template.history_link = Some(format!("../{}", utf8_percent_encode(report_name, NON_ALPHANUMERIC)));
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render table template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
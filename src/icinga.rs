@@ -0,0 +1,680 @@
+//! Communication with the Icinga API.
+
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hyper::Method;
+use hyper::body::Bytes;
+use tokio::time::Instant;
+use tracing::{debug, error};
+
+use crate::{CLIENTS, RowPart, TableQuery};
+use crate::cache::{self, CacheKey, ObjectsResponse};
+use crate::columns;
+use crate::config::{CONFIG, IcingaApiConfig};
+use crate::filter::{Expression, quote_string};
+use crate::metrics;
+use crate::objtypes::ObjectType;
+use crate::tls::{self, TlsConfigError};
+
+
+/// The maximum number of objects addressed by a single filter built from object keys.
+pub(crate) const OBJECT_BATCH_SIZE: usize = 100;
+
+
+/// A response returned by the Icinga API.
+#[derive(Clone, Debug)]
+pub(crate) struct ApiResponse {
+    pub status_code: u16,
+    pub received_at: DateTime<Utc>,
+    pub body: Bytes,
+}
+impl ApiResponse {
+    /// Returns the response body as a string, interpreting it as ISO-8859-1 if it is not valid UTF-8.
+    pub fn body_string(&self) -> String {
+        match String::from_utf8(Vec::from(self.body.as_ref())) {
+            Ok(rs) => rs,
+            Err(_) => {
+                let mut string = String::with_capacity(self.body.len());
+                for b in self.body.iter() {
+                    string.push(char::from_u32(*b as u32).unwrap());
+                }
+                string
+            },
+        }
+    }
+}
+
+
+/// An error that may occur when calling the Icinga API.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum ApiCallError {
+    #[non_exhaustive] UnknownInstance { instance: String },
+    #[non_exhaustive] Url { path: String, error: url::ParseError },
+    #[non_exhaustive] Request { url: url::Url, error: reqwest::Error },
+    #[non_exhaustive] ResponseBody { url: url::Url, error: reqwest::Error },
+}
+impl fmt::Display for ApiCallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownInstance { instance, .. }
+                => write!(f, "no Icinga instance named {:?} is configured", instance),
+            Self::Url { path, error, .. }
+                => write!(f, "failed to append path {:?} to Icinga API base URL: {}", path, error),
+            Self::Request { url, error, .. }
+                => write!(f, "failed to obtain response from {:?}: {}", url.as_str(), error),
+            Self::ResponseBody { url, error, .. }
+                => write!(f, "failed to obtain response bytes from {:?}: {}", url.as_str(), error),
+        }
+    }
+}
+impl std::error::Error for ApiCallError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::UnknownInstance { .. } => None,
+            Self::Url { error, .. } => Some(error),
+            Self::Request { error, .. } => Some(error),
+            Self::ResponseBody { error, .. } => Some(error),
+        }
+    }
+}
+
+
+/// An error that may occur when building the HTTP client for an Icinga instance.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum ClientBuildError {
+    #[non_exhaustive] Tls { error: TlsConfigError },
+    #[non_exhaustive] Http { error: reqwest::Error },
+}
+impl fmt::Display for ClientBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tls { error, .. }
+                => write!(f, "failed to set up TLS: {}", error),
+            Self::Http { error, .. }
+                => write!(f, "failed to set up HTTP client: {}", error),
+        }
+    }
+}
+impl std::error::Error for ClientBuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Tls { error, .. } => Some(error),
+            Self::Http { error, .. } => Some(error),
+        }
+    }
+}
+
+
+/// An error that may occur when querying objects from Icinga.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum QueryError {
+    /// The Icinga API could not be called.
+    #[non_exhaustive] ApiCall { error: ApiCallError },
+
+    /// The Icinga API returned something that is not JSON.
+    #[non_exhaustive] Json { error: serde_json::Error },
+
+    /// The Icinga API returned JSON in an unexpected structure.
+    #[non_exhaustive] UnexpectedStructure { description: String },
+
+    /// The Icinga API returned an error.
+    #[non_exhaustive] Icinga { response: ApiResponse },
+}
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ApiCall { error, .. }
+                => write!(f, "{}", error),
+            Self::Json { error, .. }
+                => write!(f, "failed to parse Icinga response as JSON: {}", error),
+            Self::UnexpectedStructure { description, .. }
+                => write!(f, "unexpected Icinga response structure: {}", description),
+            Self::Icinga { response, .. }
+                => write!(f, "Icinga returned status code {}: {}", response.status_code, response.body_string()),
+        }
+    }
+}
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ApiCall { error, .. } => Some(error),
+            Self::Json { error, .. } => Some(error),
+            Self::UnexpectedStructure { .. } => None,
+            Self::Icinga { .. } => None,
+        }
+    }
+}
+
+
+/// The result of a successful object query.
+#[derive(Clone, Debug)]
+pub(crate) struct QueryResult {
+    pub status_code: u16,
+    pub fetched_at: DateTime<Utc>,
+    pub rows: Vec<RowPart>,
+}
+
+
+/// An error that occurred while querying one of several Icinga instances.
+#[derive(Debug)]
+pub(crate) struct InstanceError {
+    pub instance: String,
+    pub error: QueryError,
+}
+
+
+/// The merged result of querying all Icinga instances.
+#[derive(Debug)]
+pub(crate) struct MergedQueryResult {
+    /// The status code returned by the instances that were queried successfully.
+    pub status_code: u16,
+
+    /// When the last instance responded.
+    pub fetched_at: DateTime<Utc>,
+
+    /// When the first instance responded; with caching, this is when the oldest data was obtained.
+    pub oldest_fetched_at: DateTime<Utc>,
+
+    /// The rows returned by all instances, sorted.
+    pub rows: Vec<RowPart>,
+
+    /// The errors of the instances that could not be queried.
+    pub errors: Vec<InstanceError>,
+}
+
+
+/// Credentials with which a user accesses the Icinga API instead of those configured for the
+/// instance.
+#[derive(Clone, Eq, Hash, PartialEq)]
+pub(crate) struct IcingaCredentials {
+    pub username: String,
+    pub password: String,
+}
+impl fmt::Debug for IcingaCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IcingaCredentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+
+/// The result of an action performed on a single object.
+#[derive(Clone, Debug)]
+pub(crate) struct ActionResult {
+    pub instance: String,
+    pub name: String,
+    pub code: u16,
+    pub status: String,
+}
+impl ActionResult {
+    pub fn is_success(&self) -> bool {
+        self.code >= 200 && self.code < 300
+    }
+
+    /// The key of the row representing the object, see [RowPart::key].
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.instance, self.name)
+    }
+}
+
+
+/// Splits a row key (`instance:host` or `instance:host!service`) into the instance, host and
+/// service name.
+///
+/// The service name is empty for hosts. Returns `None` if the key does not contain an instance.
+pub(crate) fn split_object_key(key: &str) -> Option<(String, String, String)> {
+    let (instance, name) = key.split_once(':')?;
+    let (host, service) = match name.split_once('!') {
+        Some((host, service)) => (host, service),
+        None => (name, ""),
+    };
+    Some((instance.to_owned(), host.to_owned(), service.to_owned()))
+}
+
+
+/// Returns a filter expression matching exactly the objects with the given host and service names.
+pub(crate) fn objects_filter<'a, I: IntoIterator<Item = &'a (String, String)>>(object_keys: I) -> String {
+    let object_filters: Vec<String> = object_keys.into_iter()
+        .map(|(host, service)| if service.is_empty() {
+            format!("host.name == {}", quote_string(host))
+        } else {
+            format!("host.name == {} && service.name == {}", quote_string(host), quote_string(service))
+        })
+        .collect();
+    object_filters.join(" || ")
+}
+
+
+/// Builds the HTTP client used to access an Icinga instance.
+pub(crate) fn build_client(icinga_config: &IcingaApiConfig) -> Result<reqwest::Client, ClientBuildError> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(icinga_config.timeout_s));
+    match tls::icinga_client_config(icinga_config) {
+        Ok(Some(tls_config)) => {
+            builder = builder.use_preconfigured_tls(tls_config);
+        },
+        Ok(None) => {
+            builder = builder.danger_accept_invalid_certs(icinga_config.allow_invalid_certs);
+        },
+        Err(error) => return Err(ClientBuildError::Tls { error }),
+    }
+    builder.build()
+        .map_err(|error| ClientBuildError::Http { error })
+}
+
+
+/// Adds HTTP basic authentication to a request: the given user credentials if any, otherwise those
+/// configured for the Icinga instance, if any.
+fn add_basic_auth(request: reqwest::RequestBuilder, icinga_config: &IcingaApiConfig, credentials: Option<&IcingaCredentials>) -> reqwest::RequestBuilder {
+    if let Some(credentials) = credentials {
+        return request.basic_auth(&credentials.username, Some(&credentials.password));
+    }
+    match &icinga_config.username {
+        Some(username) => request.basic_auth(username, icinga_config.password.as_ref()),
+        None => request,
+    }
+}
+
+
+/// The names of all configured Icinga instances.
+pub(crate) async fn instance_names() -> Vec<String> {
+    let config_guard = CONFIG
+        .get().expect("CONFIG not set?!")
+        .read().await;
+    config_guard.instances()
+        .into_iter()
+        .map(|i| i.name)
+        .collect()
+}
+
+
+/// Obtains the configuration and the HTTP client of an Icinga instance.
+async fn get_instance(instance: &str) -> Result<(IcingaApiConfig, reqwest::Client), ApiCallError> {
+    let icinga_config = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        config_guard.instance(instance)
+            .ok_or_else(|| ApiCallError::UnknownInstance { instance: instance.to_owned() })?
+            .api
+    };
+    let client = {
+        let clients_guard = CLIENTS
+            .get().expect("CLIENTS not set?!")
+            .read().await;
+        clients_guard.get(instance)
+            .cloned()
+            .ok_or_else(|| ApiCallError::UnknownInstance { instance: instance.to_owned() })?
+    };
+    Ok((icinga_config, client))
+}
+
+
+/// Calls an endpoint of the API of an Icinga instance with a JSON body.
+///
+/// If `as_get` is true, the call is made as a POST request with `X-HTTP-Method-Override: GET`,
+/// which is how Icinga expects queries with a body to be made. If credentials are given, they are
+/// used instead of those configured for the instance.
+pub(crate) async fn call_api(
+    instance: &str,
+    path: &str,
+    as_get: bool,
+    body: &serde_json::Value,
+    credentials: Option<&IcingaCredentials>,
+) -> Result<ApiResponse, ApiCallError> {
+    let (icinga_config, client) = get_instance(instance).await?;
+    let icinga_url = icinga_config.base_url.join(path)
+        .map_err(|error| ApiCallError::Url { path: path.to_owned(), error })?;
+    debug!("requesting Icinga URL: {}", icinga_url);
+
+    let mut request = add_basic_auth(client.request(Method::POST, icinga_url.clone()), &icinga_config, credentials)
+        .header("Accept", "application/json");
+    if as_get {
+        request = request.header("X-HTTP-Method-Override", "GET");
+    }
+    let timer = metrics::ICINGA_REQUEST_DURATION.with_label_values(&[instance]).start_timer();
+    let response_res = request
+        .body(serde_json::to_string(body).expect("cannot serialize serde_json::Value to JSON?!"))
+        .send().await;
+    let response = match response_res {
+        Ok(r) => r,
+        Err(error) => {
+            metrics::ICINGA_REQUESTS.with_label_values(&[instance, "error"]).inc();
+            return Err(ApiCallError::Request { url: icinga_url.clone(), error });
+        },
+    };
+    timer.observe_duration();
+    let status_code = response.status().as_u16();
+    let received_at = Utc::now();
+    let body = match response.bytes().await {
+        Ok(b) => b,
+        Err(error) => {
+            metrics::ICINGA_REQUESTS.with_label_values(&[instance, "error"]).inc();
+            return Err(ApiCallError::ResponseBody { url: icinga_url.clone(), error });
+        },
+    };
+    metrics::ICINGA_REQUESTS.with_label_values(&[instance, &status_code.to_string()]).inc();
+
+    Ok(ApiResponse {
+        status_code,
+        received_at,
+        body,
+    })
+}
+
+
+/// Checks whether Icinga accepts the given credentials.
+///
+/// The credentials are checked against the first configured instance. They are valid if Icinga
+/// responds with a success status code and invalid if it responds with status code 401; any other
+/// response (e.g. because the user lacks the permission for the endpoint called or Icinga is
+/// unavailable) is returned as an error.
+pub(crate) async fn verify_credentials(credentials: &IcingaCredentials) -> Result<bool, QueryError> {
+    let instance = match instance_names().await.into_iter().next() {
+        Some(i) => i,
+        None => return Ok(false),
+    };
+    let response = call_api(&instance, "status/IcingaApplication", true, &serde_json::json!({}), Some(credentials)).await
+        .map_err(|error| QueryError::ApiCall { error })?;
+    match response.status_code {
+        200..=299 => Ok(true),
+        401 => Ok(false),
+        _ => Err(QueryError::Icinga { response }),
+    }
+}
+
+
+/// Queries the objects matching the given query from an Icinga instance and returns them as sorted
+/// rows.
+///
+/// If `cached` is true, a sufficiently fresh response to an identical query may be reused, see
+/// [cache::get_or_fetch].
+pub(crate) async fn fetch_rows(instance: &str, query: &TableQuery, cached: bool) -> Result<QueryResult, QueryError> {
+    // request only the attributes we need
+    let (mut attrs, joins) = columns::required_attributes(&query.columns);
+    for row_attribute in query.object_type.row_attributes {
+        attrs.insert((*row_attribute).to_owned());
+    }
+    let cache_key = CacheKey {
+        instance: instance.to_owned(),
+        objtype: query.object_type.plural,
+        filter: query.api_filter(),
+        attributes: attrs,
+        joins,
+        credentials: query.credentials.clone(),
+    };
+
+    let response = if cached {
+        cache::get_or_fetch(cache_key.clone(), || fetch_objects(&cache_key)).await?
+    } else {
+        Arc::new(fetch_objects(&cache_key).await?)
+    };
+
+    let mut rows: Vec<RowPart> = response.results.iter()
+        .map(|result| query.row_from_result(instance, result))
+        .collect();
+    query.sort_rows(&mut rows);
+
+    Ok(QueryResult {
+        status_code: response.status_code,
+        fetched_at: response.received_at,
+        rows,
+    })
+}
+
+/// Queries objects from an Icinga instance.
+async fn fetch_objects(key: &CacheKey) -> Result<ObjectsResponse, QueryError> {
+    let mut api_body = serde_json::json!({
+        "filter": key.filter,
+        "attrs": key.attributes,
+    });
+    if !key.joins.is_empty() {
+        api_body["joins"] = serde_json::json!(key.joins);
+    }
+
+    let icinga_url_path = format!("objects/{}", key.objtype);
+    let response = call_api(&key.instance, &icinga_url_path, true, &api_body, key.credentials.as_ref()).await
+        .map_err(|error| QueryError::ApiCall { error })?;
+    if response.status_code != 200 {
+        return Err(QueryError::Icinga { response });
+    }
+
+    let mut response_json: serde_json::Value = serde_json::from_slice(&response.body)
+        .map_err(|error| QueryError::Json { error })?;
+    let results = match response_json["results"].take() {
+        serde_json::Value::Array(r) => r,
+        other => return Err(QueryError::UnexpectedStructure {
+            description: format!("path $.results is not an array but {:?}", other),
+        }),
+    };
+
+    Ok(ObjectsResponse {
+        status_code: response.status_code,
+        received_at: response.received_at,
+        received_instant: Instant::now(),
+        results,
+    })
+}
+
+
+/// Queries the objects matching the given query from all Icinga instances concurrently and merges
+/// the results.
+///
+/// Returns an error only if no instance could be queried successfully; otherwise, the errors of
+/// the failing instances are returned as part of the result. See [fetch_rows] regarding `cached`.
+pub(crate) async fn fetch_all_rows(query: &TableQuery, cached: bool) -> Result<MergedQueryResult, InstanceError> {
+    let instances = instance_names().await;
+    let tasks: Vec<(String, tokio::task::JoinHandle<Result<QueryResult, QueryError>>)> = instances.into_iter()
+        .map(|instance| {
+            let task_instance = instance.clone();
+            let task_query = query.clone();
+            let task = tokio::spawn(async move {
+                fetch_rows(&task_instance, &task_query, cached).await
+            });
+            (instance, task)
+        })
+        .collect();
+
+    let mut status_code = None;
+    let mut fetched_at = None;
+    let mut oldest_fetched_at: Option<DateTime<Utc>> = None;
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (instance, task) in tasks {
+        match task.await.expect("Icinga query task panicked") {
+            Ok(result) => {
+                status_code = Some(result.status_code);
+                fetched_at = fetched_at.max(Some(result.fetched_at));
+                oldest_fetched_at = Some(match oldest_fetched_at {
+                    Some(ofa) => ofa.min(result.fetched_at),
+                    None => result.fetched_at,
+                });
+                rows.extend(result.rows);
+            },
+            Err(error) => errors.push(InstanceError { instance, error }),
+        }
+    }
+
+    let (status_code, fetched_at, oldest_fetched_at) = match (status_code, fetched_at, oldest_fetched_at) {
+        (Some(sc), Some(fa), Some(ofa)) => (sc, fa, ofa),
+        _ => {
+            // nothing worked; report the first error
+            return Err(errors.into_iter().next().expect("no Icinga instances configured"));
+        },
+    };
+    query.sort_rows(&mut rows);
+
+    Ok(MergedQueryResult {
+        status_code,
+        fetched_at,
+        oldest_fetched_at,
+        rows,
+        errors,
+    })
+}
+
+
+/// Opens a stream of events from the API of an Icinga instance.
+///
+/// Icinga delivers the events as newline-separated JSON objects. Since the client enforces a
+/// timeout on the whole request including the body, the stream is closed after `max_duration` and
+/// must then be reopened.
+pub(crate) async fn open_event_stream(
+    instance: &str,
+    queue: &str,
+    types: &[&str],
+    max_duration: Duration,
+    credentials: Option<&IcingaCredentials>,
+) -> Result<reqwest::Response, ApiCallError> {
+    let (icinga_config, client) = get_instance(instance).await?;
+    let path = "events";
+    let icinga_url = icinga_config.base_url.join(path)
+        .map_err(|error| ApiCallError::Url { path: path.to_owned(), error })?;
+    debug!("opening Icinga event stream: {}", icinga_url);
+
+    let api_body = serde_json::json!({
+        "queue": queue,
+        "types": types,
+    });
+    add_basic_auth(client.request(Method::POST, icinga_url.clone()), &icinga_config, credentials)
+        .header("Accept", "application/json")
+        .timeout(max_duration)
+        .body(serde_json::to_string(&api_body).expect("cannot serialize serde_json::Value to JSON?!"))
+        .send().await
+        .map_err(|error| ApiCallError::Request { url: icinga_url.clone(), error })
+}
+
+
+/// Performs an action via `actions/<action>` on the objects of the given type matching a filter
+/// on an Icinga instance.
+///
+/// `params` is passed in addition to the type and the filter. Returns the outcome for each object
+/// reported by Icinga.
+pub(crate) async fn perform_action_on_filter(
+    instance: &str,
+    action: &str,
+    object_type: &ObjectType,
+    filter: &str,
+    params: &serde_json::Map<String, serde_json::Value>,
+    credentials: Option<&IcingaCredentials>,
+) -> Result<Vec<ActionResult>, QueryError> {
+    let icinga_url_path = format!("actions/{}", action);
+    let mut api_body = serde_json::Value::Object(params.clone());
+    api_body["type"] = serde_json::Value::String(object_type.type_name.to_owned());
+    api_body["filter"] = serde_json::Value::String(filter.to_owned());
+
+    let response = call_api(instance, &icinga_url_path, false, &api_body, credentials).await
+        .map_err(|error| QueryError::ApiCall { error })?;
+
+    // Icinga reports partial failure with an error status code but still lists the results
+    let response_json: serde_json::Value = match serde_json::from_slice(&response.body) {
+        Ok(rj) => rj,
+        Err(error) => {
+            if response.status_code != 200 {
+                return Err(QueryError::Icinga { response });
+            }
+            return Err(QueryError::Json { error });
+        },
+    };
+    let results = match response_json["results"].as_array() {
+        Some(r) => r,
+        None => {
+            if response.status_code != 200 {
+                return Err(QueryError::Icinga { response });
+            }
+            return Err(QueryError::UnexpectedStructure {
+                description: format!("path $.results is not an array but {:?}", response_json["results"]),
+            });
+        },
+    };
+
+    Ok(
+        results.iter()
+            .map(|result| ActionResult {
+                instance: instance.to_owned(),
+                name: result["name"].as_str().unwrap_or("").to_owned(),
+                code: result["code"].as_f64().map(|c| c as u16).unwrap_or(0),
+                status: result["status"].as_str().unwrap_or("").to_owned(),
+            })
+            .collect()
+    )
+}
+
+
+/// Performs an action via `actions/<action>` on the objects of the given type matching a filter
+/// on all Icinga instances.
+///
+/// Instances on which the action fails are reported as a failed result without an object name.
+pub(crate) async fn perform_action_on_filter_everywhere(
+    action: &str,
+    object_type: &ObjectType,
+    filter: &str,
+    params: &serde_json::Map<String, serde_json::Value>,
+    credentials: Option<&IcingaCredentials>,
+) -> Vec<ActionResult> {
+    let mut action_results = Vec::new();
+    for instance in instance_names().await {
+        match perform_action_on_filter(&instance, action, object_type, filter, params, credentials).await {
+            Ok(results) => action_results.extend(results),
+            Err(e) => {
+                error!("failed to perform {} on Icinga instance {:?}: {}", action, instance, e);
+                let code = match &e {
+                    QueryError::Icinga { response } => response.status_code,
+                    _ => 0,
+                };
+                action_results.push(ActionResult {
+                    instance,
+                    name: String::new(),
+                    code,
+                    status: e.to_string(),
+                });
+            },
+        }
+    }
+    action_results
+}
+
+
+/// Performs an action via `actions/<action>` on the objects with the given row keys.
+///
+/// The objects are grouped by instance and addressed in batches, see [perform_action_on_filter].
+/// If a restriction is given, objects not matching it are not affected.
+pub(crate) async fn perform_action(
+    action: &str,
+    object_type: &ObjectType,
+    object_keys: &[(String, String, String)],
+    restriction: Option<&Expression>,
+    params: &serde_json::Map<String, serde_json::Value>,
+    credentials: Option<&IcingaCredentials>,
+) -> Result<Vec<ActionResult>, QueryError> {
+    let mut instance_to_objects: BTreeMap<&str, Vec<(String, String)>> = BTreeMap::new();
+    for (instance, host, service) in object_keys {
+        instance_to_objects
+            .entry(instance.as_str())
+            .or_default()
+            .push((host.clone(), service.clone()));
+    }
+
+    let mut action_results = Vec::with_capacity(object_keys.len());
+    for (instance, objects) in instance_to_objects {
+        for batch in objects.chunks(OBJECT_BATCH_SIZE) {
+            let batch_filter = match restriction {
+                Some(r) => format!("({}) && ({})", r, objects_filter(batch)),
+                None => objects_filter(batch),
+            };
+            let batch_results = perform_action_on_filter(instance, action, object_type, &batch_filter, params, credentials).await?;
+            action_results.extend(batch_results);
+        }
+    }
+    Ok(action_results)
+}
@@ -0,0 +1,462 @@
+//! Live-updating tables, fed by the Icinga event stream and delivered to browsers as Server-Sent
+//! Events.
+//!
+//! All browsers watching the same query share one [LiveTable], which keeps the current rows in
+//! memory. Whenever Icinga reports an event concerning an object of the queried type, the affected
+//! objects are queried again (in batches) and the changes are broadcast to the browsers.
+
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use hyper::{Body, Response};
+use hyper::body::Bytes;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+use tracing::{debug, error, warn};
+
+use crate::{return_500, RowPart, TableQuery};
+use crate::filter;
+use crate::icinga;
+
+
+/// The types of Icinga events that cause rows to be updated.
+const EVENT_TYPES: &[&str] = &["CheckResult", "StateChange", "AcknowledgementSet", "DowntimeStarted"];
+
+/// How long an event stream is kept open before it is reopened (and the table fully refreshed).
+const EVENT_STREAM_MAX_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before reconnecting after the event stream failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// How long to collect events before querying the affected objects.
+const DEBOUNCE_DELAY: Duration = Duration::from_secs(1);
+
+/// How often to check whether anybody is still watching the table if no events arrive.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often to send a keepalive comment to browsers.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The capacity of the broadcast channel between a table and its watchers.
+const BROADCAST_CAPACITY: usize = 1024;
+
+
+/// The live tables that are currently being watched.
+static LIVE_TABLES: Lazy<Mutex<HashMap<TableQuery, Weak<LiveTable>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A counter to give each event queue a unique name.
+static QUEUE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+
+/// A table cell as sent to browsers.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+struct LiveCell {
+    text: String,
+    class: String,
+}
+
+/// A table row as sent to browsers.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+struct LiveRow {
+    key: String,
+    cells: Vec<LiveCell>,
+}
+
+/// A change to a live table.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum LiveUpdate {
+    /// A row has been added or changed.
+    Upsert(LiveRow),
+
+    /// The row with the given key has been removed.
+    Remove(String),
+
+    /// The rows now have the given order.
+    Order(Vec<String>),
+}
+impl LiveUpdate {
+    /// Encodes this update as a Server-Sent Event.
+    fn to_event(&self) -> String {
+        let (event_name, data) = match self {
+            Self::Upsert(row) => ("upsert", serde_json::to_string(row)),
+            Self::Remove(key) => ("remove", serde_json::to_string(key)),
+            Self::Order(keys) => ("order", serde_json::to_string(keys)),
+        };
+        format!("event: {}\ndata: {}\n\n", event_name, data.expect("failed to serialize live update"))
+    }
+}
+
+
+/// A table whose rows are kept up to date.
+struct LiveTable {
+    query: TableQuery,
+    rows: Mutex<BTreeMap<String, RowPart>>,
+    sender: broadcast::Sender<LiveUpdate>,
+}
+impl LiveTable {
+    fn live_row(&self, row: &RowPart) -> LiveRow {
+        let cells = self.query.columns.iter()
+            .zip(row.cells.iter())
+            .map(|(column, cell)| LiveCell {
+                text: cell.to_string(),
+                class: column.cell_css_class(cell),
+            })
+            .collect();
+        LiveRow {
+            key: row.key(),
+            cells,
+        }
+    }
+
+    fn order(&self, rows: &BTreeMap<String, RowPart>) -> Vec<String> {
+        let mut sorted_rows: Vec<RowPart> = rows.values().cloned().collect();
+        self.query.sort_rows(&mut sorted_rows);
+        sorted_rows.iter().map(|r| r.key()).collect()
+    }
+
+    /// Returns the updates that bring a new watcher up to date.
+    fn snapshot(&self) -> Vec<LiveUpdate> {
+        let rows = self.rows.lock().expect("live table rows poisoned");
+        let mut updates: Vec<LiveUpdate> = rows.values()
+            .map(|row| LiveUpdate::Upsert(self.live_row(row)))
+            .collect();
+        updates.push(LiveUpdate::Order(self.order(&rows)));
+        updates
+    }
+
+    /// Updates the rows of the given instance with the given keys and broadcasts the changes.
+    ///
+    /// `new_rows` contains the current versions of the rows; rows whose keys are in `keys` but not in
+    /// `new_rows` are removed. If `keys` is `None`, all rows of the instance are replaced.
+    fn apply(&self, instance: &str, keys: Option<&BTreeSet<String>>, new_rows: Vec<RowPart>) {
+        let mut rows = self.rows.lock().expect("live table rows poisoned");
+        let mut updates = Vec::new();
+
+        let new_rows: BTreeMap<String, RowPart> = new_rows.into_iter()
+            .map(|r| (r.key(), r))
+            .collect();
+        let removed_keys: Vec<String> = rows.iter()
+            .filter(|(_k, r)| r.instance == instance)
+            .map(|(k, _r)| k)
+            .filter(|k| keys.map(|ks| ks.contains(*k)).unwrap_or(true))
+            .filter(|k| !new_rows.contains_key(*k))
+            .cloned()
+            .collect();
+        for key in removed_keys {
+            rows.remove(&key);
+            updates.push(LiveUpdate::Remove(key));
+        }
+        for (key, new_row) in new_rows {
+            if rows.get(&key) != Some(&new_row) {
+                updates.push(LiveUpdate::Upsert(self.live_row(&new_row)));
+                rows.insert(key, new_row);
+            }
+        }
+
+        if updates.is_empty() {
+            return;
+        }
+        updates.push(LiveUpdate::Order(self.order(&rows)));
+
+        for update in updates {
+            // an error only means that nobody is listening at the moment
+            let _ = self.sender.send(update);
+        }
+    }
+}
+
+
+/// Returns the live table for the given query, creating it if nobody is watching it yet.
+fn get_or_create_table(query: TableQuery) -> Arc<LiveTable> {
+    let mut tables = LIVE_TABLES.lock().expect("live tables poisoned");
+    if let Some(table) = tables.get(&query).and_then(|t| t.upgrade()) {
+        return table;
+    }
+
+    // clean up tables nobody is watching anymore
+    tables.retain(|_q, t| t.strong_count() > 0);
+
+    let (sender, _receiver) = broadcast::channel(BROADCAST_CAPACITY);
+    let table = Arc::new(LiveTable {
+        query: query.clone(),
+        rows: Mutex::new(BTreeMap::new()),
+        sender,
+    });
+    tables.insert(query, Arc::downgrade(&table));
+    tokio::spawn(run_table(Arc::downgrade(&table)));
+    table
+}
+
+
+/// Extracts the key of the object affected by an Icinga event, if it concerns the given object type.
+///
+/// Downtime events describe the affected object within the `downtime` object, where the service
+/// name is empty for host downtimes.
+fn event_object_key(event: &serde_json::Value, objtype: &str) -> Option<(String, String)> {
+    let (host, service) = if event["type"] == "DowntimeStarted" {
+        let downtime = &event["downtime"];
+        let service = downtime["service_name"].as_str()
+            .filter(|s| !s.is_empty());
+        (downtime["host_name"].as_str()?, service)
+    } else {
+        (event["host"].as_str()?, event["service"].as_str())
+    };
+    match (objtype, service) {
+        ("hosts", None) => Some((host.to_owned(), String::new())),
+        ("services", Some(s)) => Some((host.to_owned(), s.to_owned())),
+        _ => None,
+    }
+}
+
+/// Queries the given objects of an Icinga instance again and updates the table accordingly.
+async fn refetch_objects(table: &LiveTable, instance: &str, object_keys: &BTreeSet<(String, String)>) {
+    let object_keys: Vec<&(String, String)> = object_keys.iter().collect();
+    for batch in object_keys.chunks(icinga::OBJECT_BATCH_SIZE) {
+        let objects_filter = icinga::objects_filter(batch.iter().copied());
+        let objects_expression = match filter::parse(&objects_filter) {
+            Ok(e) => e,
+            Err(e) => {
+                error!("failed to parse filter {:?} for changed objects: {}", objects_filter, e);
+                continue;
+            },
+        };
+        let batch_query = table.query.narrowed(objects_expression);
+
+        match icinga::fetch_rows(instance, &batch_query, false).await {
+            Ok(result) => {
+                let keys: BTreeSet<String> = batch.iter()
+                    .map(|(host, service)| if service.is_empty() {
+                        format!("{}:{}", instance, host)
+                    } else {
+                        format!("{}:{}!{}", instance, host, service)
+                    })
+                    .collect();
+                table.apply(instance, Some(&keys), result.rows);
+            },
+            Err(e) => {
+                error!("failed to query changed {} from Icinga instance {:?}: {}", table.query.object_type.plural, instance, e);
+            },
+        }
+    }
+}
+
+/// Keeps a live table up to date for as long as somebody is watching it.
+async fn run_table(weak_table: Weak<LiveTable>) {
+    for instance in icinga::instance_names().await {
+        tokio::spawn(run_instance(weak_table.clone(), instance));
+    }
+}
+
+/// Keeps the rows of a live table returned by one Icinga instance up to date for as long as
+/// somebody is watching the table.
+async fn run_instance(weak_table: Weak<LiveTable>, instance: String) {
+    let queue_name = format!(
+        "icingcake-{}-{}",
+        std::process::id(),
+        QUEUE_COUNTER.fetch_add(1, Ordering::Relaxed),
+    );
+
+    loop {
+        // refresh everything (initially and to catch up on events missed while disconnected)
+        let credentials = {
+            let table = match weak_table.upgrade() {
+                Some(t) => t,
+                None => return,
+            };
+            match icinga::fetch_rows(&instance, &table.query, false).await {
+                Ok(result) => table.apply(&instance, None, result.rows),
+                Err(e) => error!("failed to query {} from Icinga instance {:?}: {}", table.query.object_type.plural, instance, e),
+            }
+            table.query.credentials.clone()
+        };
+
+        let mut response = match icinga::open_event_stream(&instance, &queue_name, EVENT_TYPES, EVENT_STREAM_MAX_DURATION, credentials.as_ref()).await {
+            Ok(r) if r.status().is_success() => r,
+            Ok(r) => {
+                error!("Icinga instance {:?} refused to open event stream: status code {}", instance, r.status());
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            },
+            Err(e) => {
+                error!("failed to open event stream of Icinga instance {:?}: {}", instance, e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            },
+        };
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut pending: BTreeSet<(String, String)> = BTreeSet::new();
+        let mut flush_at: Option<Instant> = None;
+        loop {
+            let table = match weak_table.upgrade() {
+                Some(t) => t,
+                None => {
+                    debug!("nobody is watching live table anymore; closing event stream");
+                    return;
+                },
+            };
+
+            let wait = match flush_at {
+                Some(fa) => fa.saturating_duration_since(Instant::now()),
+                None => IDLE_CHECK_INTERVAL,
+            };
+            match tokio::time::timeout(wait, response.chunk()).await {
+                Err(_elapsed) => {},
+                Ok(Ok(Some(chunk))) => {
+                    buffer.extend_from_slice(&chunk);
+                    while let Some(newline_index) = buffer.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = buffer.drain(..=newline_index).collect();
+                        let event: serde_json::Value = match serde_json::from_slice(&line) {
+                            Ok(e) => e,
+                            Err(e) => {
+                                warn!("failed to parse Icinga event: {}", e);
+                                continue;
+                            },
+                        };
+                        if let Some(object_key) = event_object_key(&event, table.query.object_type.plural) {
+                            pending.insert(object_key);
+                            if flush_at.is_none() {
+                                flush_at = Some(Instant::now() + DEBOUNCE_DELAY);
+                            }
+                        }
+                    }
+                },
+                Ok(Ok(None)) => {
+                    debug!("Icinga event stream ended");
+                    break;
+                },
+                Ok(Err(e)) => {
+                    error!("failed to read from Icinga event stream: {}", e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    break;
+                },
+            }
+
+            if flush_at.map(|fa| fa <= Instant::now()).unwrap_or(false) {
+                refetch_objects(&table, &instance, &pending).await;
+                pending.clear();
+                flush_at = None;
+            }
+        }
+    }
+}
+
+
+/// Responds with a stream of Server-Sent Events describing the changes to the query's rows.
+pub(crate) async fn handle_events(query: TableQuery) -> Result<Response<Body>, Infallible> {
+    let table = get_or_create_table(query);
+    let mut receiver = table.sender.subscribe();
+    let initial_updates = table.snapshot();
+
+    let (mut body_sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+        for update in initial_updates {
+            if body_sender.send_data(Bytes::from(update.to_event())).await.is_err() {
+                return;
+            }
+        }
+        loop {
+            let updates = tokio::select! {
+                received = receiver.recv() => match received {
+                    Ok(update) => vec![update],
+                    Err(RecvError::Lagged(_)) => table.snapshot(),
+                    Err(RecvError::Closed) => return,
+                },
+                _ = keepalive.tick() => {
+                    if body_sender.send_data(Bytes::from(": keepalive\n\n")).await.is_err() {
+                        return;
+                    }
+                    continue;
+                },
+            };
+            for update in updates {
+                if body_sender.send_data(Bytes::from(update.to_event())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(body)
+        .or_else(|e| {
+            error!("failed to construct event stream response: {}", e);
+            return_500()
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_result_key() {
+        let event = serde_json::json!({
+            "type": "CheckResult",
+            "timestamp": 1700000000.0,
+            "host": "web01",
+            "service": "http",
+            "check_result": { "state": 2.0, "output": "connection refused" },
+        });
+        assert_eq!(event_object_key(&event, "services"), Some(("web01".to_owned(), "http".to_owned())));
+        assert_eq!(event_object_key(&event, "hosts"), None);
+
+        let event = serde_json::json!({
+            "type": "StateChange",
+            "timestamp": 1700000000.0,
+            "host": "web01",
+            "state": 1.0,
+            "state_type": 1.0,
+        });
+        assert_eq!(event_object_key(&event, "hosts"), Some(("web01".to_owned(), String::new())));
+        assert_eq!(event_object_key(&event, "services"), None);
+    }
+
+    #[test]
+    fn test_downtime_started_key() {
+        let event: serde_json::Value = serde_json::from_str(r#"{
+            "type": "DowntimeStarted",
+            "timestamp": 1700000000.123456,
+            "downtime": {
+                "__name": "db01!postgres!icinga2-1700000000-0",
+                "host_name": "db01",
+                "service_name": "postgres",
+                "author": "admin",
+                "comment": "database upgrade",
+                "start_time": 1700000000.0,
+                "end_time": 1700003600.0,
+                "duration": 0.0,
+                "entry_time": 1699999000.0,
+                "fixed": true,
+                "triggered_by": "",
+                "scheduled_by": "",
+                "config_owner": "",
+                "name": "icinga2-1700000000-0",
+                "package": "_api",
+                "type": "Downtime",
+                "was_cancelled": false,
+                "zone": "master"
+            }
+        }"#).unwrap();
+        assert_eq!(event_object_key(&event, "services"), Some(("db01".to_owned(), "postgres".to_owned())));
+        assert_eq!(event_object_key(&event, "hosts"), None);
+
+        let mut host_event = event.clone();
+        host_event["downtime"]["__name"] = "db01!icinga2-1700000000-1".into();
+        host_event["downtime"]["service_name"] = "".into();
+        assert_eq!(event_object_key(&host_event, "hosts"), Some(("db01".to_owned(), String::new())));
+        assert_eq!(event_object_key(&host_event, "services"), None);
+    }
+}
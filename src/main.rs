@@ -1,33 +1,141 @@
+mod actions;
+mod auth;
+mod availability;
+mod cache;
+mod cli;
+mod columns;
 mod config;
+mod diff;
+mod export;
+mod filter;
+mod history;
+mod icinga;
+mod live;
+mod metrics;
+mod objtypes;
+mod reload;
+mod schedule;
+mod spreadsheet;
+mod tls;
 
 
 use std::borrow::Cow;
 use std::cmp::{Ord, Ordering, PartialOrd};
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::fmt;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Duration;
 
 use askama::Template;
-use clap::Parser;
+use chrono::{DateTime, Local, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use form_urlencoded;
 use from_to_repr::from_to_other;
-use hyper::{Body, Method, Request, Response, Server};
+use hyper::{Body, Request, Response, Server};
+use hyper::server::conn::{AddrStream, Http};
 use hyper::service::{make_service_fn, service_fn};
 use once_cell::sync::OnceCell;
-use percent_encoding::percent_decode_str;
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use tokio::net::TcpListener;
 use tokio::sync::RwLock;
+use tokio_rustls::TlsAcceptor;
 use tracing::{debug, error};
 
-use crate::config::{CONFIG, CONFIG_PATH};
+use crate::auth::{AuthOutcome, Permissions};
+use crate::columns::{AttributeSource, CellValue, Column, SortKey};
+use crate::config::{CONFIG, CONFIG_PATH, HttpsConfig, ReportConfig};
+use crate::filter::{BinaryOperator, Expression, FilterParseError};
+use crate::icinga::{ApiResponse, IcingaCredentials, InstanceError, QueryError};
+use crate::objtypes::ObjectType;
 
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Opts {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Options for serving if no subcommand is given.
+    #[command(flatten)]
+    pub serve: ServeOpts,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the web interface (the default).
+    Serve(ServeOpts),
+
+    /// Generate a single report, output it and exit.
+    Report(ReportOpts),
+
+    /// Check the configuration and exit.
+    CheckConfig(ConfigOpts),
+}
+
+#[derive(Args)]
+struct ServeOpts {
+    /// Reload the configuration whenever the configuration file changes (it is always reloaded on
+    /// SIGHUP).
+    #[arg(long)]
+    pub watch_config: bool,
+
+    #[arg(default_value = "config.toml")]
+    pub config_path: PathBuf,
+}
+
+#[derive(Args)]
+struct ConfigOpts {
+    #[arg(default_value = "config.toml")]
+    pub config_path: PathBuf,
+}
+
+#[derive(Args)]
+struct ReportOpts {
+    /// Name of the configured report to generate.
+    #[arg(long, conflicts_with_all = ["objtype", "filter", "columns", "sort"])]
+    pub report: Option<String>,
+
+    /// Type of object to query, e.g. `hosts` or `services`.
+    #[arg(long, required_unless_present = "report")]
+    pub objtype: Option<String>,
+
+    /// Icinga filter expression selecting the objects to show. If not given, all objects are shown.
+    #[arg(long)]
+    pub filter: Option<String>,
+
+    /// Comma-separated dotted paths of the attributes to show as columns.
+    #[arg(long)]
+    pub columns: Option<String>,
+
+    /// Comma-separated dotted paths of the columns by which to sort, each optionally prefixed by
+    /// `-` for descending order.
+    #[arg(long)]
+    pub sort: Option<String>,
+
+    /// The format in which to output the report.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+
+    /// The file to write the report to. If not given, the report is written to stdout.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
     #[arg(default_value = "config.toml")]
     pub config_path: PathBuf,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum ReportFormat {
+    Html,
+    Csv,
+    Json,
+    Text,
+}
+
 #[derive(Copy, Clone, Debug)]
 #[from_to_other(base_type = u8, derive_compare = "as_int")]
 enum NagiosState {
@@ -49,9 +157,47 @@ impl fmt::Display for NagiosState {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum OutputFormat {
+    Html,
+    Csv,
+    Json,
+    Xlsx,
+    Ods,
+    Live,
+    Events,
+}
+impl OutputFormat {
+    pub fn from_parameter(value: &str) -> Option<Self> {
+        match value {
+            "html" => Some(Self::Html),
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            "xlsx" => Some(Self::Xlsx),
+            "ods" => Some(Self::Ods),
+            "live" => Some(Self::Live),
+            "events" => Some(Self::Events),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Template)]
 #[template(path = "index.html")]
-struct IndexTemplate;
+struct IndexTemplate {
+    pub reports: Vec<ReportLink>,
+    pub availability: bool,
+
+    /// The CSRF token for the logout form; only set if the user has been authenticated.
+    pub logout_csrf_token: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct ReportLink {
+    pub url: String,
+    pub title: String,
+    pub history_url: Option<String>,
+}
 
 #[derive(Template)]
 #[template(path = "icinga_error.html")]
@@ -60,18 +206,84 @@ struct IcingaErrorTemplate {
     pub error_json: String,
 }
 
+#[derive(Template)]
+#[template(path = "filter_error.html")]
+struct FilterErrorTemplate {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub source_line: String,
+    pub marker: String,
+}
+
 #[derive(Template)]
 #[template(path = "table.html")]
 struct TableTemplate {
+    pub root_path: &'static str,
+    pub title: Option<String>,
+    pub filter: String,
+    pub raw_filter: String,
+    pub columns: Vec<Column>,
     pub rows: Vec<RowPart>,
+    pub export_link_prefix: String,
+    pub page_path: String,
+    pub downtime_link: String,
+    pub objtype: &'static str,
+    pub selectable: bool,
+    pub live: bool,
+    pub instance_errors: Vec<(String, String)>,
+    pub effective_filter: Option<String>,
+    pub fetched_at: String,
+    pub data_age_s: i64,
+    pub standalone: bool,
+    pub history_link: Option<String>,
+    pub csrf_token: String,
+}
+impl TableTemplate {
+    /// Assembles a table page that is viewed outside of icingcake (e.g. written to a file), i.e.
+    /// without links, forms and scripts.
+    pub fn standalone(
+        query: &TableQuery,
+        title: Option<String>,
+        rows: Vec<RowPart>,
+        errors: &[InstanceError],
+        fetched_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            root_path: "",
+            title,
+            filter: query.normalized_filter(),
+            raw_filter: query.filter.clone(),
+            columns: query.columns.clone(),
+            rows,
+            export_link_prefix: String::new(),
+            page_path: String::new(),
+            downtime_link: String::new(),
+            objtype: query.object_type.plural,
+            selectable: false,
+            live: false,
+            instance_errors: errors.iter()
+                .map(|ie| (ie.instance.clone(), ie.error.to_string()))
+                .collect(),
+            effective_filter: None,
+            fetched_at: fetched_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+            data_age_s: (Utc::now() - fetched_at).num_seconds().max(0),
+            standalone: true,
+            history_link: None,
+            csrf_token: String::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct RowPart {
+    pub instance: String,
+    pub name: String,
     pub host: String,
     pub service: String,
     pub output: String,
     pub state: NagiosState,
+    pub cells: Vec<CellValue>,
 }
 impl PartialOrd for RowPart {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
@@ -80,7 +292,10 @@ impl PartialOrd for RowPart {
             other.state.cmp(&self.state)
                 .then_with(|| self.host.cmp(&other.host))
                 .then_with(|| self.service.cmp(&other.service))
+                .then_with(|| self.name.cmp(&other.name))
+                .then_with(|| self.instance.cmp(&other.instance))
                 .then_with(|| self.output.cmp(&other.output))
+                .then_with(|| self.cells.cmp(&other.cells))
         )
     }
 }
@@ -89,9 +304,218 @@ impl Ord for RowPart {
         self.partial_cmp(other).unwrap()
     }
 }
+impl RowPart {
+    /// The instance and name of the Icinga object represented by this row, e.g.
+    /// `instance:host!service` for services.
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.instance, self.name)
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct TableQuery {
+    pub object_type: &'static ObjectType,
+    pub filter: String,
+    pub parsed_filter: Option<Expression>,
+    pub columns: Vec<Column>,
+    pub sort_keys: Vec<SortKey>,
+
+    /// The filter that objects must additionally match because of the user's roles.
+    pub restriction: Option<Expression>,
+
+    /// The user's own credentials for the Icinga API, if they are passed through.
+    pub credentials: Option<IcingaCredentials>,
+}
+impl TableQuery {
+    /// Assembles a query from its textual representation.
+    pub fn parse<'a>(
+        objtype: &'a str,
+        filter: &str,
+        columns: Option<&'a str>,
+        sort: Option<&'a str>,
+    ) -> Result<Self, TableQueryError<'a>> {
+        let object_type = objtypes::by_plural(objtype)
+            .ok_or(TableQueryError::InvalidParameter { name: "objtype", value: objtype })?;
+
+        // an empty filter matches all objects
+        let parsed_filter = if filter.trim().is_empty() {
+            None
+        } else {
+            let expression = filter::parse(filter)
+                .map_err(|error| TableQueryError::InvalidFilter { error })?;
+            Some(expression)
+        };
+
+        let columns = match columns {
+            Some(c) => Column::parse_list(object_type, c)
+                .map_err(|_| TableQueryError::InvalidParameter { name: "columns", value: c })?,
+            None => Vec::new(),
+        };
+        let columns = if !columns.is_empty() {
+            columns
+        } else {
+            Column::defaults(object_type)
+        };
+
+        let sort_keys = match sort {
+            Some(s) => SortKey::parse_list(object_type, &columns, s)
+                .map_err(|_| TableQueryError::InvalidParameter { name: "sort", value: s })?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            object_type,
+            filter: filter.to_owned(),
+            parsed_filter,
+            columns,
+            sort_keys,
+            restriction: None,
+            credentials: None,
+        })
+    }
+
+    /// Restricts the query to what the given user may see.
+    pub fn apply_permissions(&mut self, permissions: &Permissions) {
+        self.restriction = permissions.restriction.clone();
+        self.credentials = permissions.icinga_credentials.clone();
+    }
+
+    /// Assembles the query of a configured report.
+    ///
+    /// Returns a description of the problem if the report is misconfigured.
+    pub fn from_report(report: &ReportConfig) -> Result<Self, String> {
+        let columns = report.columns.join(",");
+        let sort = report.sort.join(",");
+        Self::parse(&report.objtype, &report.filter, Some(&columns), Some(&sort))
+            .map_err(|e| match e {
+                TableQueryError::InvalidParameter { name, value }
+                    => format!("report {:?} has invalid {} {:?}", report.name, name, value),
+                TableQueryError::InvalidFilter { error }
+                    => format!("report {:?} has invalid filter {:?}: {}", report.name, report.filter, error),
+            })
+    }
+
+    /// The filter in its normalized form.
+    pub fn normalized_filter(&self) -> String {
+        match &self.parsed_filter {
+            Some(pf) => pf.to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// The filter passed to Icinga.
+    ///
+    /// If the query is restricted, the restriction and the parsed filter are combined into a single
+    /// syntax tree, so that the filter entered by the user cannot escape the restriction (e.g. by
+    /// containing unbalanced parentheses).
+    pub fn api_filter(&self) -> String {
+        match (&self.restriction, &self.parsed_filter) {
+            (None, _) => self.filter.clone(),
+            (Some(restriction), None) => restriction.to_string(),
+            (Some(restriction), Some(parsed_filter)) => Expression::Binary {
+                operator: BinaryOperator::LogicalAnd,
+                left: Box::new(restriction.clone()),
+                right: Box::new(parsed_filter.clone()),
+            }.to_string(),
+        }
+    }
+
+    /// Returns a copy of the query that only matches the objects matched by both this query and the
+    /// given expression.
+    ///
+    /// The restriction, the parsed filter and the expression are combined into a single syntax
+    /// tree; combining the filter text instead would break if it ends with a comment.
+    pub fn narrowed(&self, expression: Expression) -> Self {
+        let mut combined = expression;
+        for part in [&self.parsed_filter, &self.restriction].into_iter().flatten() {
+            combined = Expression::Binary {
+                operator: BinaryOperator::LogicalAnd,
+                left: Box::new(part.clone()),
+                right: Box::new(combined),
+            };
+        }
+        let mut query = self.clone();
+        query.filter = combined.to_string();
+        query.parsed_filter = Some(combined);
+        query.restriction = None;
+        query
+    }
+
+    /// Converts an object returned by the API of the given Icinga instance into a row.
+    pub fn row_from_result(&self, instance: &str, result: &serde_json::Value) -> RowPart {
+        let attrs = &result["attrs"];
+        let string_attribute = |attribute: Option<&str>| match attribute {
+            Some(a) => attrs[a].as_str().unwrap_or("").to_owned(),
+            None => String::new(),
+        };
+
+        // the full name, e.g. host!service or host!service!downtime
+        let name = result["name"].as_str().unwrap_or("").to_owned();
+        let host = string_attribute(self.object_type.host_attribute);
+        let service = string_attribute(self.object_type.service_attribute);
+        let output = if self.object_type.output_path.is_empty() {
+            String::new()
+        } else {
+            let mut value = attrs;
+            for segment in self.object_type.output_path {
+                value = &value[*segment];
+            }
+            value.as_str().unwrap_or("").to_owned()
+        };
+        let state = self.object_type.state_attribute
+            .and_then(|a| columns::state_from_json(&attrs[a]))
+            .unwrap_or(NagiosState::Other(5));
+        let cells = self.columns.iter()
+            .map(|column| column.extract(instance, result))
+            .collect();
+        RowPart {
+            instance: instance.to_owned(),
+            name,
+            host,
+            service,
+            output,
+            state,
+            cells,
+        }
+    }
+
+    /// Adds a column showing the Icinga instance in front of the other columns, unless there is one
+    /// already.
+    pub fn add_instance_column(&mut self) {
+        if self.columns.iter().any(|c| c.source == AttributeSource::Instance) {
+            return;
+        }
+        self.columns.insert(0, Column::instance());
+        for sort_key in &mut self.sort_keys {
+            sort_key.column_index += 1;
+        }
+    }
+
+    /// Sorts rows according to the sort keys of this query.
+    pub fn sort_rows(&self, rows: &mut [RowPart]) {
+        if !self.sort_keys.is_empty() {
+            rows.sort_by(|a, b|
+                SortKey::compare(&self.sort_keys, &a.cells, &b.cells)
+                    .then_with(|| a.cmp(b))
+            );
+        } else {
+            rows.sort_unstable();
+        }
+    }
+}
 
+#[derive(Clone, Debug)]
+enum TableQueryError<'a> {
+    InvalidParameter { name: &'static str, value: &'a str },
+    InvalidFilter { error: FilterParseError },
+}
+
+
+/// How long a client may take to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
-static CLIENT: OnceCell<reqwest::Client> = OnceCell::new();
+/// The HTTP clients used to access the Icinga instances, keyed by instance name.
+static CLIENTS: OnceCell<RwLock<HashMap<String, reqwest::Client>>> = OnceCell::new();
 
 
 fn decode_path_parts(path: &str) -> Vec<String> {
@@ -102,6 +526,7 @@ fn decode_path_parts(path: &str) -> Vec<String> {
 }
 
 fn return_500() -> Result<Response<Body>, Infallible> {
+    metrics::INTERNAL_ERRORS.inc();
     Ok(
         Response::builder()
             .status(500)
@@ -122,12 +547,54 @@ async fn handle_plaintext_response<S: Into<String>>(status_code: u16, text_body:
         })
 }
 
+async fn handle_download_response<B: Into<Body>>(content_type: &str, file_name: &str, body: B) -> Result<Response<Body>, Infallible> {
+    Response::builder()
+        .status(200)
+        .header("Content-Type", content_type)
+        .header("Content-Disposition", format!("attachment; filename=\"{}\"", file_name))
+        .body(body.into())
+        .or_else(|e| {
+            error!("failed to construct download response: {}", e);
+            return_500()
+        })
+}
+
 async fn handle_404(_request: Request<Body>) -> Result<Response<Body>, Infallible> {
     handle_plaintext_response(404, "404 Not Found").await
 }
 
-async fn handle_index(_request: Request<Body>) -> Result<Response<Body>, Infallible> {
-    let template = IndexTemplate;
+async fn handle_index(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let (reports, availability) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        let reports = config_guard.reports
+            .iter()
+            .map(|r| ReportLink {
+                url: format!("report/{}", utf8_percent_encode(&r.name, NON_ALPHANUMERIC)),
+                title: r.title.clone().unwrap_or_else(|| r.name.clone()),
+                history_url: if r.history && config_guard.history.is_some() {
+                    Some(format!("history/{}", utf8_percent_encode(&r.name, NON_ALPHANUMERIC)))
+                } else {
+                    None
+                },
+            })
+            .collect();
+        let availability = config_guard.history
+            .as_ref()
+            .map(|h| h.record_availability)
+            .unwrap_or(false);
+        (reports, availability)
+    };
+
+    let logout_csrf_token = request.extensions().get::<auth::AuthenticatedUser>()
+        .map(|_| auth::csrf_token(&request));
+
+    let template = IndexTemplate {
+        reports,
+        availability,
+        logout_csrf_token,
+    };
     let rendered = match template.render() {
         Ok(r) => r,
         Err(e) => {
@@ -181,6 +648,48 @@ async fn handle_400_wrong_parameter(param_name: &str, value: &str) -> Result<Res
     ).await
 }
 
+async fn handle_400_invalid_filter(format: OutputFormat, filter: &str, error: &FilterParseError) -> Result<Response<Body>, Infallible> {
+    if format == OutputFormat::Json {
+        let json_error = serde_json::json!({
+            "filter": filter,
+            "error": error.message,
+            "line": error.line,
+            "column": error.column,
+        });
+        return Response::builder()
+            .status(400)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json_error.to_string()))
+            .or_else(|e| {
+                error!("failed to construct JSON response: {}", e);
+                return_500()
+            });
+    }
+
+    let template = FilterErrorTemplate {
+        message: error.message.clone(),
+        line: error.line,
+        column: error.column,
+        source_line: error.source_line(filter).to_owned(),
+        marker: error.marker(),
+    };
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render filter error template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(400)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
+
 async fn get_required_parameter<'a>(query_pairs: &'a [(Cow<'a, str>, Cow<'a, str>)], key: &str) -> Result<&'a Cow<'a, str>, Result<Response<Body>, Infallible>> {
     let val_opt = query_pairs
         .iter()
@@ -193,22 +702,39 @@ async fn get_required_parameter<'a>(query_pairs: &'a [(Cow<'a, str>, Cow<'a, str
     }
 }
 
+fn get_optional_parameter<'a>(query_pairs: &'a [(Cow<'a, str>, Cow<'a, str>)], key: &str) -> Option<&'a Cow<'a, str>> {
+    query_pairs
+        .iter()
+        .filter(|(k, _v)| k == key)
+        .map(|(_k, v)| v)
+        .last()
+}
+
+async fn get_output_format<'a>(query_pairs: &'a [(Cow<'a, str>, Cow<'a, str>)]) -> Result<OutputFormat, Result<Response<Body>, Infallible>> {
+    match get_optional_parameter(query_pairs, "format") {
+        Some(f) => match OutputFormat::from_parameter(f) {
+            Some(of) => Ok(of),
+            None => Err(handle_400_wrong_parameter("format", f).await),
+        },
+        None => Ok(OutputFormat::Html),
+    }
+}
+
 async fn handle_table(request: Request<Body>) -> Result<Response<Body>, Infallible> {
-    let query_pairs: Vec<(Cow<str>, Cow<str>)> = if let Some(query) = request.uri().query() {
-        form_urlencoded::parse(query.as_bytes())
-            .collect()
-    } else {
-        Vec::new()
+    let permissions = match auth::get_permissions(&request).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
     };
 
+    let query_string = request.uri().query().unwrap_or("");
+    let query_pairs: Vec<(Cow<str>, Cow<str>)> = form_urlencoded::parse(query_string.as_bytes())
+        .collect();
+
     // what are we querying?
     let objtype = match get_required_parameter(&query_pairs, "objtype").await {
         Ok(ot) => ot,
         Err(resp) => return resp,
     };
-    if objtype != "hosts" && objtype != "services" {
-        return handle_400_wrong_parameter("objtype", objtype).await;
-    }
 
     // what's the filter?
     let filter = match get_required_parameter(&query_pairs, "filter").await {
@@ -216,157 +742,313 @@ async fn handle_table(request: Request<Body>) -> Result<Response<Body>, Infallib
         Err(resp) => return resp,
     };
 
-    // build Icinga API JSON body
-    let api_body = serde_json::json!({
-        "filter": filter,
-    });
+    // how should the result be output?
+    let format = match get_output_format(&query_pairs).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+
+    // which columns should be output and how should they be sorted?
+    let columns = get_optional_parameter(&query_pairs, "columns");
+    let sort = get_optional_parameter(&query_pairs, "sort");
 
-    let icinga_config = {
+    let mut query = match TableQuery::parse(objtype, filter, columns.map(|c| &c[..]), sort.map(|s| &s[..])) {
+        Ok(q) => q,
+        Err(TableQueryError::InvalidParameter { name, value }) => return handle_400_wrong_parameter(name, value).await,
+        Err(TableQueryError::InvalidFilter { error }) => return handle_400_invalid_filter(format, filter, &error).await,
+    };
+
+    query.apply_permissions(&permissions);
+
+    let export_link_prefix = format!("table?{}&", query_string);
+    let page_path = format!("table?{}", query_string);
+    respond_table(query, format, "", export_link_prefix, page_path, None, &permissions).await
+}
+
+async fn handle_report(request: Request<Body>, report_name: &str) -> Result<Response<Body>, Infallible> {
+    let permissions = match auth::get_permissions(&request).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+
+    let query_pairs: Vec<(Cow<str>, Cow<str>)> = if let Some(query) = request.uri().query() {
+        form_urlencoded::parse(query.as_bytes())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // how should the result be output?
+    let format = match get_output_format(&query_pairs).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+
+    let report = {
         let config_guard = CONFIG
             .get().expect("CONFIG not set?!")
             .read().await;
-        config_guard.icinga_api.clone()
+        config_guard.reports
+            .iter()
+            .find(|r| r.name == report_name)
+            .cloned()
     };
-    let icinga_url_path = format!("objects/{}", objtype);
-    let icinga_url = match icinga_config.base_url.join(&icinga_url_path) {
-        Ok(u) => u,
-        Err(e) => {
-            error!(
-                "failed to append object type-specific path {:?} to Icinga API base URL {:?}: {}",
-                icinga_url_path, icinga_config.base_url, e,
-            );
-            return return_500();
-        },
+    let report = match report {
+        Some(r) => r,
+        None => return handle_404(request).await,
     };
-    debug!("requesting Icinga URL: {}", icinga_url);
-
-    // contact Icinga
-    let client = CLIENT.get().expect("CLIENT not set?!");
-    let response_res = client
-        .request(Method::POST, icinga_url.clone())
-        .basic_auth(&icinga_config.username, Some(&icinga_config.password))
-        .header("X-HTTP-Method-Override", "GET")
-        .body(serde_json::to_string(&api_body).expect("cannot serialize serde_json::Value to JSON?!"))
-        .send().await;
-    let response = match response_res {
-        Ok(r) => r,
+
+    let mut query = match TableQuery::from_report(&report) {
+        Ok(q) => q,
         Err(e) => {
-            error!("failed to obtain response from {:?}: {}", icinga_url, e);
+            error!("{}", e);
             return return_500();
         },
     };
-    let response_status = response.status();
-    let response_bytes = match response.bytes().await {
-        Ok(rb) => rb,
-        Err(e) => {
-            error!("failed to obtain response bytes from {:?}: {}", icinga_url, e);
+
+    query.apply_permissions(&permissions);
+
+    let encoded_name = utf8_percent_encode(&report.name, NON_ALPHANUMERIC).to_string();
+    let export_link_prefix = format!("{}?", encoded_name);
+    let page_path = format!("report/{}", encoded_name);
+    respond_table(query, format, "../", export_link_prefix, page_path, Some(&report), &permissions).await
+}
+
+async fn respond_table(
+    mut query: TableQuery,
+    format: OutputFormat,
+    root_path: &'static str,
+    export_link_prefix: String,
+    page_path: String,
+    report: Option<&ReportConfig>,
+    permissions: &Permissions,
+) -> Result<Response<Body>, Infallible> {
+    // with multiple instances, show which one each row comes from
+    if icinga::instance_names().await.len() > 1 {
+        query.add_instance_column();
+    }
+
+    let objtype = query.object_type.plural;
+    let filter = query.filter.as_str();
+
+    if format == OutputFormat::Events {
+        return live::handle_events(query).await;
+    }
+
+    let query_result = match icinga::fetch_all_rows(&query, true).await {
+        Ok(qr) => qr,
+        Err(InstanceError { error: QueryError::Icinga { response }, .. }) => return respond_icinga_error(&query, format, &response).await,
+        Err(InstanceError { instance, error }) => {
+            error!("failed to query {} from Icinga instance {:?}: {}", objtype, instance, error);
             return return_500();
         },
     };
+    for instance_error in &query_result.errors {
+        error!("failed to query {} from Icinga instance {:?}: {}", objtype, instance_error.instance, instance_error.error);
+    }
+    let rows = query_result.rows;
+    let columns = &query.columns;
 
-    if response_status == 200 {
-        let response_json: serde_json::Value = match serde_json::from_slice(&response_bytes) {
-            Ok(rj) => rj,
-            Err(e) => {
-                error!("failed to parse response from {:?} as JSON: {}", icinga_url, e);
-                return return_500();
-            },
-        };
-
-        let mut rows = Vec::new();
-        let results = match response_json["results"].as_array() {
-            Some(r) => r,
-            None => {
-                error!("path $.results of {:?} response is not an array but {:?}", icinga_url, response_json["results"]);
-                return return_500();
-            },
-        };
-        for result in results {
-            let host = if objtype == "services" {
-                result["attrs"]["host_name"].as_str().unwrap_or("").to_owned()
-            } else {
-                result["attrs"]["name"].as_str().unwrap_or("").to_owned()
-            };
-            let service = if objtype == "services" {
-                result["attrs"]["name"].as_str().unwrap_or("").to_owned()
-            } else {
-                String::new()
-            };
-            let output = result["attrs"]["last_check_result"]["output"].as_str().unwrap_or("").to_owned();
-            let state_num = result["attrs"]["state"].as_u64().unwrap_or(5).try_into().unwrap_or(6);
-            let state = NagiosState::from(state_num);
-            rows.push(RowPart {
-                host,
-                service,
-                output,
-                state,
-            });
-        }
-
-        rows.sort_unstable();
+    if let Some(report) = report {
+        metrics::record_report(&report.name, &rows, report.state_metrics);
+    }
 
-        let template = TableTemplate {
-            rows,
+    if format == OutputFormat::Csv {
+        let csv = export::rows_to_csv(columns, &rows);
+        return handle_download_response(
+            "text/csv; charset=utf-8; header=present",
+            &format!("icingcake-{}.csv", objtype),
+            csv,
+        ).await;
+    } else if format == OutputFormat::Xlsx || format == OutputFormat::Ods {
+        let (spreadsheet_res, mime_type, extension) = if format == OutputFormat::Xlsx {
+            (spreadsheet::rows_to_xlsx(columns, &rows), spreadsheet::XLSX_MIME_TYPE, "xlsx")
+        } else {
+            (spreadsheet::rows_to_ods(columns, &rows), spreadsheet::ODS_MIME_TYPE, "ods")
         };
-        let rendered = match template.render() {
-            Ok(r) => r,
+        let document = match spreadsheet_res {
+            Ok(s) => s,
             Err(e) => {
-                error!("failed to render table template: {}", e);
+                error!("failed to generate {} spreadsheet: {}", extension, e);
                 return return_500();
             },
         };
-        Response::builder()
+        return handle_download_response(
+            mime_type,
+            &format!("icingcake-{}.{}", objtype, extension),
+            document,
+        ).await;
+    } else if format == OutputFormat::Json {
+        let json_table = export::JsonTable::new(
+            objtype,
+            filter,
+            query_result.fetched_at,
+            query_result.status_code,
+            columns,
+            &rows,
+            &query_result.errors,
+        );
+        let json = serde_json::to_string(&json_table)
+            .expect("failed to serialize JSON table");
+        return Response::builder()
             .status(200)
-            .header("Content-Type", "text/html; charset=utf-8")
-            .body(Body::from(rendered))
+            .header("Content-Type", "application/json")
+            .body(Body::from(json))
             .or_else(|e| {
-                error!("failed to construct HTML response: {}", e);
+                error!("failed to construct JSON response: {}", e);
                 return_500()
-            })
-    } else {
-        let response_string = match String::from_utf8(Vec::from(response_bytes.as_ref())) {
-            Ok(rs) => rs,
-            Err(_) => {
-                let mut string = String::with_capacity(response_bytes.len());
-                for b in response_bytes {
-                    string.push(char::from_u32(b as u32).unwrap());
-                }
-                string
-            },
-        };
+            });
+    }
 
-        let template = IcingaErrorTemplate {
-            status_code: response_status.as_u16(),
-            error_json: response_string,
-        };
-        let rendered = match template.render() {
-            Ok(r) => r,
-            Err(e) => {
-                error!("failed to render error template: {}", e);
-                return return_500();
-            },
+    let history_enabled = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        config_guard.history.is_some()
+    };
+    let history_link = report
+        .filter(|r| r.history && history_enabled)
+        .map(|r| format!("{}history/{}", root_path, utf8_percent_encode(&r.name, NON_ALPHANUMERIC)));
+    let downtime_link = form_urlencoded::Serializer::new(format!("{}downtime?", root_path))
+        .append_pair("objtype", objtype)
+        .append_pair("filter", filter)
+        .finish();
+    let template = TableTemplate {
+        root_path,
+        title: report.map(|r| r.title.clone().unwrap_or_else(|| r.name.clone())),
+        filter: query.normalized_filter(),
+        raw_filter: query.filter.clone(),
+        columns: query.columns.clone(),
+        rows,
+        export_link_prefix,
+        page_path,
+        downtime_link,
+        objtype,
+        selectable: query.object_type.actionable,
+        live: format == OutputFormat::Live,
+        instance_errors: query_result.errors.iter()
+            .map(|ie| (ie.instance.clone(), ie.error.to_string()))
+            .collect(),
+        effective_filter: if permissions.show_effective_filter && query.restriction.is_some() {
+            Some(query.api_filter())
+        } else {
+            None
+        },
+        fetched_at: query_result.oldest_fetched_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+        data_age_s: (Utc::now() - query_result.oldest_fetched_at).num_seconds().max(0),
+        standalone: false,
+        history_link,
+        csrf_token: permissions.csrf_token.clone(),
+    };
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render table template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
+}
+
+async fn respond_icinga_error(query: &TableQuery, format: OutputFormat, response: &ApiResponse) -> Result<Response<Body>, Infallible> {
+    let response_string = response.body_string();
+
+    if format == OutputFormat::Json {
+        // pass on the error as structured data if Icinga gave us any
+        let error_value: serde_json::Value = serde_json::from_str(&response_string)
+            .unwrap_or(serde_json::Value::String(response_string));
+        let json_error = export::JsonIcingaError {
+            objtype: query.object_type.plural,
+            filter: &query.filter,
+            fetched_at: response.received_at,
+            icinga_status: response.status_code,
+            error: error_value,
         };
-        Response::builder()
-            .status(200)
-            .header("Content-Type", "text/html; charset=utf-8")
-            .body(Body::from(rendered))
+        let json = serde_json::to_string(&json_error)
+            .expect("failed to serialize JSON error");
+        return Response::builder()
+            .status(502)
+            .header("Content-Type", "application/json")
+            .body(Body::from(json))
             .or_else(|e| {
-                error!("failed to construct HTML response: {}", e);
+                error!("failed to construct JSON response: {}", e);
                 return_500()
-            })
+            });
     }
+
+    let template = IcingaErrorTemplate {
+        status_code: response.status_code,
+        error_json: response_string,
+    };
+    let rendered = match template.render() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("failed to render error template: {}", e);
+            return return_500();
+        },
+    };
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(rendered))
+        .or_else(|e| {
+            error!("failed to construct HTML response: {}", e);
+            return_500()
+        })
 }
 
-async fn handle_http(request: Request<Body>) -> Result<Response<Body>, Infallible> {
-    let mut path_parts = decode_path_parts(request.uri().path());
-    while path_parts.len() > 0 && path_parts[0].len() == 0 {
-        path_parts.remove(0);
+/// The name of the route handling a path, used to label metrics.
+fn route_name(path_parts: &[String]) -> &'static str {
+    match path_parts {
+        [] => "index",
+        [p] if p == "table" => "table",
+        [p] if p == "acknowledge" => "acknowledge",
+        [p] if p == "downtime" => "downtime",
+        [p] if p == "recheck" => "recheck",
+        [p] if p == "logout" => "logout",
+        [p] if p == "metrics" => "metrics",
+        [p] if p == "availability" => "availability",
+        [p, _] if p == "report" => "report",
+        [p, _] if p == "static" => "static",
+        [p, _] if p == "compare" => "compare",
+        [p, _] if p == "history" => "history",
+        [p, _, _] if p == "history" => "snapshot",
+        _ => "other",
     }
+}
 
+async fn route_request(request: Request<Body>, path_parts: &[String]) -> Result<Response<Body>, Infallible> {
     if path_parts.len() == 0 {
         handle_index(request).await
     } else if &path_parts == &["table"] {
         handle_table(request).await
+    } else if &path_parts == &["acknowledge"] {
+        actions::handle_acknowledge(request).await
+    } else if &path_parts == &["downtime"] {
+        actions::handle_downtime(request).await
+    } else if &path_parts == &["recheck"] {
+        actions::handle_recheck(request).await
+    } else if &path_parts == &["logout"] {
+        auth::handle_logout(request).await
+    } else if &path_parts == &["availability"] {
+        availability::handle_availability(request).await
+    } else if &path_parts == &["metrics"] {
+        metrics::handle_metrics(request).await
+    } else if path_parts.len() == 2 && path_parts[0] == "report" {
+        handle_report(request, &path_parts[1]).await
+    } else if path_parts.len() == 2 && path_parts[0] == "compare" {
+        diff::handle_compare(request, &path_parts[1]).await
+    } else if path_parts.len() == 2 && path_parts[0] == "history" {
+        history::handle_history(request, &path_parts[1]).await
+    } else if path_parts.len() == 3 && path_parts[0] == "history" {
+        history::handle_snapshot(request, &path_parts[1], &path_parts[2]).await
     } else if path_parts.len() == 2 && path_parts[0] == "static" {
         handle_static(request, &path_parts[1]).await
     } else {
@@ -374,47 +1056,291 @@ async fn handle_http(request: Request<Body>) -> Result<Response<Body>, Infallibl
     }
 }
 
+async fn handle_http(request: Request<Body>, remote_addr: SocketAddr) -> Result<Response<Body>, Infallible> {
+    let mut path_parts = decode_path_parts(request.uri().path());
+    while path_parts.len() > 0 && path_parts[0].len() == 0 {
+        path_parts.remove(0);
+    }
+    let route = route_name(&path_parts);
 
-#[tokio::main]
-async fn main() {
-    // parse command line
-    let opts = Opts::parse();
+    let timer = metrics::HTTP_REQUEST_DURATION.with_label_values(&[route]).start_timer();
+    let response = match handle_authenticated(request, &path_parts, remote_addr).await {
+        Ok(r) => r,
+        Err(e) => match e {},
+    };
+    timer.observe_duration();
+    metrics::HTTP_REQUESTS.with_label_values(&[route, response.status().as_str()]).inc();
 
-    // set up tracing
-    let (stdout_non_blocking, _guard) = tracing_appender::non_blocking::NonBlockingBuilder::default()
-        .lossy(false)
-        .finish(std::io::stdout());
-    tracing_subscriber::fmt()
-        .event_format(tracing_subscriber::fmt::format())
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .with_writer(stdout_non_blocking)
-        .init();
+    Ok(response)
+}
+
+async fn handle_authenticated(mut request: Request<Body>, path_parts: &[String], remote_addr: SocketAddr) -> Result<Response<Body>, Infallible> {
+    let new_session_token = match auth::authenticate(&request, remote_addr).await {
+        AuthOutcome::Disabled => None,
+        AuthOutcome::Authenticated { user, new_session_token } => {
+            request.extensions_mut().insert(user);
+            new_session_token
+        },
+        AuthOutcome::Unauthenticated { realm } => {
+            return auth::respond_401(realm.as_deref()).await;
+        },
+    };
+
+    let mut response = match route_request(request, path_parts).await {
+        Ok(r) => r,
+        Err(e) => match e {},
+    };
+    if let Some(token) = new_session_token {
+        auth::add_session_cookie(&mut response, &token).await;
+    }
+    Ok(response)
+}
+
+
+/// Serves HTTP/1.1 and HTTP/2 via TLS.
+///
+/// Each connection is accepted with the TLS configuration current at that time, which is reloaded
+/// when the certificate files change.
+///
+/// Only returns if the server cannot be set up, with a description of the problem.
+async fn serve_https(listen_socket_address: SocketAddr, https_config: HttpsConfig) -> Result<(), String> {
+    let server_config = tls::server_config(&https_config)
+        .map_err(|e| format!("failed to set up TLS: {}", e))?;
+    let current_config = Arc::new(RwLock::new(Arc::new(server_config)));
+    tokio::spawn(tls::watch_server_config(Arc::clone(&current_config), https_config));
+
+    let listener = TcpListener::bind(listen_socket_address).await
+        .map_err(|e| format!("failed to bind listening socket {}: {}", listen_socket_address, e))?;
+    loop {
+        let (tcp_stream, remote_addr) = match listener.accept().await {
+            Ok(sr) => sr,
+            Err(e) => {
+                // e.g. too many open files; don't spin
+                error!("failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            },
+        };
+        let acceptor = TlsAcceptor::from(Arc::clone(&*current_config.read().await));
 
+        tokio::spawn(async move {
+            let tls_stream = match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(tcp_stream)).await {
+                Ok(Ok(ts)) => ts,
+                Ok(Err(e)) => {
+                    debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                    return;
+                },
+                Err(_) => {
+                    debug!("TLS handshake with {} timed out", remote_addr);
+                    return;
+                },
+            };
+            let service = service_fn(move |request| handle_http(request, remote_addr));
+            if let Err(e) = Http::new().serve_connection(tls_stream, service).await {
+                debug!("error serving connection from {}: {}", remote_addr, e);
+            }
+        });
+    }
+}
+
+
+/// Loads the configuration and creates the HTTP clients for the Icinga instances.
+///
+/// Returns a description of the problem if this fails.
+fn initialize(config_path: PathBuf) -> Result<(), String> {
     // store config path
-    CONFIG_PATH.set(opts.config_path).expect("CONFIG_PATH already set?!");
+    CONFIG_PATH.set(config_path).expect("CONFIG_PATH already set?!");
 
     // load config
-    let config = config::load().expect("failed to load config");
-    let listen_socket_address = config.http_server.listen_socket_address;
-    let api_timeout = config.icinga_api.timeout_s;
-    let allow_invalid_certs = config.icinga_api.allow_invalid_certs;
+    let config = config::load()
+        .map_err(|e| format!("failed to load config: {}", e))?;
+    let instances = config.instances();
     CONFIG.set(RwLock::new(config)).expect("CONFIG already set?!");
 
-    // create HTTP client
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(api_timeout))
-        .danger_accept_invalid_certs(allow_invalid_certs)
-        .build()
-        .expect("failed to initialize HTTP client");
-    CLIENT.set(client).expect("CLIENT already set?!");
-
-    // create HTTP server
-    let make_service = make_service_fn(|_conn| async {
-        Ok::<_, Infallible>(service_fn(handle_http))
+    // create HTTP clients
+    let mut clients = HashMap::new();
+    for instance in instances {
+        let client = icinga::build_client(&instance.api)
+            .map_err(|e| format!("failed to initialize HTTP client for Icinga instance {:?}: {}", instance.name, e))?;
+        clients.insert(instance.name, client);
+    }
+    CLIENTS.set(RwLock::new(clients)).expect("CLIENTS already set?!");
+    Ok(())
+}
+
+async fn serve(opts: ServeOpts) -> ExitCode {
+    if let Err(e) = initialize(opts.config_path) {
+        error!("{}", e);
+        return ExitCode::FAILURE;
+    }
+    let (listen_socket_address, https_config) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        (config_guard.http_server.listen_socket_address, config_guard.http_server.tls.clone())
+    };
+
+    // reload config when asked to
+    tokio::spawn(reload::reload_on_sighup());
+    if opts.watch_config {
+        tokio::spawn(reload::reload_on_change());
+    }
+
+    // send scheduled reports
+    tokio::spawn(schedule::run_schedules());
+
+    // record snapshots of reports
+    tokio::spawn(history::record_snapshots());
+    tokio::spawn(availability::record_availability());
+
+    // create HTTP(S) server
+    if let Some(https_config) = https_config {
+        if let Err(e) = serve_https(listen_socket_address, https_config).await {
+            error!("{}", e);
+        }
+        return ExitCode::FAILURE;
+    }
+    let make_service = make_service_fn(|conn: &AddrStream| {
+        let remote_addr = conn.remote_addr();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle_http(request, remote_addr)))
+        }
     });
     let server = Server::bind(&listen_socket_address).serve(make_service);
 
     if let Err(e) = server.await {
         error!("server error: {}", e);
     }
+    ExitCode::FAILURE
+}
+
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // parse command line
+    let opts = Opts::parse();
+    let command = opts.command.unwrap_or(Command::Serve(opts.serve));
+
+    // set up tracing; only the server logs to stdout, since the other commands may output to it
+    let non_blocking_builder = tracing_appender::non_blocking::NonBlockingBuilder::default()
+        .lossy(false);
+    let (non_blocking, _guard) = if let Command::Serve(_) = &command {
+        non_blocking_builder.finish(std::io::stdout())
+    } else {
+        non_blocking_builder.finish(std::io::stderr())
+    };
+    tracing_subscriber::fmt()
+        .event_format(tracing_subscriber::fmt::format())
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(non_blocking)
+        .init();
+
+    match command {
+        Command::Serve(serve_opts) => serve(serve_opts).await,
+        Command::Report(report_opts) => cli::run_report(report_opts).await,
+        Command::CheckConfig(config_opts) => cli::check_config(config_opts).await,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restricted_query(restriction: &str, filter: &str) -> TableQuery {
+        let mut query = TableQuery::parse("services", filter, None, None).unwrap();
+        query.restriction = Some(filter::parse(restriction).unwrap());
+        query
+    }
+
+    /// Asserts that the filter passed to Icinga is the restriction ANDed with the user's filter.
+    fn assert_restricted(restriction: &str, filter: &str) {
+        let query = restricted_query(restriction, filter);
+        let expected = Expression::Binary {
+            operator: BinaryOperator::LogicalAnd,
+            left: Box::new(filter::parse(restriction).unwrap()),
+            right: Box::new(filter::parse(filter).unwrap()),
+        };
+        let api_filter = query.api_filter();
+        assert_eq!(filter::parse(&api_filter).unwrap(), expected, "{:?}", api_filter);
+    }
+
+    #[test]
+    fn test_api_filter_unrestricted() {
+        for filter in ["", "  ", "host.name == \"web01\"", "a ||  b // comment", "service.state != 0\n&& !service.acknowledgement"] {
+            let query = TableQuery::parse("services", filter, None, None).unwrap();
+            assert_eq!(query.api_filter(), filter);
+        }
+    }
+
+    #[test]
+    fn test_api_filter_restricted() {
+        let restriction = "\"team-db\" in host.groups";
+        assert_restricted(restriction, "service.state == 2");
+        assert_restricted(restriction, "service.state == 2 || true");
+        assert_restricted(restriction, "true || service.state == 2 && false");
+        assert_restricted(restriction, "(true) || (true)");
+        assert_restricted(restriction, "host.name == \"x\\\") || (\\\"y\"");
+        assert_restricted(restriction, "host.name == {{{x\") || true || (\"}}}");
+        assert_restricted(restriction, "match(\"*\", host.name) || true // \")");
+        assert_restricted(restriction, "true /* ) || ( */ || true");
+
+        assert_eq!(
+            restricted_query(restriction, "service.state == 2 || true").api_filter(),
+            "\"team-db\" in host.groups && (service.state == 2 || true)",
+        );
+    }
+
+    #[test]
+    fn test_api_filter_restriction_with_or() {
+        // restrictions of multiple roles are joined with ||
+        let restriction = "\"team-db\" in host.groups || \"team-web\" in host.groups";
+        assert_restricted(restriction, "service.state == 2");
+        assert_restricted(restriction, "service.state == 2 || true");
+        assert_eq!(
+            restricted_query(restriction, "true").api_filter(),
+            "(\"team-db\" in host.groups || \"team-web\" in host.groups) && true",
+        );
+    }
+
+    #[test]
+    fn test_api_filter_restriction_without_filter() {
+        let query = restricted_query("\"team-db\" in host.groups", "");
+        assert_eq!(query.api_filter(), "\"team-db\" in host.groups");
+    }
+
+    #[test]
+    fn test_narrowed() {
+        let narrowing = filter::parse("host.name == \"web01\"").unwrap();
+
+        let query = TableQuery::parse("services", "", None, None).unwrap();
+        assert_eq!(query.narrowed(narrowing.clone()).api_filter(), "host.name == \"web01\"");
+
+        for filter in ["service.state != 0 // problems only", "service.state != 0 # problems only"] {
+            let query = TableQuery::parse("services", filter, None, None).unwrap();
+            assert_eq!(
+                query.narrowed(narrowing.clone()).api_filter(),
+                "service.state != 0 && host.name == \"web01\"",
+            );
+        }
+
+        let query = restricted_query("\"team-db\" in host.groups", "service.state == 2 || true // )");
+        let narrowed = query.narrowed(narrowing);
+        assert_eq!(narrowed.restriction, None);
+        assert_eq!(
+            narrowed.api_filter(),
+            "\"team-db\" in host.groups && ((service.state == 2 || true) && host.name == \"web01\")",
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_filters_rejected() {
+        for filter in ["true) || (true", "true) || true", "(true", "\"x\" || \"y", "true || true)"] {
+            assert!(
+                matches!(TableQuery::parse("services", filter, None, None), Err(TableQueryError::InvalidFilter { .. })),
+                "{:?} was accepted",
+                filter,
+            );
+        }
+    }
 }
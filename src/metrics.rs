@@ -0,0 +1,114 @@
+//! Metrics about icingcake itself, exported in the Prometheus text format via `/metrics`.
+
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+
+use hyper::{Body, Request, Response};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, register_histogram_vec,
+    register_int_counter, register_int_counter_vec, register_int_gauge_vec, TextEncoder,
+};
+use tracing::error;
+
+use crate::{return_500, RowPart};
+
+
+/// HTTP requests handled, by route and status code.
+pub(crate) static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "icingcake_http_requests_total",
+    "HTTP requests handled, by route and status code.",
+    &["route", "status"]
+).expect("failed to register metric"));
+
+/// Time taken to handle HTTP requests, by route.
+pub(crate) static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| register_histogram_vec!(
+    "icingcake_http_request_duration_seconds",
+    "Time taken to handle HTTP requests, by route.",
+    &["route"]
+).expect("failed to register metric"));
+
+/// Internal server errors, e.g. failures to render a template.
+pub(crate) static INTERNAL_ERRORS: Lazy<IntCounter> = Lazy::new(|| register_int_counter!(
+    "icingcake_internal_errors_total",
+    "Requests answered with 500 Internal Server Error, e.g. because a template failed to render."
+).expect("failed to register metric"));
+
+/// Icinga API calls, by instance and status code (`error` if no complete response was obtained).
+pub(crate) static ICINGA_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "icingcake_icinga_requests_total",
+    "Icinga API calls, by instance and status code (error if no complete response was obtained).",
+    &["instance", "status"]
+).expect("failed to register metric"));
+
+/// Time until Icinga API calls are answered with response headers, by instance.
+pub(crate) static ICINGA_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| register_histogram_vec!(
+    "icingcake_icinga_request_duration_seconds",
+    "Time until Icinga API calls are answered with response headers, by instance.",
+    &["instance"]
+).expect("failed to register metric"));
+
+/// Object queries, by how they were answered by the cache.
+pub(crate) static CACHE_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "icingcake_cache_requests_total",
+    "Object queries, by how they were answered: hit (fresh cached response), coalesced (response to an identical query in flight) or miss (Icinga API called).",
+    &["result"]
+).expect("failed to register metric"));
+
+/// Rows shown by each report when it was last generated.
+pub(crate) static REPORT_ROWS: Lazy<IntGaugeVec> = Lazy::new(|| register_int_gauge_vec!(
+    "icingcake_report_rows",
+    "Rows shown by each report when it was last generated.",
+    &["report"]
+).expect("failed to register metric"));
+
+/// Objects shown by each report when it was last generated, by state.
+pub(crate) static REPORT_OBJECTS_BY_STATE: Lazy<IntGaugeVec> = Lazy::new(|| register_int_gauge_vec!(
+    "icingcake_report_objects",
+    "Objects shown by each report when it was last generated, by state (only for reports with state_metrics enabled).",
+    &["report", "state"]
+).expect("failed to register metric"));
+
+
+/// Records the rows of a generated report.
+///
+/// If `by_state` is set, the rows are also counted by state.
+pub(crate) fn record_report(report_name: &str, rows: &[RowPart], by_state: bool) {
+    REPORT_ROWS.with_label_values(&[report_name]).set(rows.len() as i64);
+
+    if by_state {
+        let mut state_counts: BTreeMap<String, i64> = BTreeMap::new();
+        for row in rows {
+            *state_counts.entry(row.state.to_string()).or_insert(0) += 1;
+        }
+
+        // states that have disappeared must be reset to 0 rather than keep their last value
+        for state in ["Ok", "Warning", "Critical", "Unknown"] {
+            state_counts.entry(state.to_owned()).or_insert(0);
+        }
+        for (state, count) in state_counts {
+            REPORT_OBJECTS_BY_STATE.with_label_values(&[report_name, &state]).set(count);
+        }
+    }
+}
+
+
+/// Outputs all metrics in the Prometheus text format.
+pub(crate) async fn handle_metrics(_request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("failed to encode metrics: {}", e);
+        return return_500();
+    }
+
+    Response::builder()
+        .status(200)
+        .header("Content-Type", encoder.format_type())
+        .body(Body::from(buffer))
+        .or_else(|e| {
+            error!("failed to construct metrics response: {}", e);
+            return_500()
+        })
+}
@@ -0,0 +1,182 @@
+//! Descriptions of the Icinga object types that can be queried.
+
+
+/// An Icinga object type that can be queried via `objects/<plural>`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct ObjectType {
+    /// The plural name, as used in the URL of the Icinga API endpoint.
+    pub plural: &'static str,
+
+    /// The singular name, as used in filter expressions and joins.
+    pub singular: &'static str,
+
+    /// The name of the type, as passed in the `type` field of actions.
+    pub type_name: &'static str,
+
+    /// The object types that can be joined to this one.
+    pub joins: &'static [&'static str],
+
+    /// Whether actions such as acknowledging problems can be performed on objects of this type.
+    pub actionable: bool,
+
+    /// The columns shown if the user does not choose any.
+    pub default_columns: &'static [&'static str],
+
+    /// The attributes that are always requested to identify the object and obtain its state.
+    pub row_attributes: &'static [&'static str],
+
+    /// The attribute containing the name of the host the object belongs to, if any.
+    pub host_attribute: Option<&'static str>,
+
+    /// The attribute containing the name of the service the object belongs to, if any.
+    pub service_attribute: Option<&'static str>,
+
+    /// The path to the attribute containing the object's textual output, if any.
+    pub output_path: &'static [&'static str],
+
+    /// The attribute containing the object's state, if it has one.
+    pub state_attribute: Option<&'static str>,
+}
+
+
+/// All the object types that can be queried.
+pub(crate) const OBJECT_TYPES: &[ObjectType] = &[
+    ObjectType {
+        plural: "hosts",
+        singular: "host",
+        type_name: "Host",
+        joins: &[],
+        actionable: true,
+        default_columns: &["host.name", "state", "last_check_result.output"],
+        row_attributes: &["name", "state", "last_check_result"],
+        host_attribute: Some("name"),
+        service_attribute: None,
+        output_path: &["last_check_result", "output"],
+        state_attribute: Some("state"),
+    },
+    ObjectType {
+        plural: "services",
+        singular: "service",
+        type_name: "Service",
+        joins: &["host"],
+        actionable: true,
+        default_columns: &["host.name", "service.name", "state", "last_check_result.output"],
+        row_attributes: &["name", "host_name", "state", "last_check_result"],
+        host_attribute: Some("host_name"),
+        service_attribute: Some("name"),
+        output_path: &["last_check_result", "output"],
+        state_attribute: Some("state"),
+    },
+    ObjectType {
+        plural: "downtimes",
+        singular: "downtime",
+        type_name: "Downtime",
+        joins: &["host", "service"],
+        actionable: false,
+        default_columns: &["host_name", "service_name", "author", "comment", "start_time", "end_time", "fixed", "trigger_time"],
+        row_attributes: &["host_name", "service_name", "comment"],
+        host_attribute: Some("host_name"),
+        service_attribute: Some("service_name"),
+        output_path: &["comment"],
+        state_attribute: None,
+    },
+    ObjectType {
+        plural: "comments",
+        singular: "comment",
+        type_name: "Comment",
+        joins: &["host", "service"],
+        actionable: false,
+        default_columns: &["host_name", "service_name", "author", "text", "entry_time", "expire_time"],
+        row_attributes: &["host_name", "service_name", "text"],
+        host_attribute: Some("host_name"),
+        service_attribute: Some("service_name"),
+        output_path: &["text"],
+        state_attribute: None,
+    },
+    ObjectType {
+        plural: "notifications",
+        singular: "notification",
+        type_name: "Notification",
+        joins: &["host", "service"],
+        actionable: false,
+        default_columns: &["host_name", "service_name", "name", "users", "user_groups", "last_notification", "notification_number"],
+        row_attributes: &["host_name", "service_name"],
+        host_attribute: Some("host_name"),
+        service_attribute: Some("service_name"),
+        output_path: &[],
+        state_attribute: None,
+    },
+    ObjectType {
+        plural: "users",
+        singular: "user",
+        type_name: "User",
+        joins: &[],
+        actionable: false,
+        default_columns: &["name", "display_name", "email", "groups", "enable_notifications"],
+        row_attributes: &[],
+        host_attribute: None,
+        service_attribute: None,
+        output_path: &[],
+        state_attribute: None,
+    },
+    ObjectType {
+        plural: "hostgroups",
+        singular: "hostgroup",
+        type_name: "HostGroup",
+        joins: &[],
+        actionable: false,
+        default_columns: &["name", "display_name", "groups"],
+        row_attributes: &[],
+        host_attribute: None,
+        service_attribute: None,
+        output_path: &[],
+        state_attribute: None,
+    },
+    ObjectType {
+        plural: "servicegroups",
+        singular: "servicegroup",
+        type_name: "ServiceGroup",
+        joins: &[],
+        actionable: false,
+        default_columns: &["name", "display_name", "groups"],
+        row_attributes: &[],
+        host_attribute: None,
+        service_attribute: None,
+        output_path: &[],
+        state_attribute: None,
+    },
+    ObjectType {
+        plural: "checkcommands",
+        singular: "checkcommand",
+        type_name: "CheckCommand",
+        joins: &[],
+        actionable: false,
+        default_columns: &["name", "command", "timeout"],
+        row_attributes: &[],
+        host_attribute: None,
+        service_attribute: None,
+        output_path: &[],
+        state_attribute: None,
+    },
+    ObjectType {
+        plural: "zones",
+        singular: "zone",
+        type_name: "Zone",
+        joins: &[],
+        actionable: false,
+        default_columns: &["name", "parent", "endpoints", "global"],
+        row_attributes: &[],
+        host_attribute: None,
+        service_attribute: None,
+        output_path: &[],
+        state_attribute: None,
+    },
+];
+
+
+/// Returns the object type with the given plural name, if it exists.
+pub(crate) fn by_plural(plural: &str) -> Option<&'static ObjectType> {
+    OBJECT_TYPES
+        .iter()
+        .find(|ot| ot.plural == plural)
+}
@@ -0,0 +1,142 @@
+//! Reloading the configuration while icingcake is running.
+
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use notify::{RecursiveMode, Watcher};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::CLIENTS;
+use crate::config::{self, CONFIG, CONFIG_PATH};
+use crate::icinga;
+
+
+/// How long to wait for further changes after the configuration file has changed, since editors
+/// often write a file in multiple steps.
+const CHANGE_SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+
+/// Reloads the configuration file and swaps it in along with the HTTP clients for the Icinga
+/// instances.
+///
+/// If the new configuration cannot be loaded or a client cannot be built from it, the previous
+/// configuration remains in effect. Clients of instances whose API configuration has not changed
+/// are kept.
+pub(crate) async fn reload() {
+    let new_config = match config::load() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("failed to reload configuration; keeping the previous one: {}", e);
+            return;
+        },
+    };
+
+    let mut config_guard = CONFIG
+        .get().expect("CONFIG not set?!")
+        .write().await;
+    let mut clients_guard = CLIENTS
+        .get().expect("CLIENTS not set?!")
+        .write().await;
+
+    let mut new_clients = HashMap::new();
+    for instance in new_config.instances() {
+        let unchanged_client = config_guard.instance(&instance.name)
+            .filter(|old_instance| old_instance.api == instance.api)
+            .and_then(|_| clients_guard.get(&instance.name).cloned());
+        let client = match unchanged_client {
+            Some(c) => c,
+            None => match icinga::build_client(&instance.api) {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("failed to initialize HTTP client for Icinga instance {:?}; keeping the previous configuration: {}", instance.name, e);
+                    return;
+                },
+            },
+        };
+        new_clients.insert(instance.name, client);
+    }
+
+    if new_config.http_server != config_guard.http_server {
+        warn!("changes to http_server only take effect after a restart");
+    }
+
+    *clients_guard = new_clients;
+    *config_guard = new_config;
+    info!("configuration reloaded");
+}
+
+
+/// Reloads the configuration whenever the process receives SIGHUP.
+pub(crate) async fn reload_on_sighup() {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("failed to listen for SIGHUP: {}", e);
+            return;
+        },
+    };
+    while let Some(()) = hangups.recv().await {
+        info!("SIGHUP received; reloading configuration");
+        reload().await;
+    }
+}
+
+
+/// Reloads the configuration whenever the configuration file changes.
+///
+/// The directory containing the file is watched, since editors and configuration management tools
+/// often replace the file instead of writing to it.
+pub(crate) async fn reload_on_change() {
+    let config_path = CONFIG_PATH.get().expect("CONFIG_PATH not set?!");
+    let config_path = match config_path.canonicalize() {
+        Ok(cp) => cp,
+        Err(e) => {
+            error!("failed to resolve path of configuration file {}: {}", config_path.display(), e);
+            return;
+        },
+    };
+    let config_dir = match config_path.parent() {
+        Some(cd) => cd.to_owned(),
+        None => {
+            error!("configuration file {} has no parent directory", config_path.display());
+            return;
+        },
+    };
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let watched_path = config_path.clone();
+    let watcher_res = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        match res {
+            Ok(event) => {
+                if event.paths.contains(&watched_path) {
+                    // the receiver only goes away when the program ends
+                    let _ = sender.send(());
+                }
+            },
+            Err(e) => error!("error watching configuration file: {}", e),
+        }
+    });
+    let mut watcher = match watcher_res {
+        Ok(w) => w,
+        Err(e) => {
+            error!("failed to watch configuration file: {}", e);
+            return;
+        },
+    };
+    if let Err(e) = watcher.watch(&config_dir, RecursiveMode::NonRecursive) {
+        error!("failed to watch directory {}: {}", config_dir.display(), e);
+        return;
+    }
+
+    while let Some(()) = receiver.recv().await {
+        // let the changes settle, then reload once
+        tokio::time::sleep(CHANGE_SETTLE_DELAY).await;
+        while receiver.try_recv().is_ok() {
+        }
+        info!("configuration file changed; reloading configuration");
+        reload().await;
+    }
+}
@@ -0,0 +1,246 @@
+//! Sending reports via e-mail on a schedule.
+
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use askama::Template;
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use tracing::{debug, error, info, warn};
+
+use crate::{TableQuery, TableTemplate};
+use crate::config::{CONFIG, ScheduledReportConfig, SmtpConfig, SmtpTlsMode};
+use crate::export;
+use crate::icinga::{self, InstanceError, QueryError};
+
+
+/// The longest time to sleep between checks for due reports, so that changes to the schedules
+/// made by reloading the configuration are picked up in time.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+
+/// An error that may occur when generating or sending a scheduled report.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum DeliveryError {
+    /// The report or the SMTP server is no longer configured.
+    #[non_exhaustive] NotConfigured { description: String },
+
+    /// The report's query is invalid.
+    #[non_exhaustive] Query { description: String },
+
+    /// The objects could not be queried from Icinga.
+    #[non_exhaustive] Fetch { instance: String, error: Box<QueryError> },
+
+    /// The report could not be rendered.
+    #[non_exhaustive] Render { error: askama::Error },
+
+    /// An e-mail address is invalid.
+    #[non_exhaustive] Address { address: String, error: lettre::address::AddressError },
+
+    /// The e-mail could not be assembled.
+    #[non_exhaustive] Message { error: lettre::error::Error },
+
+    /// The e-mail could not be delivered to the SMTP server.
+    #[non_exhaustive] Smtp { error: lettre::transport::smtp::Error },
+}
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConfigured { description, .. }
+                => write!(f, "{}", description),
+            Self::Query { description, .. }
+                => write!(f, "{}", description),
+            Self::Fetch { instance, error, .. }
+                => write!(f, "failed to query Icinga instance {:?}: {}", instance, error),
+            Self::Render { error, .. }
+                => write!(f, "failed to render table template: {}", error),
+            Self::Address { address, error, .. }
+                => write!(f, "invalid e-mail address {:?}: {}", address, error),
+            Self::Message { error, .. }
+                => write!(f, "failed to assemble e-mail: {}", error),
+            Self::Smtp { error, .. }
+                => write!(f, "failed to send e-mail: {}", error),
+        }
+    }
+}
+impl std::error::Error for DeliveryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::NotConfigured { .. } => None,
+            Self::Query { .. } => None,
+            Self::Fetch { error, .. } => Some(error.as_ref()),
+            Self::Render { error, .. } => Some(error),
+            Self::Address { error, .. } => Some(error),
+            Self::Message { error, .. } => Some(error),
+            Self::Smtp { error, .. } => Some(error),
+        }
+    }
+}
+
+
+/// Parses an e-mail address, including an optional display name.
+fn parse_mailbox(address: &str) -> Result<Mailbox, DeliveryError> {
+    address.parse()
+        .map_err(|error| DeliveryError::Address { address: address.to_owned(), error })
+}
+
+
+/// Builds the transport through which e-mail is sent to the configured SMTP server.
+fn build_transport(smtp: &SmtpConfig) -> Result<AsyncSmtpTransport<Tokio1Executor>, DeliveryError> {
+    let mut builder = match smtp.tls {
+        SmtpTlsMode::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.server)
+            .map_err(|error| DeliveryError::Smtp { error })?,
+        SmtpTlsMode::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.server)
+            .map_err(|error| DeliveryError::Smtp { error })?,
+        SmtpTlsMode::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.server),
+    };
+    if let Some(port) = smtp.port {
+        builder = builder.port(port);
+    }
+    if let Some(username) = &smtp.username {
+        let password = smtp.password.clone().unwrap_or_default();
+        builder = builder.credentials(Credentials::new(username.clone(), password));
+    }
+    Ok(builder.build())
+}
+
+
+/// Generates a scheduled report and sends it to its recipients.
+///
+/// Returns whether the report was sent; it is not sent if it is empty and `skip_if_empty` is set.
+/// Instances that cannot be queried are listed in the report like on the web interface.
+async fn generate_and_send(scheduled: &ScheduledReportConfig) -> Result<bool, DeliveryError> {
+    let (report, smtp) = {
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!")
+            .read().await;
+        let report = config_guard.reports
+            .iter()
+            .find(|r| r.name == scheduled.report)
+            .cloned();
+        (report, config_guard.smtp.clone())
+    };
+    let report = report
+        .ok_or_else(|| DeliveryError::NotConfigured { description: format!("no report named {:?} is configured", scheduled.report) })?;
+    let smtp = smtp
+        .ok_or_else(|| DeliveryError::NotConfigured { description: "smtp is not configured".to_owned() })?;
+
+    let mut query = TableQuery::from_report(&report)
+        .map_err(|description| DeliveryError::Query { description })?;
+    if icinga::instance_names().await.len() > 1 {
+        query.add_instance_column();
+    }
+
+    let query_result = icinga::fetch_all_rows(&query, false).await
+        .map_err(|InstanceError { instance, error }| DeliveryError::Fetch { instance, error: Box::new(error) })?;
+    for instance_error in &query_result.errors {
+        warn!(
+            "scheduled report {:?}: failed to query {} from Icinga instance {:?}: {}",
+            report.name, query.object_type.plural, instance_error.instance, instance_error.error,
+        );
+    }
+    if scheduled.skip_if_empty && query_result.rows.is_empty() {
+        return Ok(false);
+    }
+
+    let title = report.title.clone().unwrap_or_else(|| report.name.clone());
+    let subject = scheduled.subject.clone()
+        .unwrap_or_else(|| format!("{} ({})", title, Local::now().format("%Y-%m-%d")));
+    let csv = export::rows_to_csv(&query.columns, &query_result.rows);
+    let template = TableTemplate::standalone(
+        &query,
+        Some(title),
+        query_result.rows,
+        &query_result.errors,
+        query_result.oldest_fetched_at,
+    );
+    let html = template.render()
+        .map_err(|error| DeliveryError::Render { error })?;
+
+    let mut builder = Message::builder()
+        .from(parse_mailbox(&smtp.from)?)
+        .subject(subject);
+    for recipient in &scheduled.recipients {
+        builder = builder.to(parse_mailbox(recipient)?);
+    }
+    let csv_type = ContentType::parse("text/csv; charset=utf-8")
+        .expect("failed to parse CSV content type");
+    let message = builder
+        .multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::html(html))
+                .singlepart(Attachment::new(format!("{}.csv", report.name)).body(csv, csv_type))
+        )
+        .map_err(|error| DeliveryError::Message { error })?;
+
+    build_transport(&smtp)?
+        .send(message).await
+        .map_err(|error| DeliveryError::Smtp { error })?;
+    Ok(true)
+}
+
+
+/// Generates and sends a scheduled report, logging the outcome.
+async fn deliver(scheduled: ScheduledReportConfig) {
+    match generate_and_send(&scheduled).await {
+        Ok(true) => info!("scheduled report {:?} sent to {:?}", scheduled.report, scheduled.recipients),
+        Ok(false) => info!("scheduled report {:?} is empty; not sending it", scheduled.report),
+        Err(e) => error!("failed to deliver scheduled report {:?} to {:?}: {}", scheduled.report, scheduled.recipients, e),
+    }
+}
+
+
+/// Sends the scheduled reports whenever they are due.
+///
+/// The schedules are taken from the current configuration at each check, so reloading the
+/// configuration changes them without a restart. Reports that became due while icingcake was not
+/// running are not sent.
+pub(crate) async fn run_schedules() {
+    let mut last_check: DateTime<Local> = Local::now();
+    loop {
+        let scheduled_reports = {
+            let config_guard = CONFIG
+                .get().expect("CONFIG not set?!")
+                .read().await;
+            config_guard.scheduled_reports.clone()
+        };
+
+        let now = Local::now();
+        let mut next_run: Option<DateTime<Local>> = None;
+        for scheduled in scheduled_reports {
+            let schedule = match Schedule::from_str(&scheduled.schedule) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("invalid schedule {:?} for report {:?}: {}", scheduled.schedule, scheduled.report, e);
+                    continue;
+                },
+            };
+
+            if let Some(next) = schedule.after(&now).next() {
+                next_run = Some(next_run.map_or(next, |nr| nr.min(next)));
+            }
+            let due = schedule.after(&last_check)
+                .next()
+                .map(|time| time <= now)
+                .unwrap_or(false);
+            if due {
+                debug!("scheduled report {:?} is due", scheduled.report);
+                tokio::spawn(deliver(scheduled));
+            }
+        }
+        last_check = now;
+
+        let sleep_duration = next_run
+            .and_then(|nr| (nr - Local::now()).to_std().ok())
+            .map(|d| d.min(MAX_SLEEP))
+            .unwrap_or(MAX_SLEEP);
+        tokio::time::sleep(sleep_duration).await;
+    }
+}
@@ -0,0 +1,641 @@
+//! Exporting table rows as spreadsheet documents (Office Open XML and OpenDocument).
+
+
+use std::fmt;
+use std::io::{self, Cursor, Write};
+
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use rust_xlsxwriter::{Color, ExcelDateTime, Format, Workbook, XlsxError};
+use zip::{CompressionMethod, ZipWriter};
+use zip::result::ZipError;
+use zip::write::FileOptions;
+
+use crate::{NagiosState, RowPart};
+use crate::columns::{CellValue, Column};
+
+
+/// The name of the single worksheet in each generated document.
+const SHEET_NAME: &str = "icingcake";
+
+/// The number format of timestamp cells in XLSX documents.
+const XLSX_DATETIME_FORMAT: &str = "yyyy-mm-dd hh:mm:ss";
+
+/// The MIME type of an OpenDocument spreadsheet.
+pub(crate) const ODS_MIME_TYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+
+/// The MIME type of an Office Open XML spreadsheet.
+pub(crate) const XLSX_MIME_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+
+/// An error that may occur when generating a spreadsheet.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum SpreadsheetError {
+    #[non_exhaustive] Xlsx { error: XlsxError },
+    #[non_exhaustive] Zip { error: ZipError },
+    #[non_exhaustive] Io { error: io::Error },
+}
+impl fmt::Display for SpreadsheetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Xlsx { error, .. }
+                => write!(f, "error generating XLSX document: {}", error),
+            Self::Zip { error, .. }
+                => write!(f, "error generating ZIP archive: {}", error),
+            Self::Io { error, .. }
+                => write!(f, "I/O error: {}", error),
+        }
+    }
+}
+impl std::error::Error for SpreadsheetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Xlsx { error, .. } => Some(error),
+            Self::Zip { error, .. } => Some(error),
+            Self::Io { error, .. } => Some(error),
+        }
+    }
+}
+impl From<XlsxError> for SpreadsheetError {
+    fn from(error: XlsxError) -> Self { Self::Xlsx { error } }
+}
+impl From<ZipError> for SpreadsheetError {
+    fn from(error: ZipError) -> Self { Self::Zip { error } }
+}
+impl From<io::Error> for SpreadsheetError {
+    fn from(error: io::Error) -> Self { Self::Io { error } }
+}
+
+
+/// Returns the background color of a state cell as 0xRRGGBB.
+///
+/// Keep this in sync with the `td.state` styles in `base.html`.
+fn state_background_color(state: NagiosState) -> u32 {
+    match state {
+        NagiosState::Ok => 0x96f9c0,
+        NagiosState::Warning => 0xffe0a4,
+        NagiosState::Critical => 0xffcdd5,
+        NagiosState::Unknown => 0xe7bfff,
+        NagiosState::Other(_) => 0xb5ffff,
+    }
+}
+
+/// Returns the name of the ODS cell style for the given state.
+fn ods_state_style_name(state: NagiosState) -> String {
+    match state {
+        NagiosState::Other(_) => "state-other".to_owned(),
+        other => format!("state-{}", other.to_base_type()),
+    }
+}
+
+
+/// Returns the local date and time of a timestamp, which is how timestamps are shown in tables.
+fn local_datetime(timestamp: &DateTime<Utc>) -> NaiveDateTime {
+    timestamp.with_timezone(&Local).naive_local()
+}
+
+
+/// Returns the spreadsheet-style letters of the column with the given zero-based index.
+fn column_letters(index: usize) -> String {
+    let mut letters = Vec::new();
+    let mut remaining = index + 1;
+    while remaining > 0 {
+        let digit = (remaining - 1) % 26;
+        letters.push((b'A' + digit as u8) as char);
+        remaining = (remaining - 1) / 26;
+    }
+    letters.iter().rev().collect()
+}
+
+/// Returns the width, in characters, of the given column.
+fn column_width(column: &Column) -> f64 {
+    match column.css_class().as_str() {
+        "output" => 80.0,
+        "state" => 10.0,
+        _ => 30.0,
+    }
+}
+
+
+/// Renders the given rows as an Office Open XML (XLSX) workbook.
+pub(crate) fn rows_to_xlsx(columns: &[Column], rows: &[RowPart]) -> Result<Vec<u8>, SpreadsheetError> {
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    worksheet.set_name(SHEET_NAME)?;
+
+    let header_format = Format::new()
+        .set_bold()
+        .set_font_color(Color::White)
+        .set_background_color(Color::RGB(0x333333));
+    let text_format = Format::new()
+        .set_text_wrap();
+    let datetime_format = Format::new()
+        .set_num_format(XLSX_DATETIME_FORMAT);
+
+    for (col, column) in columns.iter().enumerate() {
+        worksheet.write_string_with_format(0, col as u16, column.title(), &header_format)?;
+        worksheet.set_column_width(col as u16, column_width(column))?;
+    }
+
+    for (index, row) in rows.iter().enumerate() {
+        let row_num = (index + 1) as u32;
+        for (col, cell) in row.cells.iter().enumerate() {
+            let col = col as u16;
+            match cell {
+                CellValue::Boolean(b) => {
+                    worksheet.write_boolean(row_num, col, *b)?;
+                },
+                CellValue::Number(n) if n.is_finite() => {
+                    worksheet.write_number(row_num, col, *n)?;
+                },
+                CellValue::Timestamp(t) => {
+                    // Excel only knows the years 1900 to 9999
+                    let local_timestamp = local_datetime(t).and_utc().timestamp();
+                    match ExcelDateTime::from_timestamp(local_timestamp) {
+                        Ok(datetime) => worksheet.write_datetime_with_format(row_num, col, &datetime, &datetime_format)?,
+                        Err(_) => worksheet.write_string_with_format(row_num, col, cell.to_string(), &text_format)?,
+                    };
+                },
+                CellValue::State(state) => {
+                    let state_format = Format::new()
+                        .set_background_color(Color::RGB(state_background_color(*state)));
+                    worksheet.write_string_with_format(row_num, col, cell.to_string(), &state_format)?;
+                },
+                _ => {
+                    worksheet.write_string_with_format(row_num, col, cell.to_string(), &text_format)?;
+                },
+            }
+        }
+    }
+
+    worksheet.set_freeze_panes(1, 0)?;
+    worksheet.autofilter(0, 0, rows.len() as u32, (columns.len() - 1) as u16)?;
+
+    Ok(workbook.save_to_buffer()?)
+}
+
+
+/// Whether a character may appear in an XML 1.0 document.
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{FFFD}' | '\u{10000}'..='\u{10FFFF}')
+}
+
+/// Escapes a string for inclusion in XML text or attribute values.
+///
+/// Characters that XML does not allow at all (e.g. the escape character starting ANSI color
+/// sequences in plugin output) are replaced by U+FFFD.
+fn xml_escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            other if !is_xml_char(other) => ret.push('\u{FFFD}'),
+            other => ret.push(other),
+        }
+    }
+    ret
+}
+
+/// Returns the attributes of an ODS cell that specify the type and value of the given cell.
+fn ods_value_attributes(cell: &CellValue) -> String {
+    match cell {
+        CellValue::Boolean(b) => format!("office:value-type=\"boolean\" office:boolean-value=\"{}\"", b),
+        CellValue::Number(n) if n.is_finite() => format!("office:value-type=\"float\" office:value=\"{}\"", n),
+        CellValue::Timestamp(t) => format!(
+            "office:value-type=\"date\" office:date-value=\"{}\"",
+            local_datetime(t).format("%Y-%m-%dT%H:%M:%S"),
+        ),
+        _ => "office:value-type=\"string\"".to_owned(),
+    }
+}
+
+/// Appends an ODS cell to the given content, splitting multi-line text into paragraphs.
+///
+/// `value_attributes` specify the type and value of the cell; `text` is what is displayed.
+fn push_ods_cell(content: &mut String, style_name: &str, value_attributes: &str, text: &str) {
+    content.push_str(&format!(
+        "<table:table-cell table:style-name=\"{}\" {}>",
+        xml_escape(style_name), value_attributes,
+    ));
+    for line in text.split('\n') {
+        content.push_str("<text:p>");
+        content.push_str(&xml_escape(line.trim_end_matches('\r')));
+        content.push_str("</text:p>");
+    }
+    content.push_str("</table:table-cell>");
+}
+
+/// Generates the `content.xml` part of an ODS document.
+fn ods_content(columns: &[Column], rows: &[RowPart]) -> String {
+    let mut content = String::new();
+    content.push_str(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+        "<office:document-content",
+        " xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\"",
+        " xmlns:style=\"urn:oasis:names:tc:opendocument:xmlns:style:1.0\"",
+        " xmlns:table=\"urn:oasis:names:tc:opendocument:xmlns:table:1.0\"",
+        " xmlns:text=\"urn:oasis:names:tc:opendocument:xmlns:text:1.0\"",
+        " xmlns:fo=\"urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0\"",
+        " xmlns:number=\"urn:oasis:names:tc:opendocument:xmlns:datastyle:1.0\"",
+        " office:version=\"1.2\">",
+        "<office:automatic-styles>",
+        "<number:date-style style:name=\"datetime\">",
+        "<number:year number:style=\"long\"/><number:text>-</number:text>",
+        "<number:month number:style=\"long\"/><number:text>-</number:text>",
+        "<number:day number:style=\"long\"/><number:text> </number:text>",
+        "<number:hours number:style=\"long\"/><number:text>:</number:text>",
+        "<number:minutes number:style=\"long\"/><number:text>:</number:text>",
+        "<number:seconds number:style=\"long\"/>",
+        "</number:date-style>",
+        "<style:style style:name=\"header\" style:family=\"table-cell\">",
+        "<style:table-cell-properties fo:background-color=\"#333333\"/>",
+        "<style:text-properties fo:color=\"#ffffff\" fo:font-weight=\"bold\"/>",
+        "</style:style>",
+        "<style:style style:name=\"text\" style:family=\"table-cell\">",
+        "<style:table-cell-properties fo:wrap-option=\"wrap\" style:vertical-align=\"top\"/>",
+        "</style:style>",
+        "<style:style style:name=\"timestamp\" style:family=\"table-cell\" style:data-style-name=\"datetime\">",
+        "<style:table-cell-properties style:vertical-align=\"top\"/>",
+        "</style:style>",
+    ));
+    let styled_states = [
+        NagiosState::Ok,
+        NagiosState::Warning,
+        NagiosState::Critical,
+        NagiosState::Unknown,
+        NagiosState::Other(0),
+    ];
+    for state in styled_states {
+        content.push_str(&format!(
+            concat!(
+                "<style:style style:name=\"{}\" style:family=\"table-cell\">",
+                "<style:table-cell-properties fo:background-color=\"#{:06x}\" style:vertical-align=\"top\"/>",
+                "</style:style>",
+            ),
+            ods_state_style_name(state),
+            state_background_color(state),
+        ));
+    }
+    content.push_str(concat!(
+        "</office:automatic-styles>",
+        "<office:body>",
+        "<office:spreadsheet>",
+    ));
+
+    content.push_str(&format!("<table:table table:name=\"{}\">", xml_escape(SHEET_NAME)));
+    content.push_str(&format!("<table:table-column table:number-columns-repeated=\"{}\"/>", columns.len()));
+
+    content.push_str("<table:table-row>");
+    for column in columns {
+        push_ods_cell(&mut content, "header", "office:value-type=\"string\"", &column.title());
+    }
+    content.push_str("</table:table-row>");
+
+    for row in rows {
+        content.push_str("<table:table-row>");
+        for cell in &row.cells {
+            let style_name = match cell {
+                CellValue::State(state) => ods_state_style_name(*state),
+                CellValue::Timestamp(_) => "timestamp".to_owned(),
+                _ => "text".to_owned(),
+            };
+            push_ods_cell(&mut content, &style_name, &ods_value_attributes(cell), &cell.to_string());
+        }
+        content.push_str("</table:table-row>");
+    }
+    content.push_str("</table:table>");
+
+    // the autofilter is a database range with filter buttons
+    content.push_str(&format!(
+        concat!(
+            "<table:database-ranges>",
+            "<table:database-range table:name=\"__Anonymous_Sheet_DB__0\"",
+            " table:target-range-address=\"{sheet}.A1:{sheet}.{last_col}{last_row}\"",
+            " table:display-filter-buttons=\"true\"/>",
+            "</table:database-ranges>",
+        ),
+        sheet = xml_escape(SHEET_NAME),
+        last_col = column_letters(columns.len() - 1),
+        last_row = rows.len() + 1,
+    ));
+
+    content.push_str(concat!(
+        "</office:spreadsheet>",
+        "</office:body>",
+        "</office:document-content>",
+    ));
+    content
+}
+
+/// Generates the `settings.xml` part of an ODS document, which freezes the header row.
+fn ods_settings() -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+            "<office:document-settings",
+            " xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\"",
+            " xmlns:config=\"urn:oasis:names:tc:opendocument:xmlns:config:1.0\"",
+            " office:version=\"1.2\">",
+            "<office:settings>",
+            "<config:config-item-set config:name=\"ooo:view-settings\">",
+            "<config:config-item-map-indexed config:name=\"Views\">",
+            "<config:config-item-map-entry>",
+            "<config:config-item config:name=\"ViewId\" config:type=\"string\">view1</config:config-item>",
+            "<config:config-item-map-named config:name=\"Tables\">",
+            "<config:config-item-map-entry config:name=\"{sheet}\">",
+            "<config:config-item config:name=\"HorizontalSplitMode\" config:type=\"short\">0</config:config-item>",
+            "<config:config-item config:name=\"VerticalSplitMode\" config:type=\"short\">2</config:config-item>",
+            "<config:config-item config:name=\"VerticalSplitPosition\" config:type=\"int\">1</config:config-item>",
+            "<config:config-item config:name=\"ActiveSplitRange\" config:type=\"short\">2</config:config-item>",
+            "<config:config-item config:name=\"PositionTop\" config:type=\"int\">0</config:config-item>",
+            "<config:config-item config:name=\"PositionBottom\" config:type=\"int\">1</config:config-item>",
+            "</config:config-item-map-entry>",
+            "</config:config-item-map-named>",
+            "</config:config-item-map-entry>",
+            "</config:config-item-map-indexed>",
+            "</config:config-item-set>",
+            "</office:settings>",
+            "</office:document-settings>",
+        ),
+        sheet = xml_escape(SHEET_NAME),
+    )
+}
+
+/// Generates the `META-INF/manifest.xml` part of an ODS document.
+fn ods_manifest() -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+            "<manifest:manifest",
+            " xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\"",
+            " manifest:version=\"1.2\">",
+            "<manifest:file-entry manifest:full-path=\"/\" manifest:version=\"1.2\" manifest:media-type=\"{}\"/>",
+            "<manifest:file-entry manifest:full-path=\"content.xml\" manifest:media-type=\"text/xml\"/>",
+            "<manifest:file-entry manifest:full-path=\"settings.xml\" manifest:media-type=\"text/xml\"/>",
+            "</manifest:manifest>",
+        ),
+        ODS_MIME_TYPE,
+    )
+}
+
+/// Renders the given rows as an OpenDocument spreadsheet (ODS).
+pub(crate) fn rows_to_ods(columns: &[Column], rows: &[RowPart]) -> Result<Vec<u8>, SpreadsheetError> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+    // the MIME type must be the first entry and must be stored uncompressed
+    let stored = FileOptions::default()
+        .compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(ODS_MIME_TYPE.as_bytes())?;
+
+    zip.start_file("META-INF/manifest.xml", deflated)?;
+    zip.write_all(ods_manifest().as_bytes())?;
+
+    zip.start_file("content.xml", deflated)?;
+    zip.write_all(ods_content(columns, rows).as_bytes())?;
+
+    zip.start_file("settings.xml", deflated)?;
+    zip.write_all(ods_settings().as_bytes())?;
+
+    let cursor = zip.finish()?;
+    Ok(cursor.into_inner())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use chrono::TimeZone;
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::objtypes;
+
+    fn row(cells: Vec<CellValue>) -> RowPart {
+        RowPart {
+            instance: "icinga".to_owned(),
+            name: "host1".to_owned(),
+            host: "host1".to_owned(),
+            service: String::new(),
+            output: String::new(),
+            state: NagiosState::Ok,
+            cells,
+        }
+    }
+
+    fn columns(count: usize) -> Vec<Column> {
+        let hosts = objtypes::by_plural("hosts").unwrap();
+        vec![Column::parse(hosts, "name").unwrap(); count]
+    }
+
+    fn read_entry(document: &[u8], name: &str) -> String {
+        let mut archive = ZipArchive::new(Cursor::new(document)).unwrap();
+        let mut entry = archive.by_name(name).unwrap();
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        content
+    }
+
+    /// Checks that a document is well-formed XML, returning a description of the first problem.
+    ///
+    /// Only the constructs icingcake generates are supported: a declaration, elements, attributes,
+    /// text and the predefined entities.
+    fn check_well_formed(xml: &str) -> Result<(), String> {
+        if let Some(c) = xml.chars().find(|c| !is_xml_char(*c)) {
+            return Err(format!("invalid character {:?}", c));
+        }
+        let rest = xml.strip_prefix("<?xml").ok_or("missing declaration")?;
+        let mut rest = &rest[rest.find("?>").ok_or("unterminated declaration")? + 2..];
+
+        let check_entities = |text: &str| -> Result<(), String> {
+            for (i, _) in text.match_indices('&') {
+                let entity = &text[i..];
+                if !["&amp;", "&lt;", "&gt;", "&quot;", "&apos;"].iter().any(|e| entity.starts_with(e)) {
+                    return Err(format!("invalid entity at {:?}", &entity[..entity.len().min(10)]));
+                }
+            }
+            Ok(())
+        };
+
+        let mut open_elements: Vec<&str> = Vec::new();
+        let mut seen_root = false;
+        while !rest.is_empty() {
+            let text_end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..text_end];
+            if open_elements.is_empty() && !text.trim().is_empty() {
+                return Err(format!("text outside of the root element: {:?}", text));
+            }
+            if text.contains('>') {
+                return Err(format!("unescaped `>` in text {:?}", text));
+            }
+            check_entities(text)?;
+            rest = &rest[text_end..];
+            if rest.is_empty() {
+                break;
+            }
+
+            let tag_end = rest.find('>').ok_or("unterminated tag")?;
+            let tag = &rest[1..tag_end];
+            rest = &rest[tag_end + 1..];
+            if let Some(name) = tag.strip_prefix('/') {
+                match open_elements.pop() {
+                    Some(open) if open == name => {},
+                    other => return Err(format!("`</{}>` closes {:?}", name, other)),
+                }
+                continue;
+            }
+
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(t) => (t, true),
+                None => (tag, false),
+            };
+            let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let name = &tag[..name_end];
+            if name.is_empty() {
+                return Err("element without a name".to_owned());
+            }
+            if open_elements.is_empty() {
+                if seen_root {
+                    return Err(format!("second root element {:?}", name));
+                }
+                seen_root = true;
+            }
+
+            let mut attributes = tag[name_end..].trim_start();
+            while !attributes.is_empty() {
+                let (_attribute_name, after_name) = attributes.split_once("=\"")
+                    .ok_or_else(|| format!("invalid attribute in {:?}", tag))?;
+                let (value, after_value) = after_name.split_once('"')
+                    .ok_or_else(|| format!("unterminated attribute value in {:?}", tag))?;
+                if value.contains('<') {
+                    return Err(format!("`<` in attribute value {:?}", value));
+                }
+                check_entities(value)?;
+                attributes = after_value.trim_start();
+            }
+
+            if !self_closing {
+                open_elements.push(name);
+            }
+        }
+        if !open_elements.is_empty() {
+            return Err(format!("unclosed elements {:?}", open_elements));
+        }
+        if !seen_root {
+            return Err("no root element".to_owned());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_well_formedness_check() {
+        assert!(check_well_formed("<?xml version=\"1.0\"?><a b=\"&amp;\"><c/>x &lt; y</a>").is_ok());
+        assert!(check_well_formed("<?xml version=\"1.0\"?><a><b></a></b>").is_err());
+        assert!(check_well_formed("<?xml version=\"1.0\"?><a>&</a>").is_err());
+        assert!(check_well_formed("<?xml version=\"1.0\"?><a>\u{1B}</a>").is_err());
+        assert!(check_well_formed("<?xml version=\"1.0\"?><a b=\"<\"/>").is_err());
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(xml_escape("<&\"'>"), "&lt;&amp;&quot;&apos;&gt;");
+        assert_eq!(xml_escape("\u{1B}[31mCRITICAL\u{1B}[0m"), "\u{FFFD}[31mCRITICAL\u{FFFD}[0m");
+        assert_eq!(xml_escape("a\u{0}b\u{8}c\u{B}d\u{C}e\u{E}f\u{1F}g"), "a\u{FFFD}b\u{FFFD}c\u{FFFD}d\u{FFFD}e\u{FFFD}f\u{FFFD}g");
+        assert_eq!(xml_escape("tab\tcr\rlf\n"), "tab\tcr\rlf\n");
+        assert_eq!(xml_escape("\u{FFFE}\u{1F600}"), "\u{FFFD}\u{1F600}");
+    }
+
+    #[test]
+    fn test_ods_content_well_formed() {
+        let values = [
+            "<script>alert('x')</script>",
+            "Tom & \"Jerry\"",
+            "line1\nline2\r\nline3",
+            "\u{1B}[1;31mCRITICAL\u{1B}[0m - disk \u{0}full\u{7}",
+            "\u{B}\u{C}\u{E}\u{1F}",
+            "",
+        ];
+        let cells: Vec<CellValue> = values.iter()
+            .map(|v| CellValue::Text((*v).to_owned()))
+            .chain([
+                CellValue::Number(1.5),
+                CellValue::Boolean(true),
+                CellValue::Timestamp(Utc.timestamp_opt(1_700_000_000, 0).unwrap()),
+                CellValue::State(NagiosState::Critical),
+                CellValue::List(vec!["<a>".to_owned(), "b&c".to_owned()]),
+                CellValue::Empty,
+            ])
+            .collect();
+        let columns = columns(cells.len());
+        let document = rows_to_ods(&columns, &[row(cells)]).unwrap();
+
+        let content = read_entry(&document, "content.xml");
+        check_well_formed(&content).unwrap();
+        assert!(content.contains("<text:p>&lt;script&gt;alert(&apos;x&apos;)&lt;/script&gt;</text:p>"));
+        assert!(content.contains("<text:p>Tom &amp; &quot;Jerry&quot;</text:p>"));
+        assert!(content.contains("<text:p>line1</text:p><text:p>line2</text:p><text:p>line3</text:p>"));
+        assert!(content.contains("<text:p>\u{FFFD}[1;31mCRITICAL\u{FFFD}[0m - disk \u{FFFD}full\u{FFFD}</text:p>"));
+
+        check_well_formed(&read_entry(&document, "settings.xml")).unwrap();
+        check_well_formed(&read_entry(&document, "META-INF/manifest.xml")).unwrap();
+        assert_eq!(read_entry(&document, "mimetype"), ODS_MIME_TYPE);
+    }
+
+    #[test]
+    fn test_ods_cell_types() {
+        let timestamp = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let cells = vec![
+            CellValue::Number(-2.5),
+            CellValue::Boolean(false),
+            CellValue::Timestamp(timestamp),
+            CellValue::Text("3".to_owned()),
+        ];
+        let columns = columns(cells.len());
+        let content = read_entry(&rows_to_ods(&columns, &[row(cells)]).unwrap(), "content.xml");
+
+        assert!(content.contains("<table:table-cell table:style-name=\"text\" office:value-type=\"float\" office:value=\"-2.5\"><text:p>-2.5</text:p>"));
+        assert!(content.contains("<table:table-cell table:style-name=\"text\" office:value-type=\"boolean\" office:boolean-value=\"false\"><text:p>false</text:p>"));
+        let date_value = local_datetime(&timestamp).format("%Y-%m-%dT%H:%M:%S").to_string();
+        assert!(content.contains(&format!("<table:table-cell table:style-name=\"timestamp\" office:value-type=\"date\" office:date-value=\"{}\">", date_value)));
+        assert!(content.contains("<table:table-cell table:style-name=\"text\" office:value-type=\"string\"><text:p>3</text:p>"));
+    }
+
+    #[test]
+    fn test_xlsx_cell_types() {
+        let cells = vec![
+            CellValue::Number(-2.5),
+            CellValue::Boolean(true),
+            CellValue::Timestamp(Utc.timestamp_opt(1_700_000_000, 0).unwrap()),
+            CellValue::Text("3".to_owned()),
+            CellValue::State(NagiosState::Critical),
+        ];
+        let columns = columns(cells.len());
+        let document = rows_to_xlsx(&columns, &[row(cells)]).unwrap();
+        let sheet = read_entry(&document, "xl/worksheets/sheet1.xml");
+        check_well_formed(&sheet).unwrap();
+
+        // numbers and booleans are stored as such, text in the shared strings
+        assert!(sheet.contains("<c r=\"A2\"><v>-2.5</v></c>"));
+        assert!(sheet.contains("<c r=\"B2\" t=\"b\"><v>1</v></c>"));
+        assert!(sheet.contains("<c r=\"D2\" s=\"3\" t=\"s\">"));
+        assert!(sheet.contains("<c r=\"E2\" s=\"4\" t=\"s\">"));
+
+        // timestamps are stored as the number of days since 1899-12-30 in local time
+        let timestamp_cell = &sheet[sheet.find("<c r=\"C2\" s=\"2\"><v>").unwrap()..];
+        let value_start = timestamp_cell.find("<v>").unwrap() + 3;
+        let value_end = timestamp_cell.find("</v>").unwrap();
+        let days: f64 = timestamp_cell[value_start..value_end].parse().unwrap();
+        let local_timestamp = local_datetime(&Utc.timestamp_opt(1_700_000_000, 0).unwrap()).and_utc().timestamp();
+        assert!((days - (local_timestamp as f64 / 86400.0 + 25569.0)).abs() < 1e-6);
+        let styles = read_entry(&document, "xl/styles.xml");
+        assert!(styles.contains(&format!("formatCode=\"{}\"", XLSX_DATETIME_FORMAT)));
+    }
+}
@@ -0,0 +1,295 @@
+//! TLS configuration for connections to the Icinga API and for the HTTPS listener.
+
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerConfig, ServerName};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::server::AllowAnyAuthenticatedClient;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::config::{HttpsConfig, IcingaApiConfig, TlsVersion};
+
+
+/// How often to check whether the files of the HTTPS listener's TLS configuration have changed.
+const SERVER_CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+
+/// An error that may occur when assembling a TLS configuration.
+#[derive(Debug)]
+#[non_exhaustive]
+pub(crate) enum TlsConfigError {
+    #[non_exhaustive] Reading { path: PathBuf, error: io::Error },
+    #[non_exhaustive] NoCertificates { path: PathBuf },
+    #[non_exhaustive] InvalidCaCertificate { path: PathBuf },
+    #[non_exhaustive] NoPrivateKey { path: PathBuf },
+    #[non_exhaustive] CertificateWithoutKey,
+    #[non_exhaustive] InvalidFingerprint { fingerprint: String },
+    #[non_exhaustive] Rustls { error: rustls::Error },
+}
+impl fmt::Display for TlsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reading { path, error, .. }
+                => write!(f, "error reading {}: {}", path.display(), error),
+            Self::NoCertificates { path, .. }
+                => write!(f, "no certificates found in {}", path.display()),
+            Self::InvalidCaCertificate { path, .. }
+                => write!(f, "invalid CA certificate in {}", path.display()),
+            Self::NoPrivateKey { path, .. }
+                => write!(f, "no private key found in {}", path.display()),
+            Self::CertificateWithoutKey
+                => write!(f, "a client certificate and a client key must be configured together"),
+            Self::InvalidFingerprint { fingerprint, .. }
+                => write!(f, "invalid SHA-256 fingerprint {:?}", fingerprint),
+            Self::Rustls { error, .. }
+                => write!(f, "TLS error: {}", error),
+        }
+    }
+}
+impl std::error::Error for TlsConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Reading { error, .. } => Some(error),
+            Self::NoCertificates { .. } => None,
+            Self::InvalidCaCertificate { .. } => None,
+            Self::NoPrivateKey { .. } => None,
+            Self::CertificateWithoutKey => None,
+            Self::InvalidFingerprint { .. } => None,
+            Self::Rustls { error, .. } => Some(error),
+        }
+    }
+}
+
+
+/// Verifies the certificate of an Icinga server.
+///
+/// If a chain verifier is set, the certificate must be issued by a trusted CA. If fingerprints are
+/// set, the SHA-256 fingerprint of the certificate must match one of them. If neither is set, any
+/// certificate is accepted.
+struct IcingaCertVerifier {
+    chain_verifier: Option<WebPkiVerifier>,
+    fingerprints: Vec<[u8; 32]>,
+}
+impl ServerCertVerifier for IcingaCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(chain_verifier) = &self.chain_verifier {
+            chain_verifier.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)?;
+        }
+
+        if !self.fingerprints.is_empty() {
+            let fingerprint: [u8; 32] = Sha256::digest(&end_entity.0).into();
+            if !self.fingerprints.contains(&fingerprint) {
+                return Err(rustls::Error::General("server certificate does not match any pinned fingerprint".to_owned()));
+            }
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+
+/// Parses a SHA-256 fingerprint written in hexadecimal, optionally with colons between the bytes.
+fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    let hex_digits: Vec<u8> = fingerprint.chars()
+        .filter(|c| *c != ':')
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()?;
+    if hex_digits.len() != 64 {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    for (byte, pair) in bytes.iter_mut().zip(hex_digits.chunks(2)) {
+        *byte = (pair[0] << 4) | pair[1];
+    }
+    Some(bytes)
+}
+
+fn open_pem(path: &Path) -> Result<BufReader<File>, TlsConfigError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|error| TlsConfigError::Reading { path: path.to_owned(), error })
+}
+
+/// Loads all certificates from a PEM file.
+pub(crate) fn load_certificates(path: &Path) -> Result<Vec<Certificate>, TlsConfigError> {
+    let mut reader = open_pem(path)?;
+    let certificates: Vec<Certificate> = rustls_pemfile::certs(&mut reader)
+        .map_err(|error| TlsConfigError::Reading { path: path.to_owned(), error })?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certificates.is_empty() {
+        return Err(TlsConfigError::NoCertificates { path: path.to_owned() });
+    }
+    Ok(certificates)
+}
+
+/// Loads the first private key from a PEM file.
+pub(crate) fn load_private_key(path: &Path) -> Result<PrivateKey, TlsConfigError> {
+    let mut reader = open_pem(path)?;
+    let items = rustls_pemfile::read_all(&mut reader)
+        .map_err(|error| TlsConfigError::Reading { path: path.to_owned(), error })?;
+    for item in items {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key)
+                => return Ok(PrivateKey(key)),
+            _ => {},
+        }
+    }
+    Err(TlsConfigError::NoPrivateKey { path: path.to_owned() })
+}
+
+
+/// Assembles the TLS configuration for accessing an Icinga instance.
+///
+/// Returns `None` if neither a CA bundle, a client certificate nor pinned fingerprints are
+/// configured, in which case the HTTP client's defaults are sufficient.
+pub(crate) fn icinga_client_config(icinga_config: &IcingaApiConfig) -> Result<Option<ClientConfig>, TlsConfigError> {
+    let needs_custom_config = icinga_config.ca_bundle_path.is_some()
+        || icinga_config.client_cert_path.is_some()
+        || icinga_config.client_key_path.is_some()
+        || !icinga_config.pinned_fingerprints.is_empty();
+    if !needs_custom_config {
+        return Ok(None);
+    }
+
+    let fingerprints = icinga_config.pinned_fingerprints.iter()
+        .map(|f| parse_fingerprint(f).ok_or_else(|| TlsConfigError::InvalidFingerprint { fingerprint: f.clone() }))
+        .collect::<Result<Vec<[u8; 32]>, TlsConfigError>>()?;
+
+    let mut roots = RootCertStore::empty();
+    match &icinga_config.ca_bundle_path {
+        Some(ca_bundle_path) => {
+            for certificate in load_certificates(ca_bundle_path)? {
+                roots.add(&certificate)
+                    .map_err(|_| TlsConfigError::InvalidCaCertificate { path: ca_bundle_path.clone() })?;
+            }
+        },
+        None => {
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+            }));
+        },
+    }
+
+    // a pinned certificate need not be issued by a trusted CA unless a CA bundle is given explicitly
+    let verify_chain = !icinga_config.allow_invalid_certs
+        && (fingerprints.is_empty() || icinga_config.ca_bundle_path.is_some());
+    let chain_verifier = if verify_chain {
+        Some(WebPkiVerifier::new(roots, None))
+    } else {
+        None
+    };
+    let verifier = IcingaCertVerifier {
+        chain_verifier,
+        fingerprints,
+    };
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    let client_config = match (&icinga_config.client_cert_path, &icinga_config.client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let certificates = load_certificates(cert_path)?;
+            let key = load_private_key(key_path)?;
+            builder.with_client_auth_cert(certificates, key)
+                .map_err(|error| TlsConfigError::Rustls { error })?
+        },
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(TlsConfigError::CertificateWithoutKey),
+    };
+    Ok(Some(client_config))
+}
+
+
+/// Assembles the TLS configuration of the HTTPS listener.
+///
+/// Both HTTP/2 and HTTP/1.1 are offered via ALPN.
+pub(crate) fn server_config(https_config: &HttpsConfig) -> Result<ServerConfig, TlsConfigError> {
+    let versions: &[&'static rustls::SupportedProtocolVersion] = match https_config.min_version {
+        TlsVersion::V1_2 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::V1_3 => &[&rustls::version::TLS13],
+    };
+    let builder = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)
+        .map_err(|error| TlsConfigError::Rustls { error })?;
+
+    let builder = match &https_config.client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(client_ca_path)? {
+                roots.add(&certificate)
+                    .map_err(|_| TlsConfigError::InvalidCaCertificate { path: client_ca_path.clone() })?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        },
+        None => builder.with_no_client_auth(),
+    };
+
+    let certificates = load_certificates(&https_config.cert_chain_path)?;
+    let key = load_private_key(&https_config.private_key_path)?;
+    let mut server_config = builder.with_single_cert(certificates, key)
+        .map_err(|error| TlsConfigError::Rustls { error })?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+
+/// Returns when each file of the HTTPS listener's TLS configuration has last been modified.
+fn server_config_modification_times(https_config: &HttpsConfig) -> Vec<Option<SystemTime>> {
+    let mut paths = vec![&https_config.cert_chain_path, &https_config.private_key_path];
+    if let Some(client_ca_path) = &https_config.client_ca_path {
+        paths.push(client_ca_path);
+    }
+    paths.into_iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Periodically checks whether the files of the HTTPS listener's TLS configuration have changed
+/// and, if so, reloads them into `current_config`.
+///
+/// If the new files cannot be loaded (e.g. because the certificate has been replaced but the key
+/// not yet), the previous configuration remains in effect.
+pub(crate) async fn watch_server_config(current_config: Arc<RwLock<Arc<ServerConfig>>>, https_config: HttpsConfig) {
+    let mut modification_times = server_config_modification_times(&https_config);
+    loop {
+        tokio::time::sleep(SERVER_CONFIG_CHECK_INTERVAL).await;
+
+        let new_modification_times = server_config_modification_times(&https_config);
+        if new_modification_times == modification_times {
+            continue;
+        }
+        modification_times = new_modification_times;
+
+        match server_config(&https_config) {
+            Ok(sc) => {
+                *current_config.write().await = Arc::new(sc);
+                info!("reloaded TLS certificate and key");
+            },
+            Err(e) => {
+                error!("failed to reload TLS certificate and key; keeping the previous ones: {}", e);
+            },
+        }
+    }
+}
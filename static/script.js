@@ -81,6 +81,134 @@ var Icingcake;
         }
         filterField.value = criteria.join(" && ");
     }
+    const OBJECT_TYPE_CHOICES = [
+        {
+            plural: "hosts",
+            title: "Host",
+            criteria: [
+                ["Host-Name", "host.name"],
+                ["Adresse", "host.address"],
+            ],
+            columns: "host.name, state, last_check_result.output",
+        },
+        {
+            plural: "services",
+            title: "Service",
+            criteria: [
+                ["Host-Name", "host.name"],
+                ["Service-Name", "service.name"],
+            ],
+            columns: "host.name, service.name, state, last_check_result.output",
+        },
+        {
+            plural: "downtimes",
+            title: "Downtime",
+            criteria: [
+                ["Host-Name", "downtime.host_name"],
+                ["Service-Name", "downtime.service_name"],
+                ["Autor", "downtime.author"],
+                ["Kommentar", "downtime.comment"],
+            ],
+            columns: "host_name, service_name, author, comment, start_time, end_time, fixed, trigger_time",
+        },
+        {
+            plural: "comments",
+            title: "Kommentar",
+            criteria: [
+                ["Host-Name", "comment.host_name"],
+                ["Service-Name", "comment.service_name"],
+                ["Autor", "comment.author"],
+                ["Text", "comment.text"],
+            ],
+            columns: "host_name, service_name, author, text, entry_time, expire_time",
+        },
+        {
+            plural: "notifications",
+            title: "Benachrichtigung",
+            criteria: [
+                ["Host-Name", "notification.host_name"],
+                ["Service-Name", "notification.service_name"],
+                ["Name", "notification.name"],
+            ],
+            columns: "host_name, service_name, name, users, user_groups, last_notification, notification_number",
+        },
+        {
+            plural: "users",
+            title: "Benutzer",
+            criteria: [
+                ["Name", "user.name"],
+                ["Anzeigename", "user.display_name"],
+                ["E-Mail", "user.email"],
+            ],
+            columns: "name, display_name, email, groups, enable_notifications",
+        },
+        {
+            plural: "hostgroups",
+            title: "Hostgruppe",
+            criteria: [
+                ["Name", "hostgroup.name"],
+                ["Anzeigename", "hostgroup.display_name"],
+            ],
+            columns: "name, display_name, groups",
+        },
+        {
+            plural: "servicegroups",
+            title: "Servicegruppe",
+            criteria: [
+                ["Name", "servicegroup.name"],
+                ["Anzeigename", "servicegroup.display_name"],
+            ],
+            columns: "name, display_name, groups",
+        },
+        {
+            plural: "checkcommands",
+            title: "Check-Command",
+            criteria: [
+                ["Name", "checkcommand.name"],
+            ],
+            columns: "name, command, timeout",
+        },
+        {
+            plural: "zones",
+            title: "Zone",
+            criteria: [
+                ["Name", "zone.name"],
+                ["Eltern-Zone", "zone.parent"],
+            ],
+            columns: "name, parent, endpoints, global",
+        },
+    ];
+    function getObjectTypeChoice(form) {
+        const objTypeSelect = form.querySelector("select[name=objtype]");
+        if (objTypeSelect !== null) {
+            for (const choice of OBJECT_TYPE_CHOICES) {
+                if (choice.plural === objTypeSelect.value) {
+                    return choice;
+                }
+            }
+        }
+        return OBJECT_TYPE_CHOICES[0];
+    }
+    function fillCriterionSelect(criterionSelect, choice) {
+        while (criterionSelect.firstChild !== null) {
+            criterionSelect.removeChild(criterionSelect.firstChild);
+        }
+        for (const [title, criterion] of choice.criteria) {
+            addSelectOption(criterionSelect, title, criterion);
+        }
+    }
+    function updateObjectType(form) {
+        const choice = getObjectTypeChoice(form);
+        const criterionSelects = form.querySelectorAll("p.filter-row select.criterion");
+        for (let i = 0; i < criterionSelects.length; i++) {
+            fillCriterionSelect(criterionSelects[i], choice);
+        }
+        const columnsInput = form.querySelector("input[name=columns]");
+        if (columnsInput !== null) {
+            columnsInput.placeholder = choice.columns;
+        }
+        updateFilterField(form);
+    }
     function addFilterRow(form) {
         const filterRowP = document.createElement("p");
         form.appendChild(filterRowP);
@@ -88,8 +216,7 @@ var Icingcake;
         const criterionSelect = document.createElement("select");
         filterRowP.appendChild(criterionSelect);
         criterionSelect.classList.add("criterion");
-        addSelectOption(criterionSelect, "Host-Name", "host.name");
-        addSelectOption(criterionSelect, "Service-Name", "service.name");
+        fillCriterionSelect(criterionSelect, getObjectTypeChoice(form));
         criterionSelect.addEventListener("change", () => updateFilterField(form));
         criterionSelect.addEventListener("input", () => updateFilterField(form));
         const operatorSelect = document.createElement("select");
@@ -124,6 +251,9 @@ var Icingcake;
     }
     function setUp() {
         const form = document.querySelector("form.icingcake-form");
+        if (form === null) {
+            return;
+        }
         const objTypeP = document.createElement("p");
         form.appendChild(objTypeP);
         objTypeP.classList.add("obj-type");
@@ -133,8 +263,21 @@ var Icingcake;
         const objTypeSelect = document.createElement("select");
         objTypeLabel.appendChild(objTypeSelect);
         objTypeSelect.name = "objtype";
-        addSelectOption(objTypeSelect, "Host", "hosts");
-        addSelectOption(objTypeSelect, "Service", "services");
+        for (const choice of OBJECT_TYPE_CHOICES) {
+            addSelectOption(objTypeSelect, choice.title, choice.plural);
+        }
+        objTypeSelect.addEventListener("change", () => updateObjectType(form));
+        const columnsP = document.createElement("p");
+        form.appendChild(columnsP);
+        columnsP.classList.add("columns");
+        const columnsLabel = document.createElement("label");
+        columnsP.appendChild(columnsLabel);
+        columnsLabel.textContent = "Spalten: ";
+        const columnsInput = document.createElement("input");
+        columnsLabel.appendChild(columnsInput);
+        columnsInput.type = "text";
+        columnsInput.name = "columns";
+        columnsInput.placeholder = getObjectTypeChoice(form).columns;
         addFilterRow(form);
         const filterField = document.createElement("input");
         form.appendChild(filterField);
@@ -151,6 +294,158 @@ var Icingcake;
         const noJsWarning = form.querySelector("p.no-js-warning");
         deleteNode(noJsWarning);
     }
+    function findLiveRow(tbody, key) {
+        for (let i = 0; i < tbody.rows.length; i++) {
+            if (tbody.rows[i].dataset.key === key) {
+                return tbody.rows[i];
+            }
+        }
+        return null;
+    }
+    function upsertLiveRow(tbody, selectable, row) {
+        let tr = findLiveRow(tbody, row.key);
+        if (tr === null) {
+            tr = document.createElement("tr");
+            tbody.appendChild(tr);
+            tr.dataset.key = row.key;
+            if (selectable) {
+                const selectTd = document.createElement("td");
+                tr.appendChild(selectTd);
+                selectTd.className = "select";
+                const checkbox = document.createElement("input");
+                selectTd.appendChild(checkbox);
+                checkbox.type = "checkbox";
+                checkbox.name = "object";
+                checkbox.value = row.key;
+            }
+        }
+        // keep the selection cell (and whether it is checked)
+        for (let i = tr.cells.length - 1; i >= 0; i--) {
+            if (!tr.cells[i].classList.contains("select")) {
+                deleteNode(tr.cells[i]);
+            }
+        }
+        for (const cell of row.cells) {
+            const td = document.createElement("td");
+            tr.appendChild(td);
+            td.className = cell.class;
+            td.textContent = cell.text;
+        }
+    }
+    function removeLiveRow(tbody, key) {
+        const tr = findLiveRow(tbody, key);
+        if (tr !== null) {
+            deleteNode(tr);
+        }
+    }
+    function orderLiveRows(tbody, keys) {
+        const keyToRow = new Map();
+        for (let i = 0; i < tbody.rows.length; i++) {
+            const rowKey = tbody.rows[i].dataset.key;
+            if (rowKey !== undefined) {
+                keyToRow.set(rowKey, tbody.rows[i]);
+            }
+        }
+        // appending an existing row moves it to the end
+        for (const key of keys) {
+            const tr = keyToRow.get(key);
+            if (tr !== undefined) {
+                tbody.appendChild(tr);
+            }
+        }
+    }
+    function setUpLive() {
+        const table = document.querySelector("table.icingcake-live");
+        if (table === null) {
+            return;
+        }
+        const eventsUrl = table.dataset.eventsUrl;
+        if (eventsUrl === undefined) {
+            return;
+        }
+        const tbody = table.tBodies[0];
+        const selectable = table.classList.contains("icingcake-selectable");
+        const source = new EventSource(eventsUrl);
+        source.addEventListener("upsert", ev => upsertLiveRow(tbody, selectable, JSON.parse(ev.data)));
+        source.addEventListener("remove", ev => removeLiveRow(tbody, JSON.parse(ev.data)));
+        source.addEventListener("order", ev => orderLiveRows(tbody, JSON.parse(ev.data)));
+    }
+    function setUpSelection() {
+        const selectAll = document.querySelector("table.icingcake-selectable input.select-all");
+        if (selectAll === null) {
+            return;
+        }
+        selectAll.addEventListener("change", () => {
+            const checkboxes = document.querySelectorAll("table.icingcake-selectable input[name=object]");
+            for (let i = 0; i < checkboxes.length; i++) {
+                checkboxes[i].checked = selectAll.checked;
+            }
+        });
+    }
+    const RECHECK_POLL_INTERVAL_MS = 2000;
+    const RECHECK_MAX_POLLS = 300;
+    function updateRecheck(table, status) {
+        const tbody = table.tBodies[0];
+        for (const row of status.rows) {
+            const tr = findLiveRow(tbody, row.key);
+            if (tr === null) {
+                continue;
+            }
+            const stateTd = tr.querySelector("td.state");
+            const lastCheckTd = tr.querySelector("td.last_check");
+            if (stateTd === null || lastCheckTd === null) {
+                continue;
+            }
+            if (row.done) {
+                stateTd.className = `state state-${row.state}`;
+                stateTd.textContent = row.state_name;
+            }
+            lastCheckTd.textContent = row.last_check;
+        }
+        const progress = document.querySelector("p.recheck-progress progress");
+        if (progress !== null) {
+            progress.max = status.total;
+            progress.value = status.done;
+        }
+        const doneSpan = document.querySelector("p.recheck-progress span.recheck-done");
+        if (doneSpan !== null) {
+            doneSpan.textContent = `${status.done}`;
+        }
+        const totalSpan = document.querySelector("p.recheck-progress span.recheck-total");
+        if (totalSpan !== null) {
+            totalSpan.textContent = `${status.total}`;
+        }
+    }
+    function pollRecheck(table, statusUrl, remainingPolls) {
+        const xhr = new XMLHttpRequest();
+        xhr.addEventListener("load", () => {
+            let finished = false;
+            if (xhr.status === 200) {
+                const status = JSON.parse(xhr.responseText);
+                updateRecheck(table, status);
+                finished = (status.done >= status.total);
+            }
+            if (!finished && remainingPolls > 1) {
+                window.setTimeout(() => pollRecheck(table, statusUrl, remainingPolls - 1), RECHECK_POLL_INTERVAL_MS);
+            }
+        });
+        xhr.open("GET", statusUrl, true);
+        xhr.send();
+    }
+    function setUpRecheck() {
+        const table = document.querySelector("table.icingcake-recheck");
+        if (table === null) {
+            return;
+        }
+        const statusUrl = table.dataset.statusUrl;
+        if (statusUrl === undefined) {
+            return;
+        }
+        window.setTimeout(() => pollRecheck(table, statusUrl, RECHECK_MAX_POLLS), RECHECK_POLL_INTERVAL_MS);
+    }
     document.addEventListener("DOMContentLoaded", setUp);
+    document.addEventListener("DOMContentLoaded", setUpLive);
+    document.addEventListener("DOMContentLoaded", setUpSelection);
+    document.addEventListener("DOMContentLoaded", setUpRecheck);
 })(Icingcake || (Icingcake = {}));
 //# sourceMappingURL=script.js.map
\ No newline at end of file